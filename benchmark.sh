@@ -11,3 +11,9 @@ echo Benchmarking pixel-search
 $TIME $CARGO_RUN_CMD pixel_search -- apples.png --size 128 --winsize 7 2>&1 | tail -n2 | head -n1
 echo Benchmarking quilt
 $TIME $CARGO_RUN_CMD quilt -- apples.png --size 1024 --blocksize 64 --overlap 12 2>&1 | tail -n2 | head -n1
+echo Benchmarking quilt with the squared L2 distance
+$TIME $CARGO_RUN_CMD quilt -- apples.png --size 1024 --blocksize 64 --overlap 12 --distance l2_squared 2>&1 | tail -n2 | head -n1
+echo Benchmarking quilt with 512 pixel patches
+$TIME $CARGO_RUN_CMD quilt -- apples.png --size 928 --blocksize 512 --overlap 96 --distance l2_squared 2>&1 | tail -n2 | head -n1
+echo Benchmarking quilt seams, candidate errors and a whole synthesis
+cargo bench --manifest-path libtexsyn/Cargo.toml --features nightly benches 2>&1 | grep "bench:"
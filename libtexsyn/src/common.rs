@@ -1,4 +1,4 @@
-use image::GenericImage;
+use image::{GenericImage, Rgb, RgbImage};
 use num_traits::Float;
 use std::cmp::Ordering;
 use std::ops::{Add, AddAssign};
@@ -44,12 +44,12 @@ impl<F> AddAssign for OrderedFloat<F> where F: Float {
 }
 
 #[derive(Debug, Clone)]
-/// Describes a square patch in the source image
+/// Describes a rectangular patch in the source image
 pub struct Patch {
     /// Coordinates of the bottom-left corner of the patch
     pub coords: (u32, u32),
-    /// Size of the patch in pixels
-    pub size: u32
+    /// Width and height of the patch in pixels
+    pub size: (u32, u32)
 }
 
 #[derive(Debug)]
@@ -59,6 +59,184 @@ pub struct Rect {
     pub size: (u32, u32)
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Describes by how many pixels an image is extended on each side
+pub struct Extension {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// One of the eight symmetries of the square, i.e. a 90° rotation or a flip
+pub enum Transform {
+    Identity,
+    /// Clockwise rotation by 90°
+    Rotate90,
+    Rotate180,
+    /// Clockwise rotation by 270°
+    Rotate270,
+    /// Mirror along the vertical axis
+    FlipHorizontal,
+    /// Mirror along the horizontal axis
+    FlipVertical,
+    /// Mirror along the main diagonal
+    Transpose,
+    /// Mirror along the anti-diagonal
+    Transverse
+}
+
+impl Transform {
+    /// Inverse of the transform.
+    pub fn inverse(&self) -> Transform {
+        match *self {
+            Transform::Rotate90 => Transform::Rotate270,
+            Transform::Rotate270 => Transform::Rotate90,
+            t => t
+        }
+    }
+
+    /// Size of an image of the specified size once transformed.
+    pub fn size(&self, (w, h): (u32, u32)) -> (u32, u32) {
+        match *self {
+            Transform::Rotate90 | Transform::Rotate270 | Transform::Transpose | Transform::Transverse => (h, w),
+            _ => (w, h)
+        }
+    }
+
+    /// Coordinates of a pixel of an image of the specified size once transformed.
+    pub fn apply(&self, (x, y): (u32, u32), (w, h): (u32, u32)) -> (u32, u32) {
+        match *self {
+            Transform::Identity => (x, y),
+            Transform::Rotate90 => (h - 1 - y, x),
+            Transform::Rotate180 => (w - 1 - x, h - 1 - y),
+            Transform::Rotate270 => (y, w - 1 - x),
+            Transform::FlipHorizontal => (w - 1 - x, y),
+            Transform::FlipVertical => (x, h - 1 - y),
+            Transform::Transpose => (y, x),
+            Transform::Transverse => (h - 1 - y, w - 1 - x)
+        }
+    }
+
+    /// Offset between two pixels once transformed.
+    pub fn apply_offset(&self, (dx, dy): (i32, i32)) -> (i32, i32) {
+        match *self {
+            Transform::Identity => (dx, dy),
+            Transform::Rotate90 => (-dy, dx),
+            Transform::Rotate180 => (-dx, -dy),
+            Transform::Rotate270 => (dy, -dx),
+            Transform::FlipHorizontal => (-dx, dy),
+            Transform::FlipVertical => (dx, -dy),
+            Transform::Transpose => (dy, dx),
+            Transform::Transverse => (-dy, -dx)
+        }
+    }
+
+    /// Transform an image.
+    pub fn apply_image(&self, img: &RgbImage) -> RgbImage {
+        let (w, h) = self.size(img.dimensions());
+        let inverse = self.inverse();
+        RgbImage::from_fn(w, h, |x, y| {
+            let (sx, sy) = inverse.apply((x, y), (w, h));
+            *img.get_pixel(sx, sy)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Set of transforms applied to the source image to generate candidates.
+/// Useful with isotropic textures, whose transformed patches are as valid as
+/// the original ones.
+pub enum TransformSet {
+    /// The source image is used as is
+    None,
+    /// Horizontal and vertical flips, and both together, which is the
+    /// rotation by 180°
+    Flips,
+    /// Rotations by multiples of 90°
+    Rotations,
+    /// All the rotations by multiples of 90° and flips
+    All
+}
+
+impl TransformSet {
+    /// List the transforms of the set, starting with the identity.
+    pub fn transforms(&self) -> Vec<Transform> {
+        match *self {
+            TransformSet::None => vec!(Transform::Identity),
+            TransformSet::Flips => vec!(Transform::Identity, Transform::FlipHorizontal, Transform::FlipVertical, Transform::Rotate180),
+            TransformSet::Rotations => vec!(Transform::Identity, Transform::Rotate90, Transform::Rotate180, Transform::Rotate270),
+            TransformSet::All => vec!(Transform::Identity, Transform::Rotate90, Transform::Rotate180, Transform::Rotate270,
+                                      Transform::FlipHorizontal, Transform::FlipVertical, Transform::Transpose, Transform::Transverse)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Pixel of the source image from which a synthesized pixel was copied
+pub struct SourcePixel {
+    /// Coordinates of the pixel in the source image
+    pub coords: (u32, u32),
+    /// Transform of the source image around the pixel
+    pub transform: Transform
+}
+
+#[derive(Debug, Clone)]
+/// Map of the source pixels from which each pixel of a synthesized image was
+/// copied. Pixels which weren't copied from the source image, e.g. the known
+/// pixels of a filled image, have no source pixel.
+pub struct SourceMap {
+    width: u32,
+    height: u32,
+    pixels: Vec<Option<SourcePixel>>
+}
+
+impl SourceMap {
+    /// Create a map of the specified size without any source pixel.
+    pub fn new(width: u32, height: u32) -> SourceMap {
+        SourceMap { width: width, height: height, pixels: vec![None; (width * height) as usize] }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Source pixel of the specified pixel, if any.
+    pub fn get(&self, x: u32, y: u32) -> Option<SourcePixel> {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, source: Option<SourcePixel>) {
+        self.pixels[(y * self.width + x) as usize] = source;
+    }
+
+    /// Render the synthesis again by copying the pixels of the specified
+    /// image, e.g. a companion map of the source image such as a normal map.
+    /// Pixels without source pixel are taken from `background`.
+    pub fn render(&self, image: &RgbImage, background: &RgbImage) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| match self.get(x, y) {
+            Some(s) => *image.get_pixel(s.coords.0, s.coords.1),
+            None => *background.get_pixel(x, y)
+        })
+    }
+
+    /// Visualize the map. The red and green channels are the coordinates of
+    /// the source pixels scaled to the size of the source image, and the blue
+    /// channel encodes their transform. Pixels without source pixel are black.
+    pub fn to_image(&self, source_size: (u32, u32)) -> RgbImage {
+        let transforms = TransformSet::All.transforms();
+        let scale = |v: u32, len: u32| if len > 1 { (v * 255 / (len - 1)) as u8 } else { 0 };
+        RgbImage::from_fn(self.width, self.height, |x, y| match self.get(x, y) {
+            Some(s) => {
+                let t = transforms.iter().position(|&t| t == s.transform).unwrap() as u8;
+                Rgb { data: [scale(s.coords.0, source_size.0), scale(s.coords.1, source_size.1), 255 - 16 * t] }
+            },
+            None => Rgb { data: [0, 0, 0] }
+        })
+    }
+}
+
 pub fn blit_rect<I>(bottom: &mut I, top: &I, rect: &Rect, buf_coords: (u32, u32))
     where I: GenericImage
 {
@@ -73,12 +251,34 @@ pub fn blit_rect<I>(bottom: &mut I, top: &I, rect: &Rect, buf_coords: (u32, u32)
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::convert::TryInto;
+
+    #[test]
+    fn test_transforms() {
+        let img = RgbImage::from_fn(3, 2, |x, y| Rgb { data: [(y * 3 + x) as u8; 3] });
+        let value = |img: &RgbImage, x, y| img.get_pixel(x, y).data[0];
+        for t in TransformSet::All.transforms() {
+            let transformed = t.apply_image(&img);
+            assert_eq!(transformed.dimensions(), t.size((3, 2)));
+            assert_eq!(t.inverse().apply_image(&transformed).into_raw(), img.clone().into_raw());
+            for (x, y, p) in img.enumerate_pixels() {
+                let (tx, ty) = t.apply((x, y), (3, 2));
+                assert_eq!(value(&transformed, tx, ty), p.data[0]);
+                // Offsets between pixels are transformed consistently
+                let (ox, oy) = t.apply_offset((1, 0));
+                if x < 2 {
+                    let (nx, ny) = t.apply((x + 1, y), (3, 2));
+                    assert_eq!((nx as i32 - tx as i32, ny as i32 - ty as i32), (ox, oy));
+                }
+            }
+        }
+        let rotated = Transform::Rotate90.apply_image(&img);
+        assert_eq!((value(&rotated, 0, 0), value(&rotated, 1, 0)), (3, 0));
+    }
 
     #[test]
     fn test_ordered_float_tryfrom() {
         let f = 72.;
-        let of: OrderedFloat<_> = f.try_into().unwrap();
+        let of = OrderedFloat::try_from(f).unwrap();
         assert!(of.val == f);
     }
 }
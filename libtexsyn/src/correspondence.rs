@@ -0,0 +1,15 @@
+//! Various correspondence quantities, used to match patches against a target
+//! image in texture transfer.
+use image::Rgb;
+
+pub type CorrespondenceFunction = fn(&Rgb<u8>) -> f64;
+
+/// Luminance of a pixel, as defined by ITU-R BT.601
+pub fn luminance(p: &Rgb<u8>) -> f64 {
+    0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64
+}
+
+/// Average of the color channels of a pixel
+pub fn intensity(p: &Rgb<u8>) -> f64 {
+    (p[0] as f64 + p[1] as f64 + p[2] as f64) / 3.
+}
@@ -3,12 +3,40 @@ use image::Rgb;
 
 pub type DistanceFunction = fn(&Rgb<u8>, &Rgb<u8>) -> f64;
 
+#[derive(Debug, Clone, Copy)]
+/// Distance used by a generator to compare pixels. Knowing which distance is
+/// used lets the generators pick faster algorithms for some of them.
+pub enum Distance {
+    /// `l1` distance
+    L1,
+    /// `l2` distance
+    L2,
+    /// `l2_squared` distance. The `Quilter` computes the overlap errors of all
+    /// the candidate patches at once with this distance, which is much faster.
+    L2Squared,
+    /// Any other distance function
+    Custom(DistanceFunction)
+}
+
+impl Distance {
+    /// Function computing the distance.
+    pub fn function(&self) -> DistanceFunction {
+        match *self {
+            Distance::L1 => l1,
+            Distance::L2 => l2,
+            Distance::L2Squared => l2_squared,
+            Distance::Custom(f) => f
+        }
+    }
+}
+
 /// L1 distance, also known as Manhattan distance
 pub fn l1(p1: &Rgb<u8>, p2: &Rgb<u8>) -> f64 {
     let f = |c1, c2| ((c1 as f64) - (c2 as f64)).abs();
     f(p1[0], p2[0]) + f(p1[1], p2[1]) + f(p1[2], p2[2])
 }
 
+/// L2 distance, also known as Euclidean distance
 pub fn l2(p1: &Rgb<u8>, p2: &Rgb<u8>) -> f64 {
     let f = |c1, c2| {
         let n = (c1 as i32) - (c2 as i32);
@@ -16,3 +44,12 @@ pub fn l2(p1: &Rgb<u8>, p2: &Rgb<u8>) -> f64 {
     };
     ((f(p1[0], p2[0]) + f(p1[1], p2[1]) + f(p1[2], p2[2])) as f64).sqrt()
 }
+
+/// Squared L2 distance
+pub fn l2_squared(p1: &Rgb<u8>, p2: &Rgb<u8>) -> f64 {
+    let f = |c1, c2| {
+        let n = (c1 as i32) - (c2 as i32);
+        n * n
+    };
+    (f(p1[0], p2[0]) + f(p1[1], p2[1]) + f(p1[2], p2[2])) as f64
+}
@@ -41,5 +41,9 @@ error_chain! {
             description("Invalid argument")
             display("Invalid argument: {}", msg)
         }
+        NotConverged(iterations: usize) {
+            description("Solver did not converge")
+            display("Solver did not converge in {} iterations", iterations)
+        }
     }
 }
@@ -0,0 +1,174 @@
+//! Fast Fourier transform, used to compute cross-correlations between images.
+use rayon::prelude::*;
+
+use std::f64::consts::PI;
+use std::ops::{Add, Sub, Mul};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re: re, im: im }
+    }
+
+    pub fn conj(self) -> Complex {
+        Complex { re: self.re, im: -self.im }
+    }
+
+    pub fn scale(self, s: f64) -> Complex {
+        Complex { re: self.re * s, im: self.im * s }
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex { re: self.re + other.re, im: self.im + other.im }
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex { re: self.re - other.re, im: self.im - other.im }
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex { re: self.re * other.re - self.im * other.im,
+                  im: self.re * other.im + self.im * other.re }
+    }
+}
+
+/// In place radix-2 FFT of a sequence whose length is a power of two. The
+/// inverse transform is not normalized.
+fn fft(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+
+    // Bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j { data.swap(i, j); }
+    }
+
+    let sign = if inverse { 1. } else { -1. };
+    let mut len = 2;
+    while len <= n {
+        let twiddles: Vec<Complex> = (0..len / 2).map(|k| {
+            let angle = sign * 2. * PI * k as f64 / len as f64;
+            Complex::new(angle.cos(), angle.sin())
+        }).collect();
+        for chunk in data.chunks_mut(len) {
+            for k in 0..len / 2 {
+                let (a, b) = (chunk[k], chunk[k + len / 2] * twiddles[k]);
+                chunk[k] = a + b;
+                chunk[k + len / 2] = a - b;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+fn transpose(data: &[Complex], width: usize, height: usize) -> Vec<Complex> {
+    let mut t = Vec::with_capacity(data.len());
+    for x in 0..width {
+        for y in 0..height {
+            t.push(data[y * width + x]);
+        }
+    }
+    t
+}
+
+/// In place 2D FFT of row-major data whose dimensions are powers of two.
+/// The inverse transform is normalized.
+fn fft_2d(data: &mut Vec<Complex>, size: (usize, usize), inverse: bool) {
+    let (width, height) = size;
+    data.par_chunks_mut(width).for_each(|row| fft(row, inverse));
+    let mut columns = transpose(data, width, height);
+    columns.par_chunks_mut(height).for_each(|column| fft(column, inverse));
+    *data = transpose(&columns, height, width);
+    if inverse {
+        let s = 1. / (width * height) as f64;
+        for c in data.iter_mut() { *c = c.scale(s); }
+    }
+}
+
+/// Size to which data of the specified size is zero-padded before being transformed.
+pub fn padded_size(size: (u32, u32)) -> (usize, usize) {
+    ((size.0 as usize).next_power_of_two(), (size.1 as usize).next_power_of_two())
+}
+
+/// Compute the spectrum of real row-major data of the specified size,
+/// zero-padded to the `padded` size.
+pub fn forward(values: &[f64], size: (u32, u32), padded: (usize, usize)) -> Vec<Complex> {
+    let mut data = vec![Complex::new(0., 0.); padded.0 * padded.1];
+    for y in 0..size.1 as usize {
+        for x in 0..size.0 as usize {
+            data[y * padded.0 + x] = Complex::new(values[y * size.0 as usize + x], 0.);
+        }
+    }
+    fft_2d(&mut data, padded, false);
+    data
+}
+
+/// Compute the real data of the specified spectrum, in row-major order at the padded size.
+pub fn inverse(mut spectrum: Vec<Complex>, padded: (usize, usize)) -> Vec<f64> {
+    fft_2d(&mut spectrum, padded, true);
+    spectrum.into_iter().map(|c| c.re).collect()
+}
+
+/// Accumulate the spectrum of the cross-correlation of an image and a kernel
+/// into `acc`, scaled by `weight`. Once transformed back, the value at `(u, v)`
+/// is the sum over the kernel pixels `p` of `kernel(p) * image((u, v) + p)`,
+/// which is exact as long as the kernel fits in the image at `(u, v)`.
+pub fn accumulate_correlation(acc: &mut [Complex], image: &[Complex], kernel: &[Complex], weight: f64) {
+    for ((a, i), k) in acc.iter_mut().zip(image).zip(kernel) {
+        *a = *a + (*i * k.conj()).scale(weight);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_correlation() {
+        let (w, h) = (7u32, 5u32);
+        let image: Vec<f64> = (0..w * h).map(|i| ((i * 37) % 11) as f64).collect();
+        let (kw, kh) = (3u32, 2u32);
+        let kernel = vec!(1., -2., 0.5, 3., 0., 1.);
+
+        let padded = padded_size((w, h));
+        let (fi, fk) = (forward(&image, (w, h), padded), forward(&kernel, (kw, kh), padded));
+        let mut acc = vec![Complex::new(0., 0.); padded.0 * padded.1];
+        accumulate_correlation(&mut acc, &fi, &fk, 2.);
+        let corr = inverse(acc, padded);
+
+        for v in 0..h - kh + 1 {
+            for u in 0..w - kw + 1 {
+                let mut expected = 0.;
+                for y in 0..kh {
+                    for x in 0..kw {
+                        expected += 2. * kernel[(y * kw + x) as usize] * image[((v + y) * w + u + x) as usize];
+                    }
+                }
+                assert!((corr[v as usize * padded.0 + u as usize] - expected).abs() < 1e-9);
+            }
+        }
+    }
+}
@@ -1,2 +1,4 @@
 pub mod patch;
 pub mod per_pixel;
+
+pub use common::{Extension, SourceMap, SourcePixel, Transform, TransformSet};
@@ -0,0 +1,192 @@
+//! Blending of patches across the seams along which they are cut.
+use std::cmp::min;
+
+/// 5-tap binomial kernel used to build the pyramids
+const BINOMIAL: [f64; 5] = [1. / 16., 4. / 16., 6. / 16., 4. / 16., 1. / 16.];
+
+#[derive(Debug, Clone)]
+/// Floating point image with an arbitrary number of channels, stored in
+/// row-major order.
+pub struct FloatImage {
+    pub width: u32,
+    pub height: u32,
+    pub channels: usize,
+    pub data: Vec<f64>
+}
+
+impl FloatImage {
+    pub fn new(width: u32, height: u32, channels: usize) -> FloatImage {
+        FloatImage { width: width, height: height, channels: channels,
+                     data: vec![0.; width as usize * height as usize * channels] }
+    }
+
+    fn index(&self, x: u32, y: u32, c: usize) -> usize {
+        (y as usize * self.width as usize + x as usize) * self.channels + c
+    }
+
+    pub fn get(&self, x: u32, y: u32, c: usize) -> f64 {
+        self.data[self.index(x, y, c)]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, c: usize, v: f64) {
+        let i = self.index(x, y, c);
+        self.data[i] = v;
+    }
+
+    /// Convolve the image with a symmetric separable kernel of odd length,
+    /// clamping coordinates at the image edges.
+    fn convolve(&self, kernel: &[f64]) -> FloatImage {
+        let r = (kernel.len() / 2) as i64;
+        let clamp = |v: i64, max: u32| if v < 0 { 0 } else { min(v, max as i64 - 1) as u32 };
+        let mut horizontal = FloatImage::new(self.width, self.height, self.channels);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                for c in 0..self.channels {
+                    let v = kernel.iter().enumerate()
+                                  .map(|(i, k)| k * self.get(clamp(x as i64 + i as i64 - r, self.width), y, c))
+                                  .sum();
+                    horizontal.set(x, y, c, v);
+                }
+            }
+        }
+        let mut res = FloatImage::new(self.width, self.height, self.channels);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                for c in 0..self.channels {
+                    let v = kernel.iter().enumerate()
+                                  .map(|(i, k)| k * horizontal.get(x, clamp(y as i64 + i as i64 - r, self.height), c))
+                                  .sum();
+                    res.set(x, y, c, v);
+                }
+            }
+        }
+        res
+    }
+
+    /// Blur and subsample the image by a factor of two.
+    fn reduce(&self) -> FloatImage {
+        let blurred = self.convolve(&BINOMIAL);
+        let mut res = FloatImage::new((self.width + 1) / 2, (self.height + 1) / 2, self.channels);
+        for y in 0..res.height {
+            for x in 0..res.width {
+                for c in 0..self.channels {
+                    res.set(x, y, c, blurred.get(2 * x, 2 * y, c));
+                }
+            }
+        }
+        res
+    }
+
+    /// Upsample the image to the specified size, and blur it.
+    fn expand(&self, width: u32, height: u32) -> FloatImage {
+        let mut res = FloatImage::new(width, height, self.channels);
+        for y in 0..height {
+            for x in 0..width {
+                for c in 0..self.channels {
+                    res.set(x, y, c, self.get(x / 2, y / 2, c));
+                }
+            }
+        }
+        res.convolve(&BINOMIAL)
+    }
+}
+
+/// Blur the image with a box filter of the specified radius.
+pub fn box_blur(img: &FloatImage, radius: u32) -> FloatImage {
+    let kernel = vec![1. / (2 * radius + 1) as f64; (2 * radius + 1) as usize];
+    img.convolve(&kernel)
+}
+
+/// Build a gaussian pyramid of at most the specified number of levels. The
+/// first level is the image itself.
+fn gaussian_pyramid(img: &FloatImage, levels: u32) -> Vec<FloatImage> {
+    let mut pyramid = vec!(img.clone());
+    for _ in 1..levels {
+        let next = {
+            let prev = pyramid.last().unwrap();
+            if prev.width == 1 || prev.height == 1 { break; }
+            prev.reduce()
+        };
+        pyramid.push(next);
+    }
+    pyramid
+}
+
+/// Build a laplacian pyramid from a gaussian pyramid. The last level is the
+/// last level of the gaussian pyramid, and each other level is the
+/// difference between the gaussian level and the expanded next level.
+fn laplacian_pyramid(gaussian: &[FloatImage]) -> Vec<FloatImage> {
+    let mut pyramid: Vec<FloatImage> = gaussian.windows(2).map(|w| {
+        let mut level = w[0].clone();
+        let expanded = w[1].expand(w[0].width, w[0].height);
+        for (v, e) in level.data.iter_mut().zip(expanded.data) { *v -= e; }
+        level
+    }).collect();
+    pyramid.push(gaussian.last().unwrap().clone());
+    pyramid
+}
+
+/// Blend two images of the same size by multi-band blending, as described by
+/// Burt and Adelson. `mask` is a single channel image giving the weight of
+/// `new` at each pixel. Each frequency band is blended over a transition
+/// area proportional to its wavelength.
+pub fn multi_band_blend(old: &FloatImage, new: &FloatImage, mask: &FloatImage, levels: u32) -> FloatImage {
+    let masks = gaussian_pyramid(mask, levels);
+    let old_bands = laplacian_pyramid(&gaussian_pyramid(old, levels));
+    let new_bands = laplacian_pyramid(&gaussian_pyramid(new, levels));
+
+    let blended: Vec<FloatImage> = masks.iter().zip(old_bands).zip(new_bands).map(|((m, mut o), n)| {
+        for y in 0..o.height {
+            for x in 0..o.width {
+                let a = m.get(x, y, 0);
+                for c in 0..o.channels {
+                    let v = a * n.get(x, y, c) + (1. - a) * o.get(x, y, c);
+                    o.set(x, y, c, v);
+                }
+            }
+        }
+        o
+    }).collect();
+
+    // Collapse the blended pyramid
+    let mut res = blended.last().unwrap().clone();
+    for level in blended.iter().rev().skip(1) {
+        let mut next = level.clone();
+        let expanded = res.expand(level.width, level.height);
+        for (v, e) in next.data.iter_mut().zip(expanded.data) { *v += e; }
+        res = next;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multi_band_blend() {
+        let mut old = FloatImage::new(13, 9, 3);
+        let mut mask = FloatImage::new(13, 9, 1);
+        for (i, v) in old.data.iter_mut().enumerate() { *v = (i % 17) as f64; }
+        for y in 0..9 {
+            for x in 7..13 { mask.set(x, y, 0, 1.); }
+        }
+
+        // Blending an image with itself leaves it unchanged
+        let res = multi_band_blend(&old, &old, &mask, 4);
+        for (r, o) in res.data.iter().zip(&old.data) {
+            assert!((r - o).abs() < 1e-9);
+        }
+
+        // Blending two flat images gives a smooth transition between them
+        let new = FloatImage { data: vec![100.; old.data.len()], ..old.clone() };
+        let old = FloatImage { data: vec![0.; old.data.len()], ..old };
+        let res = multi_band_blend(&old, &new, &mask, 3);
+        for y in 0..9 {
+            for x in 1..13 {
+                assert!(res.get(x, y, 0) >= res.get(x - 1, y, 0) - 1e-9);
+            }
+            assert!(res.get(6, y, 0) > 0. && res.get(7, y, 0) < 100.);
+        }
+    }
+}
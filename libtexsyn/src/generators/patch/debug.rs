@@ -0,0 +1,139 @@
+//! Diagnostic images of the quilting process, to help tuning the patch and
+//! overlap sizes.
+use image::*;
+
+/// Diagnostic images of a synthesis by the `Quilter` type.
+#[derive(Debug, Clone)]
+pub struct DebugReport {
+    /// Normalized error surface of the overlap area of each patch, in the
+    /// order of the placements
+    pub error_surfaces: Vec<GrayImage>,
+    /// Output image with the visible seams drawn in red
+    pub seams: RgbImage,
+    /// Output image with the outline of each patch drawn in green
+    pub outlines: RgbImage,
+    /// Heatmap of the number of candidates among which the patch covering
+    /// each pixel was chosen, from blue for a single candidate to red for the
+    /// largest number of candidates. Pixels which weren't quilted are black.
+    pub candidates: RgbImage
+}
+
+/// Debug data recorded on the buffer during a synthesis.
+pub struct DebugData {
+    width: u32,
+    height: u32,
+    /// Whether each buffer pixel lies on a visible seam
+    pub seams: Vec<bool>,
+    /// Number of candidates of the patch covering each buffer pixel
+    pub candidates: Vec<usize>,
+    /// Buffer coordinates and normalized error surface of each patch
+    pub error_surfaces: Vec<((u32, u32), GrayImage)>
+}
+
+impl DebugData {
+    pub fn new(width: u32, height: u32) -> DebugData {
+        let n = (width * height) as usize;
+        DebugData { width: width, height: height, seams: vec![false; n], candidates: vec![0; n], error_surfaces: vec!() }
+    }
+
+    /// Index of a buffer pixel.
+    pub fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    /// Draw the report of the output image, which starts at `offset` in the
+    /// buffer. Patches are given by their rectangle relative to the output
+    /// image, and wrap around its edges if `wrap` is set.
+    pub fn report(&self, output: &RgbImage, offset: (u32, u32), patches: &[((i64, i64), (u32, u32))],
+                  wrap: bool) -> DebugReport {
+        let (w, h) = output.dimensions();
+        let index = |x: u32, y: u32| self.index((offset.0 + x) % self.width, (offset.1 + y) % self.height);
+
+        let seams = RgbImage::from_fn(w, h, |x, y| {
+            if self.seams[index(x, y)] { Rgb { data: [255, 0, 0] } } else { *output.get_pixel(x, y) }
+        });
+
+        let mut outlines = output.clone();
+        for &((px, py), (pw, ph)) in patches {
+            let border = (0..pw).flat_map(|x| vec!((x, 0), (x, ph - 1)))
+                                .chain((0..ph).flat_map(|y| vec!((0, y), (pw - 1, y))));
+            for (x, y) in border {
+                let (mut x, mut y) = (px + x as i64, py + y as i64);
+                if wrap {
+                    x = (x % w as i64 + w as i64) % w as i64;
+                    y = (y % h as i64 + h as i64) % h as i64;
+                }
+                if x >= 0 && y >= 0 && x < w as i64 && y < h as i64 {
+                    outlines.put_pixel(x as u32, y as u32, Rgb { data: [0, 255, 0] });
+                }
+            }
+        }
+
+        let max = (0..h).flat_map(|y| (0..w).map(move |x| (x, y))).map(|(x, y)| self.candidates[index(x, y)])
+                        .max().unwrap_or(0);
+        let candidates = RgbImage::from_fn(w, h, |x, y| {
+            let n = self.candidates[index(x, y)];
+            if n == 0 { return Rgb { data: [0, 0, 0] }; }
+            let t = if max > 1 { (n - 1) as f64 / (max - 1) as f64 } else { 0. };
+            Rgb { data: [(255. * t).round() as u8, 0, (255. * (1. - t)).round() as u8] }
+        });
+
+        DebugReport { error_surfaces: self.error_surfaces.iter().map(|&(_, ref s)| s.clone()).collect(),
+                      seams: seams, outlines: outlines, candidates: candidates }
+    }
+}
+
+/// Generate an integer image of a normalized error surface.
+pub fn error_surface_image(err_surf: &ImageBuffer<Luma<f64>, Vec<f64>>) -> GrayImage {
+    // Find the maximum
+    let mut max = 0.;
+    for pixel in err_surf.pixels() {
+        if pixel.data[0] > max {
+            max = pixel.data[0];
+        }
+    }
+
+    // Then normalize the results
+    let mut img = GrayImage::new(err_surf.width(), err_surf.height());
+    if max > 0. {
+        for (x, y, pixel) in err_surf.enumerate_pixels() {
+            img.put_pixel(x, y, Luma { data: [(pixel.data[0] * 255. / max).round() as u8] });
+        }
+    }
+
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let output = RgbImage::from_pixel(6, 4, Rgb { data: [10, 10, 10] });
+        let mut data = DebugData::new(8, 6);
+        for x in 0..8 {
+            let i = data.index(x, 2);
+            data.seams[i] = true;
+            data.candidates[i] = 3;
+        }
+        let i = data.index(1, 3);
+        data.candidates[i] = 1;
+
+        let report = data.report(&output, (1, 1), &[((-1, -1), (3, 3))], false);
+        assert_eq!(report.seams.get_pixel(0, 1).data, [255, 0, 0]);
+        assert_eq!(report.seams.get_pixel(0, 0).data, [10, 10, 10]);
+        assert_eq!(report.outlines.get_pixel(1, 1).data, [0, 255, 0]);
+        assert_eq!(report.outlines.get_pixel(0, 0).data, [10, 10, 10]);
+        assert_eq!(report.outlines.get_pixel(2, 1).data, [10, 10, 10]);
+        assert_eq!(report.candidates.get_pixel(3, 1).data, [255, 0, 0]);
+        assert_eq!(report.candidates.get_pixel(0, 2).data, [0, 0, 255]);
+        assert_eq!(report.candidates.get_pixel(0, 0).data, [0, 0, 0]);
+
+        let mut surface = ImageBuffer::from_pixel(2, 1, Luma { data: [0.] });
+        assert_eq!(error_surface_image(&surface).into_raw(), vec!(0, 0));
+        surface.put_pixel(1, 0, Luma { data: [4.] });
+        surface.put_pixel(0, 0, Luma { data: [1.] });
+        assert_eq!(error_surface_image(&surface).into_raw(), vec!(64, 255));
+    }
+}
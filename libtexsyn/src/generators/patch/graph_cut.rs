@@ -0,0 +1,151 @@
+//! Minimum cut of a flow network, used to find the seams between patches as
+//! described by Kwatra et al.
+use std::collections::VecDeque;
+use std::f64;
+
+/// Residual capacities below this value are considered saturated.
+const CAPACITY_EPSILON: f64 = 1e-9;
+
+struct Edge {
+    to: usize,
+    capacity: f64
+}
+
+/// Flow network with real valued capacities. The edges are stored in pairs,
+/// edge `i ^ 1` being the reverse of edge `i`.
+pub struct FlowGraph {
+    edges: Vec<Edge>,
+    adjacency: Vec<Vec<usize>>
+}
+
+impl FlowGraph {
+    /// Create a new graph with the specified number of nodes and no edge.
+    pub fn new(n_nodes: usize) -> FlowGraph {
+        FlowGraph { edges: vec!(), adjacency: (0..n_nodes).map(|_| vec!()).collect() }
+    }
+
+    /// Add a node to the graph, and return its index.
+    pub fn add_node(&mut self) -> usize {
+        self.adjacency.push(vec!());
+        self.adjacency.len() - 1
+    }
+
+    /// Add an edge between two nodes, with the specified capacity in each direction.
+    pub fn add_edge(&mut self, from: usize, to: usize, capacity: f64, reverse_capacity: f64) {
+        self.adjacency[from].push(self.edges.len());
+        self.edges.push(Edge { to: to, capacity: capacity });
+        self.adjacency[to].push(self.edges.len());
+        self.edges.push(Edge { to: from, capacity: reverse_capacity });
+    }
+
+    /// Compute a minimum cut between the source and sink nodes, using
+    /// Dinic's algorithm. Returns whether each node lies on the source side
+    /// of the cut.
+    pub fn min_cut(&mut self, source: usize, sink: usize) -> Vec<bool> {
+        loop {
+            let levels = self.levels(source);
+            if levels[sink].is_none() { break; }
+            let mut next_edge = vec![0; self.adjacency.len()];
+            while self.augment(source, sink, &levels, &mut next_edge) > CAPACITY_EPSILON {}
+        }
+
+        self.levels(source).into_iter().map(|l| l.is_some()).collect()
+    }
+
+    /// Compute the BFS level of each node reachable from the source in the
+    /// residual graph.
+    fn levels(&self, source: usize) -> Vec<Option<u32>> {
+        let mut levels = vec![None; self.adjacency.len()];
+        let mut queue = VecDeque::new();
+        levels[source] = Some(0);
+        queue.push_back(source);
+        while let Some(node) = queue.pop_front() {
+            let level = levels[node].unwrap();
+            for &e in &self.adjacency[node] {
+                let edge = &self.edges[e];
+                if edge.capacity > CAPACITY_EPSILON && levels[edge.to].is_none() {
+                    levels[edge.to] = Some(level + 1);
+                    queue.push_back(edge.to);
+                }
+            }
+        }
+        levels
+    }
+
+    /// Push flow along a path of increasing levels from the source to the
+    /// sink, found by a depth-first search. `next_edge` holds the first edge
+    /// of each node which hasn't been found to lead to a dead end. Returns
+    /// the amount of flow pushed.
+    fn augment(&mut self, source: usize, sink: usize, levels: &[Option<u32>], next_edge: &mut [usize]) -> f64 {
+        // Edges of the path from the source to the current node. The search
+        // is iterative, as paths may be as long as the number of nodes.
+        let mut path: Vec<usize> = vec!();
+        let mut node = source;
+        loop {
+            if node == sink {
+                let flow = path.iter().map(|&e| self.edges[e].capacity).fold(f64::INFINITY, f64::min);
+                for &e in &path {
+                    self.edges[e].capacity -= flow;
+                    self.edges[e ^ 1].capacity += flow;
+                }
+                return flow;
+            }
+            if next_edge[node] < self.adjacency[node].len() {
+                let e = self.adjacency[node][next_edge[node]];
+                let to = self.edges[e].to;
+                if self.edges[e].capacity > CAPACITY_EPSILON && levels[to] == levels[node].map(|l| l + 1) {
+                    path.push(e);
+                    node = to;
+                } else {
+                    next_edge[node] += 1;
+                }
+            } else {
+                // Dead end: go back and skip the edge leading here
+                match path.pop() {
+                    Some(e) => {
+                        node = self.edges[e ^ 1].to;
+                        next_edge[node] += 1;
+                    },
+                    None => return 0.
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_min_cut() {
+        // Two paths from 0 to 3, the cheapest cut is on their last edges
+        let mut graph = FlowGraph::new(4);
+        graph.add_edge(0, 1, 10., 0.);
+        graph.add_edge(1, 3, 2., 0.);
+        graph.add_edge(0, 2, 10., 0.);
+        graph.add_edge(2, 3, 4., 0.);
+        graph.add_edge(1, 2, 1., 1.);
+        assert_eq!(graph.min_cut(0, 3), vec!(true, true, true, false));
+
+        // Then on the first edge of the second path
+        let mut graph = FlowGraph::new(4);
+        graph.add_edge(0, 1, 5., 0.);
+        graph.add_edge(1, 3, 2., 0.);
+        graph.add_edge(0, 2, 1., 0.);
+        graph.add_edge(2, 3, 4., 0.);
+        assert_eq!(graph.min_cut(0, 3), vec!(true, true, false, false));
+    }
+
+    #[test]
+    fn test_long_path() {
+        // A single path through many nodes, cut on its cheapest edge
+        let n = 200000;
+        let mut graph = FlowGraph::new(n);
+        for i in 0..n - 1 {
+            graph.add_edge(i, i + 1, if i == n / 2 { 1. } else { 2. }, 0.);
+        }
+        let cut = graph.min_cut(0, n - 1);
+        assert!(cut[..n / 2 + 1].iter().all(|&s| s) && cut[n / 2 + 1..].iter().all(|&s| !s));
+    }
+}
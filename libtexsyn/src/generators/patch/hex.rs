@@ -0,0 +1,450 @@
+//! Generation of hexagonal tile sets from an exemplar, extending the Wang
+//! tiles of Cohen et al. to hexagons.
+use image::*;
+use rand::{Rng, SeedableRng, XorShiftRng, thread_rng};
+use rayon::prelude::*;
+
+use std::cmp::min;
+use std::f64;
+
+use distance::DistanceFunction;
+use errors::*;
+use super::quilt::{ErrorSurface, minimum_cost_vertical_path};
+
+/// Maximum number of colors of the edges, which keeps the tile set at 4096
+/// tiles
+const MAX_COLORS: u32 = 4;
+
+#[derive(Debug, Clone, Copy)]
+/// Lattice of the centers of pointy-top hexagonal tiles. Edges are numbered
+/// clockwise from the east edge: east, south-east, south-west, west,
+/// north-west and north-east.
+struct Lattice {
+    /// Offset from the center of a tile to the center of its neighbour
+    /// across each edge
+    offsets: [(i64, i64); 6]
+}
+
+impl Lattice {
+    fn new(width: u32) -> Lattice {
+        let w = width as i64;
+        let h = (width as f64 * 3f64.sqrt() / 2.).round() as i64;
+        Lattice { offsets: [(w, 0), (w / 2, h), (-w / 2, h), (-w, 0), (-w / 2, -h), (w / 2, -h)] }
+    }
+
+    /// Check whether a pixel, relative to the center of a tile, lies in the
+    /// tile, i.e. closer to its center than to any other. Pixels equidistant
+    /// to several centers belong to the last of them in row-major order, so
+    /// that the tiles cover the plane exactly once.
+    fn contains(&self, (x, y): (i64, i64)) -> bool {
+        self.offsets.iter().enumerate().all(|(k, &(vx, vy))| {
+            let (a, b) = (2 * (x * vx + y * vy), vx * vx + vy * vy);
+            a < b || (a == b && k >= 3)
+        })
+    }
+
+    /// Edge of the triangle between the center of the tile and one of its
+    /// edges which contains a pixel.
+    fn sector(&self, (x, y): (i64, i64)) -> usize {
+        let mut best = (0, f64::NEG_INFINITY);
+        for (k, &(vx, vy)) in self.offsets.iter().enumerate() {
+            let projection = (x * vx + y * vy) as f64 / (vx * vx + vy * vy) as f64;
+            if projection > best.1 { best = (k, projection); }
+        }
+        best.0
+    }
+
+    /// Vertex between an edge and the next one, relative to the center.
+    fn vertex(&self, k: usize) -> (f64, f64) {
+        let ((x1, y1), (x2, y2)) = (self.offsets[k], self.offsets[(k + 1) % 6]);
+        ((x1 + x2) as f64 / 3., (y1 + y2) as f64 / 3.)
+    }
+}
+
+/// Seam between the triangles of an edge and the next one, running near the
+/// segment from the center of the tile to the vertex between the edges.
+struct Seam {
+    /// Unit vector along the segment
+    dir: (f64, f64),
+    /// Unit vector across the segment, pointing to the triangle of the next edge
+    perp: (f64, f64),
+    /// Length of the segment
+    length: i64,
+    overlap: i64,
+    /// Offset of the seam across the segment, at each distance from the center
+    offsets: Vec<i64>
+}
+
+impl Seam {
+    /// Maximum offset of the seam at the specified distance from the center.
+    /// The seam starts on the segment at both ends, and doesn't reach the
+    /// tile edges.
+    fn range(&self, t: i64) -> i64 {
+        min(self.overlap, min(((t - 1) / 2).max(0), ((self.length - 2 - t) / 2).max(0)))
+    }
+
+    /// Nearest pixel to a point given by its coordinates along and across
+    /// the segment.
+    fn pixel(&self, t: i64, d: i64) -> (i64, i64) {
+        ((t as f64 * self.dir.0 + d as f64 * self.perp.0).round() as i64,
+         (t as f64 * self.dir.1 + d as f64 * self.perp.1).round() as i64)
+    }
+
+    /// Check whether a pixel lies on the side of the next edge, if it lies
+    /// close enough to the segment to be cut by the seam.
+    fn side(&self, (x, y): (i64, i64)) -> Option<bool> {
+        let t = (x as f64 * self.dir.0 + y as f64 * self.dir.1).round() as i64;
+        let d = (x as f64 * self.perp.0 + y as f64 * self.perp.1).round() as i64;
+        if t < 0 || t >= self.length || d.abs() > self.range(t) { None }
+        else { Some(d >= self.offsets[t as usize]) }
+    }
+}
+
+/// Describes the parameters of the `HexTiler` type.
+pub struct HexTilerParams {
+    width: u32,
+    colors: u32,
+    overlap: u32,
+    distance_func: DistanceFunction,
+    random_seed: Option<u64>
+}
+
+impl HexTilerParams {
+    /// Create a new `HexTilerParams`
+    ///
+    /// * `width`: Distance between the centers of neighbouring tiles of a
+    ///   row, which is also the width of the tiles. Must be even.
+    /// * `colors`: Number of colors of the edges, at most 4. The set contains
+    ///   one tile for each combination of edge colors, that is `colors^6`
+    ///   tiles.
+    /// * `overlap`: Maximum distance between the seams inside a tile and the
+    ///   segments from its center to its vertices
+    /// * `distance_func`: Distance function used to compute the seams
+    pub fn new(width: u32, colors: u32, overlap: u32, distance_func: DistanceFunction) -> Result<HexTilerParams> {
+        if width < 4 || width % 2 != 0 {
+            bail!(ErrorKind::InvalidArguments("Tile width must be even and at least 4".to_owned()))
+        }
+        if colors == 0 {
+            bail!(ErrorKind::InvalidArguments("Edges need at least one color".to_owned()))
+        }
+        if colors > MAX_COLORS {
+            bail!(ErrorKind::InvalidArguments(format!("Edges can't have more than {} colors", MAX_COLORS)))
+        }
+        Ok(HexTilerParams { width: width, colors: colors, overlap: overlap, distance_func: distance_func,
+                            random_seed: None })
+    }
+
+    /// Seed of the choice of the edge samples. A given seed always produces
+    /// the same tile set. Defaults to a random seed.
+    pub fn random_seed(mut self, seed: u64) -> HexTilerParams {
+        self.random_seed = Some(seed);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Tile of a hexagonal tile set. Tiles with the same color on facing edges
+/// join seamlessly.
+pub struct HexTile {
+    /// Colors of the edges, clockwise from the east edge: east, south-east,
+    /// south-west, west, north-west and north-east
+    pub edges: [u32; 6],
+    /// Tile image, transparent outside of the hexagon
+    pub image: RgbaImage
+}
+
+#[derive(Debug, Clone)]
+/// Complete set of pointy-top hexagonal tiles, with one tile for each
+/// combination of edge colors. The tile of index `i` has the color
+/// `(i / colors^k) % colors` on its edge `k`.
+///
+/// The tiles are laid out in rows, the odd rows being shifted by half a tile
+/// to the right. All the tiles have the same shape, given by `mask`.
+pub struct HexTileSet {
+    width: u32,
+    row_height: u32,
+    colors: u32,
+    center: (u32, u32),
+    mask: GrayImage,
+    tiles: Vec<HexTile>
+}
+
+impl HexTileSet {
+    /// Tiles of the set.
+    pub fn tiles(&self) -> &[HexTile] {
+        &self.tiles
+    }
+
+    /// Mask of the hexagon in the tile images. Non zero pixels belong to the
+    /// tile.
+    pub fn mask(&self) -> &GrayImage {
+        &self.mask
+    }
+
+    /// Coordinates of the center of the hexagon in the tile images.
+    pub fn center(&self) -> (u32, u32) {
+        self.center
+    }
+
+    /// Horizontal distance between the centers of neighbouring tiles of a
+    /// row, and vertical distance between the rows.
+    pub fn spacing(&self) -> (u32, u32) {
+        (self.width, self.row_height)
+    }
+
+    /// Index of the tile with the specified edge colors.
+    pub fn index(&self, edges: &[u32; 6]) -> usize {
+        edges.iter().rev().fold(0, |i, &c| i * self.colors as usize + c as usize)
+    }
+
+    /// Composite a random arrangement of tiles, with matching colors on each
+    /// pair of facing edges, to check that the tiles join seamlessly. Pixels
+    /// outside of the arrangement are black.
+    pub fn composite(&self, columns: u32, rows: u32, seed: u64) -> Result<RgbImage> {
+        if columns == 0 || rows == 0 {
+            bail!(ErrorKind::InvalidArguments("Composite needs at least one column and one row".to_owned()))
+        }
+        let (tw, th) = self.mask.dimensions();
+        let (w, h) = (self.width, self.row_height);
+        let size = ((columns - 1).checked_mul(w).and_then(|x| x.checked_add(w / 2 + tw)),
+                    (rows - 1).checked_mul(h).and_then(|y| y.checked_add(th)));
+        let mut image = match size {
+            (Some(width), Some(height)) => RgbImage::new(width, height),
+            _ => bail!(ErrorKind::InvalidArguments("Composite is too large".to_owned()))
+        };
+        let mut rng = XorShiftRng::from_seed([seed as u32, (seed >> 32) as u32, 0x2545f491, 0x4f6cdd1d]);
+
+        let mut placed: Vec<usize> = vec!();
+        for row in 0..rows {
+            let shift = row % 2;
+            for column in 0..columns {
+                let mut edges = [0; 6];
+                for c in edges.iter_mut() { *c = rng.gen_range(0, self.colors); }
+                // Match the tiles already placed to the west, north-west and north-east
+                if column > 0 {
+                    edges[3] = self.tiles[placed[(row * columns + column - 1) as usize]].edges[0];
+                }
+                if row > 0 {
+                    let above = |c: u32| &self.tiles[placed[((row - 1) * columns + c) as usize]];
+                    if column + shift > 0 { edges[4] = above(column + shift - 1).edges[1]; }
+                    if column + shift < columns { edges[5] = above(column + shift).edges[2]; }
+                }
+                let index = self.index(&edges);
+                placed.push(index);
+
+                let (ox, oy) = (column * w + shift * w / 2, row * h);
+                for (x, y, pixel) in self.tiles[index].image.enumerate_pixels() {
+                    if pixel.data[3] != 0 {
+                        image.put_pixel(ox + x, oy + y, pixel.to_rgb());
+                    }
+                }
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+/// Generates hexagonal tile sets, with the method of Cohen et al. for Wang
+/// tiles.
+///
+/// One sample of the source image is chosen for each color of each pair of
+/// opposite edges. A tile is made of six triangles between its center and
+/// its edges, each copied from the sample of the color of the edge, so that
+/// the two halves of a sample meet on the edge shared by neighbouring tiles.
+/// Inside a tile, the triangles are cut along minimum error boundaries near
+/// the segments from the center to the vertices.
+pub struct HexTiler {
+    source: RgbImage,
+    params: HexTilerParams
+}
+
+impl HexTiler {
+    /// Create a new `HexTiler` with the specified source image.
+    pub fn new(source: RgbImage, params: HexTilerParams) -> HexTiler {
+        HexTiler { source: source, params: params }
+    }
+
+    /// Size of the edge samples, which covers the triangles on both sides of
+    /// an edge and the seams around them.
+    fn sample_size(&self) -> u32 {
+        self.params.width + 2 * self.params.overlap + 6
+    }
+
+    /// Generate a complete tile set.
+    pub fn generate(&mut self) -> Result<HexTileSet> {
+        let size = self.sample_size();
+        if size > self.source.width() || size > self.source.height() {
+            bail!(ErrorKind::InvalidArguments("Source image is too small for the tile width and overlap".to_owned()))
+        }
+        let lattice = Lattice::new(self.params.width);
+
+        // Bounding box of the hexagon
+        let w = self.params.width as i64;
+        let pixels = (-w..w + 1).flat_map(|y| (-w..w + 1).map(move |x| (x, y)))
+                                .filter(|&p| lattice.contains(p)).collect::<Vec<_>>();
+        let (x_min, y_min) = (pixels.iter().map(|p| p.0).min().unwrap(), pixels.iter().map(|p| p.1).min().unwrap());
+        let (x_max, y_max) = (pixels.iter().map(|p| p.0).max().unwrap(), pixels.iter().map(|p| p.1).max().unwrap());
+        let mut mask = GrayImage::new((x_max - x_min + 1) as u32, (y_max - y_min + 1) as u32);
+        for &(x, y) in &pixels {
+            mask.put_pixel((x - x_min) as u32, (y - y_min) as u32, Luma { data: [255] });
+        }
+
+        let seed = self.params.random_seed.unwrap_or_else(|| thread_rng().gen());
+        let mut rng = XorShiftRng::from_seed([seed as u32, (seed >> 32) as u32, 0x2545f491, 0x4f6cdd1d]);
+        let samples = (0..3).map(|_| self.samples(&mut rng)).collect::<Vec<_>>();
+
+        let n = self.params.colors;
+        let tiles = (0..n.pow(6)).into_par_iter().map(|i| {
+            let mut edges = [0; 6];
+            for (k, c) in edges.iter_mut().enumerate() { *c = (i / n.pow(k as u32)) % n; }
+            let image = self.tile(&lattice, &samples, &edges, &mask, (x_min, y_min));
+            HexTile { edges: edges, image: image }
+        }).collect();
+
+        Ok(HexTileSet { width: self.params.width, row_height: lattice.offsets[1].1 as u32, colors: n,
+                        center: ((-x_min) as u32, (-y_min) as u32), mask: mask, tiles: tiles })
+    }
+
+    /// Pick random square samples of the source image, one for each color.
+    fn samples(&self, rng: &mut XorShiftRng) -> Vec<RgbImage> {
+        let size = self.sample_size();
+        (0..self.params.colors).map(|_| {
+            let x = rng.gen_range(0, self.source.width() - size + 1);
+            let y = rng.gen_range(0, self.source.height() - size + 1);
+            RgbImage::from_fn(size, size, |dx, dy| *self.source.get_pixel(x + dx, y + dy))
+        }).collect()
+    }
+
+    /// Compute the seam between the triangles of an edge and the next one.
+    fn seam<F, G>(&self, lattice: &Lattice, k: usize, left: F, right: G) -> Seam
+        where F: Fn((i64, i64)) -> Rgb<u8>, G: Fn((i64, i64)) -> Rgb<u8> {
+        let (vx, vy) = lattice.vertex(k);
+        let length = vx.hypot(vy);
+        let dir = (vx / length, vy / length);
+        let ((x1, y1), (x2, y2)) = (lattice.offsets[k], lattice.offsets[(k + 1) % 6]);
+        let perp = if (x2 - x1) as f64 * -dir.1 + (y2 - y1) as f64 * dir.0 > 0. { (-dir.1, dir.0) }
+                   else { (dir.1, -dir.0) };
+        let overlap = self.params.overlap as i64;
+        let mut seam = Seam { dir: dir, perp: perp, length: length.floor() as i64, overlap: overlap, offsets: vec!() };
+        let dist = self.params.distance_func;
+
+        // Error surface sheared along the segment
+        let err_surf = ErrorSurface::from_fn(2 * overlap as u32 + 1, seam.length as u32, |u, t| {
+            let d = u as i64 - overlap;
+            if d.abs() > seam.range(t as i64) { return Luma { data: [f64::INFINITY] }; }
+            let p = seam.pixel(t as i64, d);
+            Luma { data: [dist(&left(p), &right(p))] }
+        });
+
+        seam.offsets = vec![0; seam.length as usize];
+        for (u, t) in minimum_cost_vertical_path(&err_surf, 0, 2 * overlap as u32 + 1) {
+            seam.offsets[t as usize] = u as i64 - overlap;
+        }
+        seam
+    }
+
+    /// Assemble a tile from the samples of its edge colors.
+    fn tile(&self, lattice: &Lattice, samples: &[Vec<RgbImage>], edges: &[u32; 6], mask: &GrayImage,
+            origin: (i64, i64)) -> RgbaImage {
+        let c = (self.sample_size() / 2) as i64;
+        // Pixel of the triangle of an edge, from the sample of its color. The
+        // sample is centered on the middle of the edge.
+        let pixel = |k: usize, (x, y): (i64, i64)| {
+            let (vx, vy) = lattice.offsets[k % 3];
+            let (mx, my) = if k < 3 { (vx / 2, vy / 2) } else { (vx / 2 - vx, vy / 2 - vy) };
+            *samples[k % 3][edges[k] as usize].get_pixel((x - mx + c) as u32, (y - my + c) as u32)
+        };
+
+        let seams = (0..6).map(|k| self.seam(lattice, k, |p| pixel(k, p), |p| pixel((k + 1) % 6, p)))
+                          .collect::<Vec<_>>();
+        let label = |p: (i64, i64)| {
+            let sector = lattice.sector(p);
+            for (k, seam) in seams.iter().enumerate() {
+                let next = (k + 1) % 6;
+                if sector != k && sector != next { continue; }
+                if let Some(right) = seam.side(p) { return if right { next } else { k }; }
+            }
+            sector
+        };
+
+        RgbaImage::from_fn(mask.width(), mask.height(), |x, y| {
+            if mask.get_pixel(x, y).data[0] == 0 { return Rgba { data: [0, 0, 0, 0] }; }
+            let p = (x as i64 + origin.0, y as i64 + origin.1);
+            let Rgb { data: [r, g, b] } = pixel(label(p), p);
+            Rgba { data: [r, g, b, 255] }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use distance::l1;
+
+    #[test]
+    fn test_lattice_partition() {
+        // Each pixel lies in exactly one tile
+        let lattice = Lattice::new(10);
+        let ((ax, ay), (bx, by)) = (lattice.offsets[0], lattice.offsets[1]);
+        for y in -10..11 {
+            for x in -10..11 {
+                let n = (-3..4).flat_map(|a| (-3..4).map(move |b| (a, b)))
+                               .filter(|&(a, b)| lattice.contains((x - a * ax - b * bx, y - a * ay - b * by)))
+                               .count();
+                assert_eq!(n, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hex_tiles() {
+        // Each source pixel holds its coordinates, so that the continuity of
+        // the tiles across their edges can be checked
+        let source = RgbImage::from_fn(64, 64, |x, y| Rgb { data: [x as u8, y as u8, 0] });
+        let params = HexTilerParams::new(24, 2, 2, l1).unwrap().random_seed(3);
+        let set = HexTiler::new(source, params).generate().unwrap();
+        assert_eq!(set.tiles().len(), 64);
+        assert_eq!(set.spacing(), (24, 21));
+        assert_eq!(set.mask().pixels().filter(|p| p.data[0] != 0).count(), 24 * 21);
+        assert_eq!(set.tiles()[37].edges, [1, 0, 1, 0, 0, 1]);
+        assert_eq!(set.index(&[1, 0, 1, 0, 0, 1]), 37);
+
+        // Neighbouring pixels across matching edges are neighbours in the
+        // source, except near the vertices where three tiles meet
+        let lattice = Lattice::new(24);
+        let (cx, cy) = (set.center().0 as i64, set.center().1 as i64);
+        for k in 0..3 {
+            let (vx, vy) = lattice.offsets[k];
+            let vertices = [lattice.vertex((k + 5) % 6), lattice.vertex(k)];
+            let mut pairs = vec!();
+            for y in -24..25 {
+                for x in -24..25 {
+                    if !lattice.contains((x, y)) { continue; }
+                    if vertices.iter().any(|&(ux, uy)| (x as f64 - ux).hypot(y as f64 - uy) < 3.) { continue; }
+                    for &(dx, dy) in &[(1, 0), (0, 1), (-1, 0), (0, -1)] {
+                        if lattice.contains((x + dx - vx, y + dy - vy)) { pairs.push(((x, y), (dx, dy))); }
+                    }
+                }
+            }
+            assert!(pairs.len() > 5);
+
+            for a in set.tiles() {
+                for b in set.tiles().iter().filter(|b| b.edges[k + 3] == a.edges[k]) {
+                    for &((x, y), (dx, dy)) in &pairs {
+                        let p = a.image.get_pixel((x + cx) as u32, (y + cy) as u32).data;
+                        let q = b.image.get_pixel((x + dx - vx + cx) as u32, (y + dy - vy + cy) as u32).data;
+                        assert_eq!((p[0] as i64 + dx, p[1] as i64 + dy), (q[0] as i64, q[1] as i64));
+                    }
+                }
+            }
+        }
+
+        // The composite covers the middle of the arrangement
+        let composite = set.composite(4, 3, 1).unwrap();
+        assert_eq!(composite.dimensions(), (3 * 24 + 12 + set.mask().width(), 2 * 21 + set.mask().height()));
+        assert_ne!(composite.get_pixel(24 + cx as u32, 21 + cy as u32).data, [0, 0, 0]);
+        assert!(set.composite(0, 3, 1).is_err() && set.composite(4, 0, 1).is_err());
+        assert!(HexTilerParams::new(24, 5, 3, l1).is_err());
+    }
+}
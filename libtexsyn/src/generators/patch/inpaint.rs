@@ -0,0 +1,280 @@
+//! Implementation of the Criminisi, Pérez and Toyama exemplar-based inpainting
+//! algorithm.
+use image::*;
+use rayon::prelude::*;
+
+use std::cmp::min;
+
+use common::{OrderedFloat, blit_rect, Rect, Patch};
+use correspondence::luminance;
+use distance::DistanceFunction;
+use errors::*;
+
+type ConfidenceMap = ImageBuffer<Luma<f64>, Vec<f64>>;
+
+/// Added to the data term of the priority, so that the confidence term still
+/// orders the fill front in flat areas where the isophotes vanish.
+const DATA_TERM_EPSILON: f64 = 1e-3;
+
+fn is_known(known: &GrayImage, x: u32, y: u32) -> bool {
+    known.get_pixel(x, y).data[0] != 0
+}
+
+/// Describes the parameters of the `Inpainter` type.
+pub struct InpainterParams {
+    patch_size: u32,
+    distance_func: DistanceFunction
+}
+
+impl InpainterParams {
+    /// Create a new `InpainterParams`
+    ///
+    /// * `patch_size`: Size of the patches filled at each step. Must be an odd number.
+    /// * `distance_func`: Distance function used to compare patches
+    pub fn new(patch_size: u32, distance_func: DistanceFunction) -> Result<InpainterParams> {
+        if patch_size % 2 == 0 {
+            bail!(ErrorKind::InvalidArguments("Patch size must be odd".to_owned()))
+        }
+        Ok(InpainterParams { patch_size: patch_size, distance_func: distance_func })
+    }
+}
+
+/// Implements the Criminisi et al. exemplar-based inpainting algorithm.
+///
+/// Holes are filled one patch at a time, in an order given by the product of
+/// a confidence term, which favors patches surrounded by known pixels, and a
+/// data term, which favors patches where strong isophotes hit the fill front.
+/// This propagates linear structures into the hole.
+pub struct Inpainter {
+    image: RgbImage,
+    params: InpainterParams
+}
+
+impl Inpainter {
+    /// Create a new `Inpainter` for the specified image.
+    pub fn new(image: RgbImage, params: InpainterParams) -> Inpainter {
+        Inpainter { image: image, params: params }
+    }
+
+    /// Fill the holes of the image. Non zero pixels of `hole_mask` are
+    /// synthesized by copying patches from outside of the holes.
+    pub fn inpaint(&mut self, hole_mask: &GrayImage) -> Result<RgbImage> {
+        if self.image.dimensions() != hole_mask.dimensions() {
+            bail!(ErrorKind::InvalidArguments("Hole mask and image dimensions differ".to_owned()))
+        }
+        let mut known = GrayImage::from_fn(hole_mask.width(), hole_mask.height(), |x, y| {
+            Luma { data: [if hole_mask.get_pixel(x, y).data[0] == 0 { 255 } else { 0 }] }
+        });
+        let sources = self.source_patches(&known);
+        if sources.is_empty() {
+            bail!(ErrorKind::InvalidArguments("No patch lies entirely outside of the holes".to_owned()))
+        }
+
+        let mut buffer = self.image.clone();
+        let mut confidence = ConfidenceMap::from_fn(known.width(), known.height(), |x, y| {
+            Luma { data: [if is_known(&known, x, y) { 1. } else { 0. }] }
+        });
+
+        loop {
+            let front = Self::fill_front(&known);
+            if front.is_empty() { break; }
+
+            // Fill the patch with the highest priority on the fill front
+            let (target, target_confidence) = front.par_iter().map(|&p| {
+                let (c, d) = (self.confidence_term(&confidence, p), self.data_term(&buffer, &known, p));
+                (p, c, OrderedFloat::try_from(c * (d + DATA_TERM_EPSILON)).unwrap())
+            }).max_by_key(|&(_, _, priority)| priority).map(|(p, c, _)| (p, c)).unwrap();
+            let rect = self.patch_rect(target);
+            let origin = self.patch_origin(target);
+            let exemplar = self.best_exemplar(&buffer, &known, &rect, origin, &sources);
+
+            // Part of the exemplar overlapping the target patch, of which only
+            // the pixels falling into the holes are copied
+            let mut pixels = RgbImage::new(rect.size.0, rect.size.1);
+            let exemplar_rect = Rect { coords: ((exemplar.coords.0 as i64 + rect.coords.0 as i64 - origin.0) as u32,
+                                                (exemplar.coords.1 as i64 + rect.coords.1 as i64 - origin.1) as u32),
+                                       size: rect.size };
+            blit_rect(&mut pixels, &buffer, &exemplar_rect, (0, 0));
+            for y in rect.coords.1..rect.coords.1 + rect.size.1 {
+                for x in rect.coords.0..rect.coords.0 + rect.size.0 {
+                    if !is_known(&known, x, y) {
+                        buffer.put_pixel(x, y, *pixels.get_pixel(x - rect.coords.0, y - rect.coords.1));
+                        known.put_pixel(x, y, Luma { data: [255] });
+                        confidence.put_pixel(x, y, Luma { data: [target_confidence] });
+                    }
+                }
+            }
+            println!("{} pixels left on the fill front", front.len());
+        }
+
+        Ok(buffer)
+    }
+
+    /// List the patches lying entirely in the known region, which are the
+    /// candidates for filling the holes.
+    fn source_patches(&self, known: &GrayImage) -> Vec<Patch> {
+        let (w, h) = known.dimensions();
+        let size = self.params.patch_size;
+        if size > w || size > h { return vec!() }
+
+        // Summed area table of the hole pixels
+        let stride = (w + 1) as usize;
+        let mut sat = vec![0u32; stride * (h + 1) as usize];
+        for y in 0..h as usize {
+            for x in 0..w as usize {
+                let hole = if is_known(known, x as u32, y as u32) { 0 } else { 1 };
+                sat[(y + 1) * stride + x + 1] = hole + sat[y * stride + x + 1] + sat[(y + 1) * stride + x] - sat[y * stride + x];
+            }
+        }
+
+        let s = size as usize;
+        let mut patches = vec!();
+        for y in 0..(h - size + 1) as usize {
+            for x in 0..(w - size + 1) as usize {
+                let holes = sat[(y + s) * stride + x + s] + sat[y * stride + x] - sat[y * stride + x + s] - sat[(y + s) * stride + x];
+                if holes == 0 {
+                    patches.push(Patch { coords: (x as u32, y as u32), size: (size, size) });
+                }
+            }
+        }
+        patches
+    }
+
+    /// Compute the hole pixels which are adjacent to known pixels.
+    fn fill_front(known: &GrayImage) -> Vec<(u32, u32)> {
+        let (w, h) = known.dimensions();
+        known.enumerate_pixels().filter(|&(x, y, p)| {
+            p.data[0] == 0 &&
+            ((x != 0 && is_known(known, x - 1, y)) || (x != w - 1 && is_known(known, x + 1, y)) ||
+             (y != 0 && is_known(known, x, y - 1)) || (y != h - 1 && is_known(known, x, y + 1)))
+        }).map(|(x, y, _)| (x, y)).collect()
+    }
+
+    /// Coordinates of the top-left corner of the patch centered on the
+    /// specified pixel. May lie outside of the image.
+    fn patch_origin(&self, center: (u32, u32)) -> (i64, i64) {
+        let half = (self.params.patch_size / 2) as i64;
+        (center.0 as i64 - half, center.1 as i64 - half)
+    }
+
+    /// Rectangle of the patch centered on the specified pixel, clipped to the image.
+    fn patch_rect(&self, center: (u32, u32)) -> Rect {
+        let half = self.params.patch_size / 2;
+        let (w, h) = self.image.dimensions();
+        let (x0, y0) = (center.0.saturating_sub(half), center.1.saturating_sub(half));
+        let (x1, y1) = (min(w - 1, center.0 + half), min(h - 1, center.1 + half));
+        Rect { coords: (x0, y0), size: (x1 - x0 + 1, y1 - y0 + 1) }
+    }
+
+    /// Compute the confidence term of the priority of a fill front pixel,
+    /// i.e. the average confidence of the pixels of its patch.
+    fn confidence_term(&self, confidence: &ConfidenceMap, center: (u32, u32)) -> f64 {
+        let rect = self.patch_rect(center);
+        let mut acc = 0.;
+        for y in rect.coords.1..rect.coords.1 + rect.size.1 {
+            for x in rect.coords.0..rect.coords.0 + rect.size.0 {
+                acc += confidence.get_pixel(x, y).data[0];
+            }
+        }
+        acc / (rect.size.0 * rect.size.1) as f64
+    }
+
+    /// Compute the data term of the priority of a fill front pixel, i.e. the
+    /// strength of the isophote hitting the fill front at this pixel.
+    fn data_term(&self, buffer: &RgbImage, known: &GrayImage, center: (u32, u32)) -> f64 {
+        let (w, h) = known.dimensions();
+        let lum = |x, y| luminance(buffer.get_pixel(x, y));
+
+        // Find the strongest gradient among the known pixels of the patch
+        let rect = self.patch_rect(center);
+        let mut gradient = (0., 0.);
+        let mut max_norm = 0.;
+        for y in rect.coords.1..rect.coords.1 + rect.size.1 {
+            for x in rect.coords.0..rect.coords.0 + rect.size.0 {
+                if x == 0 || y == 0 || x == w - 1 || y == h - 1 { continue; }
+                if !(is_known(known, x - 1, y) && is_known(known, x + 1, y) &&
+                     is_known(known, x, y - 1) && is_known(known, x, y + 1)) { continue; }
+                let (gx, gy) = ((lum(x + 1, y) - lum(x - 1, y)) / 2., (lum(x, y + 1) - lum(x, y - 1)) / 2.);
+                let norm = gx * gx + gy * gy;
+                if norm > max_norm {
+                    max_norm = norm;
+                    gradient = (gx, gy);
+                }
+            }
+        }
+
+        // The normal to the fill front is the gradient of the known mask
+        let (x, y) = center;
+        let mask = |x, y| -> f64 { if is_known(known, x, y) { 1. } else { 0. } };
+        let nx = mask(min(w - 1, x + 1), y) - mask(x.saturating_sub(1), y);
+        let ny = mask(x, min(h - 1, y + 1)) - mask(x, y.saturating_sub(1));
+        let n_norm = (nx * nx + ny * ny).sqrt();
+        if n_norm == 0. { return 0.; }
+
+        // The isophote is orthogonal to the gradient
+        let isophote = (-gradient.1, gradient.0);
+        (isophote.0 * nx + isophote.1 * ny).abs() / n_norm / 255.
+    }
+
+    /// Find the source patch which best matches the known pixels of the
+    /// target rectangle, whose full patch starts at `origin`.
+    fn best_exemplar<'a>(&self, buffer: &RgbImage, known: &GrayImage, rect: &Rect,
+                         origin: (i64, i64), sources: &'a [Patch]) -> &'a Patch {
+        let dist = self.params.distance_func;
+        sources.par_iter().map(|patch| {
+            let mut error = 0.;
+            for y in rect.coords.1..rect.coords.1 + rect.size.1 {
+                for x in rect.coords.0..rect.coords.0 + rect.size.0 {
+                    if is_known(known, x, y) {
+                        let sx = (patch.coords.0 as i64 + x as i64 - origin.0) as u32;
+                        let sy = (patch.coords.1 as i64 + y as i64 - origin.1) as u32;
+                        error += dist(buffer.get_pixel(sx, sy), buffer.get_pixel(x, y));
+                    }
+                }
+            }
+            (patch, OrderedFloat::try_from(error).unwrap())
+        }).min_by_key(|&(_, error)| error).unwrap().0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use distance::l2;
+
+    #[test]
+    fn test_inpaint() {
+        // Left part of the image is blue, right part is red. The hole lies in
+        // the red part and should be filled with red only.
+        let mut image = RgbImage::from_pixel(16, 16, Rgb { data: [200, 0, 0] });
+        let mut hole_mask = GrayImage::new(16, 16);
+        for y in 0..16 {
+            for x in 0..6 {
+                image.put_pixel(x, y, Rgb { data: [0, 0, 200] });
+            }
+        }
+        for y in 6..10 {
+            for x in 10..14 {
+                image.put_pixel(x, y, Rgb { data: [0, 255, 0] });
+                hole_mask.put_pixel(x, y, Luma { data: [255] });
+            }
+        }
+
+        let params = InpainterParams::new(5, l2).unwrap();
+        let res = Inpainter::new(image, params).inpaint(&hole_mask).unwrap();
+        for y in 6..10 {
+            for x in 10..14 {
+                assert_eq!(*res.get_pixel(x, y), Rgb { data: [200, 0, 0] });
+            }
+        }
+        assert_eq!(*res.get_pixel(0, 0), Rgb { data: [0, 0, 200] });
+    }
+
+    #[test]
+    fn test_inpaint_no_source_patch() {
+        let image = RgbImage::new(8, 8);
+        let hole_mask = GrayImage::from_pixel(8, 8, Luma { data: [255] });
+        let params = InpainterParams::new(3, l2).unwrap();
+        assert!(Inpainter::new(image, params).inpaint(&hole_mask).is_err());
+    }
+}
@@ -1,3 +1,14 @@
+mod blend;
+mod debug;
+mod graph_cut;
+mod hex;
+mod inpaint;
+mod poisson;
 mod quilt;
+mod wang;
 
-pub use self::quilt::{QuilterParams, Quilter};
+pub use self::debug::DebugReport;
+pub use self::hex::{HexTilerParams, HexTiler, HexTile, HexTileSet};
+pub use self::inpaint::{InpainterParams, Inpainter};
+pub use self::quilt::{QuilterParams, Quilter, Blending, PatchPlacement, Placement, ReuseStats, SeamMethod, TransferParams};
+pub use self::wang::{WangTilerParams, WangTiler, WangTile, WangTileSet};
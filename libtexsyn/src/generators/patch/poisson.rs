@@ -0,0 +1,366 @@
+//! Gradient domain blending, as described by Pérez, Gangnet and Blake.
+//!
+//! The blended image is the one whose gradients best match a guidance field in
+//! the least squares sense, which amounts to solving a Poisson equation. The
+//! equation is solved by the conjugate gradient method, preconditioned by a
+//! multigrid V-cycle.
+use rayon::prelude::*;
+
+use errors::*;
+use super::blend::FloatImage;
+
+/// Maximum number of conjugate gradient iterations
+const MAX_ITERATIONS: usize = 500;
+/// The solver stops once the root mean square of the residual falls below this value
+const TOLERANCE: f64 = 1e-4;
+
+/// Edge between two neighbouring pixels, from `from` to `to`. The guidance
+/// gradient along the edge is `to - from`.
+struct Edge {
+    from: usize,
+    to: usize,
+    gradient: usize
+}
+
+/// Number of nodes below which the multigrid hierarchy stops coarsening
+const COARSEST_SIZE: usize = 64;
+/// Number of symmetric Gauss-Seidel sweeps solving the coarsest level
+const COARSEST_SWEEPS: usize = 20;
+
+/// Solve for the image whose gradients best match the guidance field. Fails
+/// if the solver does not converge.
+///
+/// * `init`: Initial image, which gives the values of the fixed pixels
+/// * `grad_x`: Guidance gradient between each pixel and its right neighbour
+/// * `grad_y`: Guidance gradient between each pixel and its bottom neighbour
+/// * `fixed`: Whether each pixel keeps its initial value. If no pixel is fixed,
+/// the mean of each channel is preserved.
+/// * `wrap`: Whether the last column and row are neighbours of the first ones
+pub fn solve(init: &FloatImage, grad_x: &FloatImage, grad_y: &FloatImage, fixed: &[bool], wrap: bool) -> Result<FloatImage> {
+    let (w, h) = (init.width as usize, init.height as usize);
+    let mut edges = vec!();
+    for y in 0..h {
+        for x in 0..w {
+            let p = y * w + x;
+            if x + 1 < w || (wrap && w > 1) {
+                edges.push(Edge { from: p, to: y * w + (x + 1) % w, gradient: p });
+            }
+            if y + 1 < h || (wrap && h > 1) {
+                edges.push(Edge { from: p, to: ((y + 1) % h) * w + x, gradient: w * h + p });
+            }
+        }
+    }
+
+    let levels = hierarchy(w, h, &edges, fixed);
+    let channels: Vec<Option<Vec<f64>>> = (0..init.channels).into_par_iter().map(|c| {
+        let values: Vec<f64> = (0..w * h).map(|p| init.data[p * init.channels + c]).collect();
+        let gradients: Vec<f64> = (0..w * h).map(|p| grad_x.data[p * grad_x.channels + c])
+                                            .chain((0..w * h).map(|p| grad_y.data[p * grad_y.channels + c]))
+                                            .collect();
+        solve_channel(&values, &gradients, &edges, fixed, &levels)
+    }).collect();
+
+    let mut res = FloatImage::new(init.width, init.height, init.channels);
+    for (c, values) in channels.into_iter().enumerate() {
+        let values = match values {
+            Some(values) => values,
+            None => bail!(ErrorKind::NotConverged(MAX_ITERATIONS))
+        };
+        for (p, v) in values.into_iter().enumerate() {
+            res.data[p * init.channels + c] = v;
+        }
+    }
+    Ok(res)
+}
+
+/// Apply the graph laplacian restricted to the free pixels.
+fn laplacian(v: &[f64], edges: &[Edge], fixed: &[bool]) -> Vec<f64> {
+    let mut res = vec![0.; v.len()];
+    for e in edges {
+        let d = v[e.from] - v[e.to];
+        res[e.from] += d;
+        res[e.to] -= d;
+    }
+    for (r, &f) in res.iter_mut().zip(fixed) {
+        if f { *r = 0.; }
+    }
+    res
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// One level of the multigrid hierarchy. Its operator is a weighted graph
+/// laplacian over the nodes, plus the weight of the edges towards fixed
+/// pixels on the diagonal.
+struct Level {
+    /// Position of each node on the grid of this level
+    coords: Vec<(usize, usize)>,
+    /// Diagonal of the operator
+    diagonal: Vec<f64>,
+    /// Start of the neighbours of each node in `neighbours`
+    offsets: Vec<usize>,
+    /// Neighbouring nodes and the weights of the edges to them
+    neighbours: Vec<(usize, f64)>,
+    /// Node of the next coarser level each node is aggregated into
+    parents: Vec<usize>
+}
+
+impl Level {
+    fn new(coords: Vec<(usize, usize)>, diagonal: Vec<f64>, mut weights: Vec<(usize, usize, f64)>) -> Level {
+        // Merge the duplicate edges and sort them by node
+        weights.sort_by_key(|&(a, b, _)| (a, b));
+        let mut offsets = vec![0; coords.len() + 1];
+        let mut neighbours: Vec<(usize, f64)> = vec!();
+        let mut last = None;
+        for (a, b, weight) in weights {
+            if last == Some((a, b)) {
+                neighbours.last_mut().unwrap().1 += weight;
+            } else {
+                neighbours.push((b, weight));
+                offsets[a + 1] += 1;
+                last = Some((a, b));
+            }
+        }
+        for i in 0..coords.len() {
+            offsets[i + 1] += offsets[i];
+        }
+        Level { coords: coords, diagonal: diagonal, offsets: offsets, neighbours: neighbours, parents: vec!() }
+    }
+
+    fn len(&self) -> usize {
+        self.coords.len()
+    }
+
+    fn neighbours(&self, i: usize) -> &[(usize, f64)] {
+        &self.neighbours[self.offsets[i]..self.offsets[i + 1]]
+    }
+
+    /// Aggregate the nodes by blocks of 2x2 into a coarser level, whose
+    /// operator is the Galerkin product with piecewise constant interpolation.
+    /// Returns `None` if no two nodes can be aggregated.
+    fn coarsen(&mut self) -> Option<Level> {
+        let mut ids = ::std::collections::HashMap::new();
+        let mut coords = vec!();
+        self.parents = self.coords.iter().map(|&(x, y)| {
+            *ids.entry((x / 2, y / 2)).or_insert_with(|| { coords.push((x / 2, y / 2)); coords.len() - 1 })
+        }).collect();
+        if coords.len() == self.len() {
+            return None;
+        }
+
+        let mut diagonal = vec![0.; coords.len()];
+        let mut weights = vec!();
+        for i in 0..self.len() {
+            let parent = self.parents[i];
+            diagonal[parent] += self.diagonal[i];
+            for &(j, weight) in self.neighbours(i) {
+                if self.parents[j] == parent {
+                    diagonal[parent] -= weight;
+                } else {
+                    weights.push((parent, self.parents[j], weight));
+                }
+            }
+        }
+        Some(Level::new(coords, diagonal, weights))
+    }
+
+    /// Gauss-Seidel sweep over the nodes, forward or backward.
+    fn sweep(&self, x: &mut [f64], b: &[f64], forward: bool) {
+        for k in 0..self.len() {
+            let i = if forward { k } else { self.len() - 1 - k };
+            if self.diagonal[i] <= 0. { continue; }
+            let sum = self.neighbours(i).iter().map(|&(j, weight)| weight * x[j]).sum::<f64>();
+            x[i] = (b[i] + sum) / self.diagonal[i];
+        }
+    }
+
+    fn residual(&self, x: &[f64], b: &[f64]) -> Vec<f64> {
+        (0..self.len()).map(|i| {
+            let sum = self.neighbours(i).iter().map(|&(j, weight)| weight * x[j]).sum::<f64>();
+            b[i] - self.diagonal[i] * x[i] + sum
+        }).collect()
+    }
+}
+
+/// Build the multigrid hierarchy, whose finest level has the free pixels as
+/// nodes.
+fn hierarchy(w: usize, h: usize, edges: &[Edge], fixed: &[bool]) -> Vec<Level> {
+    let mut ids = vec![None; w * h];
+    let mut coords = vec!();
+    for p in 0..w * h {
+        if !fixed[p] {
+            ids[p] = Some(coords.len());
+            coords.push((p % w, p / w));
+        }
+    }
+    let mut diagonal = vec![0.; coords.len()];
+    let mut weights = vec!();
+    for e in edges {
+        if let Some(a) = ids[e.from] { diagonal[a] += 1.; }
+        if let Some(b) = ids[e.to] { diagonal[b] += 1.; }
+        if let (Some(a), Some(b)) = (ids[e.from], ids[e.to]) {
+            weights.push((a, b, 1.));
+            weights.push((b, a, 1.));
+        }
+    }
+
+    let mut levels = vec![Level::new(coords, diagonal, weights)];
+    while levels.last().unwrap().len() > COARSEST_SIZE {
+        match levels.last_mut().unwrap().coarsen() {
+            Some(level) => levels.push(level),
+            None => break
+        }
+    }
+    levels
+}
+
+/// Approximately solve the equation of the first level for `b` with a
+/// V-cycle. This is a symmetric positive operator, so it can precondition the
+/// conjugate gradient.
+fn v_cycle(levels: &[Level], b: &[f64]) -> Vec<f64> {
+    let level = &levels[0];
+    let mut x = vec![0.; level.len()];
+    if levels.len() == 1 {
+        for _ in 0..COARSEST_SWEEPS {
+            level.sweep(&mut x, b, true);
+            level.sweep(&mut x, b, false);
+        }
+        return x;
+    }
+
+    level.sweep(&mut x, b, true);
+    let residual = level.residual(&x, b);
+    let mut coarse_b = vec![0.; levels[1].len()];
+    for (i, r) in residual.into_iter().enumerate() {
+        coarse_b[level.parents[i]] += r;
+    }
+    let coarse_x = v_cycle(&levels[1..], &coarse_b);
+    for (i, v) in x.iter_mut().enumerate() {
+        *v += coarse_x[level.parents[i]];
+    }
+    level.sweep(&mut x, b, false);
+    x
+}
+
+/// Apply the multigrid preconditioner to a residual over all the pixels.
+fn precondition(r: &[f64], fixed: &[bool], levels: &[Level]) -> Vec<f64> {
+    let free: Vec<f64> = r.iter().zip(fixed).filter(|&(_, &f)| !f).map(|(v, _)| *v).collect();
+    let mut values = v_cycle(levels, &free).into_iter();
+    let mut z: Vec<f64> = fixed.iter().map(|&f| if f { 0. } else { values.next().unwrap() }).collect();
+    // Without fixed pixels, the operator is singular: keep the correction
+    // orthogonal to the constants so that the mean is preserved
+    if fixed.iter().all(|&f| !f) {
+        let mean = z.iter().sum::<f64>() / z.len() as f64;
+        for v in z.iter_mut() { *v -= mean; }
+    }
+    z
+}
+
+/// Solve for a single channel. The unknown is the correction to apply to
+/// the initial values, which is zero on the fixed pixels. Returns `None` if
+/// the solver does not converge.
+fn solve_channel(values: &[f64], gradients: &[f64], edges: &[Edge], fixed: &[bool], levels: &[Level])
+    -> Option<Vec<f64>> {
+    // Residual of the normal equations for the initial values
+    let mut r = laplacian(values, edges, fixed);
+    for x in r.iter_mut() { *x = -*x; }
+    for e in edges {
+        let g = gradients[e.gradient];
+        if !fixed[e.to] { r[e.to] += g; }
+        if !fixed[e.from] { r[e.from] -= g; }
+    }
+
+    let mut correction = vec![0.; values.len()];
+    let mut z = precondition(&r, fixed, levels);
+    let mut p = z.clone();
+    let mut rz = dot(&r, &z);
+    let threshold = TOLERANCE * TOLERANCE * values.len() as f64;
+    for _ in 0..MAX_ITERATIONS {
+        if dot(&r, &r) <= threshold { break; }
+        let ap = laplacian(&p, edges, fixed);
+        let pap = dot(&p, &ap);
+        if pap <= 0. { break; }
+        let alpha = rz / pap;
+        for i in 0..values.len() {
+            correction[i] += alpha * p[i];
+            r[i] -= alpha * ap[i];
+        }
+        z = precondition(&r, fixed, levels);
+        let rz_next = dot(&r, &z);
+        let beta = rz_next / rz;
+        for i in 0..values.len() {
+            p[i] = z[i] + beta * p[i];
+        }
+        rz = rz_next;
+    }
+    // Written so that a NaN residual fails too
+    if !(dot(&r, &r) <= threshold) {
+        return None;
+    }
+
+    Some(values.iter().zip(correction).map(|(v, c)| v + c).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve() {
+        // The initial image is a step, the guidance field is a ramp. With the
+        // first pixel of each row fixed, the solution is the ramp itself.
+        let (w, h) = (12, 5);
+        let mut init = FloatImage::new(w, h, 2);
+        let mut grad_x = FloatImage::new(w, h, 2);
+        let grad_y = FloatImage::new(w, h, 2);
+        let mut fixed = vec![false; (w * h) as usize];
+        for y in 0..h {
+            for x in 0..w {
+                for c in 0..2 {
+                    init.set(x, y, c, if x < w / 2 { 10. } else { 50. });
+                    grad_x.set(x, y, c, 2. + c as f64);
+                }
+            }
+            fixed[(y * w) as usize] = true;
+        }
+        let res = solve(&init, &grad_x, &grad_y, &fixed, false).unwrap();
+        for y in 0..h {
+            for x in 0..w {
+                for c in 0..2 {
+                    assert!((res.get(x, y, c) - (10. + (2. + c as f64) * x as f64)).abs() < 1e-3);
+                }
+            }
+        }
+
+        // Without fixed pixels, the mean is preserved
+        let res = solve(&init, &grad_x, &grad_y, &vec![false; (w * h) as usize], false).unwrap();
+        let mean = |img: &FloatImage| (0..w * h).map(|p| img.data[2 * p as usize]).sum::<f64>() / (w * h) as f64;
+        assert!((mean(&res) - mean(&init)).abs() < 1e-3);
+        assert!((res.get(1, 0, 0) - res.get(0, 0, 0) - 2.).abs() < 1e-3);
+
+        // A guidance field with no solution is reported
+        grad_x.set(3, 2, 1, ::std::f64::NAN);
+        assert!(solve(&init, &grad_x, &grad_y, &fixed, false).is_err());
+    }
+
+    #[test]
+    fn test_solve_large() {
+        // Fixed borders and an irregular guidance field on a large image
+        let (w, h) = (300, 200);
+        let init = FloatImage::new(w, h, 1);
+        let mut grad_x = FloatImage::new(w, h, 1);
+        let mut grad_y = FloatImage::new(w, h, 1);
+        let mut fixed = vec![false; (w * h) as usize];
+        for y in 0..h {
+            for x in 0..w {
+                grad_x.set(x, y, 0, ((x * y) % 7) as f64);
+                grad_y.set(x, y, 0, ((x + 3 * y) % 5) as f64 - 2.);
+                fixed[(y * w + x) as usize] = x == 0 || y == 0 || x == w - 1 || y == h - 1;
+            }
+        }
+        let res = solve(&init, &grad_x, &grad_y, &fixed, true).unwrap();
+        assert!(res.data.iter().all(|v| v.is_finite()));
+    }
+}
@@ -1,238 +1,2039 @@
 //! Implementation of the Efros and Freeman image quilting algorithm.
 use image::*;
-use rand::{Rng, Rand, Closed01, thread_rng};
+use image::imageops::{resize, FilterType};
+use rand::{Rng, Rand, Closed01, SeedableRng, XorShiftRng, thread_rng};
 use rand::distributions::{Range, IndependentSample};
 use rayon::prelude::*;
 
 use std::collections::HashMap;
+use std::cmp::min;
+use std::f64;
+use std::ops::Index;
 use std::sync::Mutex;
 
-use common::{OrderedFloat, blit_rect, Rect, Patch};
-use distance::DistanceFunction;
+use common::{OrderedFloat, blit_rect, Rect, Patch, Extension, SourceMap, SourcePixel, Transform, TransformSet};
+use correspondence::CorrespondenceFunction;
+use distance::{Distance, DistanceFunction};
 use errors::*;
+use fft::{self, Complex};
+use super::blend::{FloatImage, box_blur, multi_band_blend};
+use super::debug::{DebugData, DebugReport, error_surface_image};
+use super::graph_cut::FlowGraph;
+use super::poisson;
 
-type ErrorSurface = ImageBuffer<Luma<f64>, Vec<f64>>;
-type CostMap = HashMap<(u32, u32), OrderedFloat<f64>>;
+pub type ErrorSurface = ImageBuffer<Luma<f64>, Vec<f64>>;
+type CorrespondenceMap = ImageBuffer<Luma<f64>, Vec<f64>>;
+/// Source coordinates of each buffer pixel copied from the source, in row-major order
+type OriginMap = Vec<Option<(u32, u32)>>;
 
-#[allow(dead_code)]
-/// Generate an integer image of a normalized error surface.
-fn debug_error_surface(mut err_surf: ErrorSurface) -> GrayImage {
-    // Find the maximum
-    let mut max = 0.;
-    for pixel in err_surf.pixels() {
-        if pixel.data[0] > max {
-            max = pixel.data[0];
+/// State of a buffer pixel which hasn't been written yet
+const PIXEL_EMPTY: u8 = 0;
+/// State of a buffer pixel which has been copied from a patch
+const PIXEL_QUILTED: u8 = 1;
+/// State of a buffer pixel which must not be overwritten
+const PIXEL_FIXED: u8 = 2;
+
+/// Cumulative minimum costs of the paths through an overlap strip, stored
+/// in row-major order.
+struct CostMap {
+    width: u32,
+    costs: Vec<OrderedFloat<f64>>
+}
+
+impl CostMap {
+    fn new(width: u32, height: u32) -> CostMap {
+        CostMap { width: width, costs: vec![OrderedFloat::try_from(0.).unwrap(); (width * height) as usize] }
+    }
+}
+
+impl Index<(u32, u32)> for CostMap {
+    type Output = OrderedFloat<f64>;
+
+    fn index(&self, (x, y): (u32, u32)) -> &OrderedFloat<f64> {
+        &self.costs[(y * self.width + x) as usize]
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Describes which sides of a patch overlap previously quilted patches
+struct OverlapArea {
+    /// Patch overlaps the patch above it
+    top: bool,
+    /// Patch overlaps the patch on its left
+    left: bool,
+    /// Patch overlaps the patch below it. Only happens when wrapping around.
+    bottom: bool,
+    /// Patch overlaps the patch on its right. Only happens when wrapping around.
+    right: bool,
+    /// Patch overlaps known pixels anywhere in its footprint, e.g. when
+    /// filling holes
+    whole: bool
+}
+
+impl OverlapArea {
+    /// Check whether the specified pixel of a patch is part of the overlap area.
+    fn contains(&self, coords: (u32, u32), patch_size: (u32, u32), overlap: (u32, u32)) -> bool {
+        let (x, y) = coords;
+        self.whole || (self.top && y < overlap.1) || (self.bottom && y >= patch_size.1 - overlap.1) ||
+        (self.left && x < overlap.0) || (self.right && x >= patch_size.0 - overlap.0)
+    }
+
+    /// Split the overlap area into disjoint rectangles, in patch coordinates.
+    /// Horizontal strips span the whole patch width, vertical strips only
+    /// cover the rows that the horizontal ones don't.
+    fn rects(&self, patch_size: (u32, u32), overlap: (u32, u32)) -> Vec<Rect> {
+        let (width, height) = patch_size;
+        if self.whole {
+            return vec!(Rect { coords: (0, 0), size: patch_size });
+        }
+        let y_start = if self.top { overlap.1 } else { 0 };
+        let y_end = if self.bottom { height - overlap.1 } else { height };
+        let mut rects = vec!();
+        if self.top {
+            rects.push(Rect { coords: (0, 0), size: (width, overlap.1) });
+        }
+        if self.bottom {
+            rects.push(Rect { coords: (0, height - overlap.1), size: (width, overlap.1) });
+        }
+        if self.left {
+            rects.push(Rect { coords: (0, y_start), size: (overlap.0, y_end - y_start) });
+        }
+        if self.right {
+            rects.push(Rect { coords: (width - overlap.0, y_start), size: (overlap.0, y_end - y_start) });
         }
+        rects
     }
+}
+
+/// Compute the overlap area of the specified patch. In tileable mode, the
+/// last column and row of patches also overlap the first ones.
+fn patch_overlap_area(patch_no: (u32, u32), n_patches: (u32, u32), tileable: bool) -> OverlapArea {
+    OverlapArea { top: patch_no.1 != 0,
+                  left: patch_no.0 != 0,
+                  bottom: tileable && patch_no.1 == n_patches.1 - 1,
+                  right: tileable && patch_no.0 == n_patches.0 - 1,
+                  whole: false }
+}
 
-    // Then normalize the results
-    for pixel in err_surf.pixels_mut() {
-        pixel.data[0] /= max/255.;
+/// Scramble the bits of a 64-bit integer, with the finalizer of the
+/// SplitMix64 generator.
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Get a pixel of an image, with coordinates wrapping around the image edges.
+fn get_pixel_wrapped(img: &RgbImage, x: u32, y: u32) -> &Rgb<u8> {
+    img.get_pixel(x % img.width(), y % img.height())
+}
+
+/// Compute the cost map of the vertical overlap strip starting at column
+/// `x_offset` of the error surface. Cost map coordinates are relative to the strip.
+fn vertical_cost_map(err_surf: &ErrorSurface, x_offset: u32, overlap: u32) -> CostMap {
+    let size = err_surf.height();
+    let mut cost_map = CostMap::new(overlap, size);
+    let error = |x: u32, y: u32| OrderedFloat::<f64>::try_from(err_surf.get_pixel(x + x_offset, y).data[0]).unwrap();
+
+    for x in 0..overlap {
+        cost_map.costs[x as usize] = error(x, 0);
+    }
+    for y in 1..size {
+        for x in 0..overlap {
+            let mut val = cost_map[(x, y - 1)];
+            if x != 0 && cost_map[(x - 1, y - 1)] < val { val = cost_map[(x - 1, y - 1)]; }
+            if x != overlap - 1 && cost_map[(x + 1, y - 1)] < val { val = cost_map[(x + 1, y - 1)]; }
+            cost_map.costs[(y * overlap + x) as usize] = val + error(x, y);
+        }
     }
 
-    let mut img = GrayImage::new(err_surf.width(), err_surf.height());
-    for (x, y, pixel) in err_surf.enumerate_pixels() {
-        img.put_pixel(x, y, Luma { data: [pixel.data[0].round() as u8] });
+    cost_map
+}
+
+/// Compute the minimum cost vertical path through the overlap strip
+/// of width `overlap` starting at column `x_offset` of the error surface, in
+/// patch coordinates.
+pub fn minimum_cost_vertical_path(err_surf: &ErrorSurface, x_offset: u32, overlap: u32) -> Vec<(u32, u32)> {
+    let mut v = vec!();
+    let cost_map = vertical_cost_map(err_surf, x_offset, overlap);
+
+    // Find path starting point
+    let size = err_surf.height();
+    let row = (0..overlap).into_iter().map(|x| cost_map[(x, size - 1)]).collect::<Vec<_>>();
+    let (mut x, mut y) = (row.into_iter().enumerate().min_by(|&(_, v1), &(_, v2)| v1.cmp(&v2)).unwrap().0 as u32,
+                          size - 1);
+    v.push((x, y));
+    while y != 0 {
+        let top = cost_map[(x, y - 1)];
+        if overlap == 1 {}
+        else if x == 0 {
+            let right = cost_map[(x + 1, y - 1)];
+            if right < top { x += 1; }
+        }
+        else if x == overlap - 1 {
+            let left = cost_map[(x - 1, y - 1)];
+            if left < top { x -= 1; }
+        }
+        else {
+            let left = cost_map[(x - 1, y - 1)];
+            let right = cost_map[(x + 1, y - 1)];
+            if left < top {
+                if left < right { x -= 1; }
+            }
+            else if right < top { x += 1; }
+        }
+        y -= 1;
+        v.push((x, y));
     }
 
-    img
+    v.into_iter().map(|(x, y)| (x + x_offset, y)).collect()
 }
 
-#[derive(Debug, Clone, Copy)]
-/// Enumerates the possible overlapping areas of two patches
-enum OverlapArea {
-    /// Patches overlap vertically
-    Top,
-    /// Patches overlap horizontall
-    Left,
-    /// Patches overlap both vertically and horizontally
-    TopLeft
+/// Compute the cost map of the horizontal overlap strip starting at row
+/// `y_offset` of the error surface. Cost map coordinates are relative to the strip.
+fn horizontal_cost_map(err_surf: &ErrorSurface, y_offset: u32, overlap: u32) -> CostMap {
+    let size = err_surf.width();
+    let mut cost_map = CostMap::new(size, overlap);
+    let error = |x: u32, y: u32| OrderedFloat::<f64>::try_from(err_surf.get_pixel(x, y + y_offset).data[0]).unwrap();
+
+    for y in 0..overlap {
+        cost_map.costs[(y * size) as usize] = error(0, y);
+    }
+    for x in 1..size {
+        for y in 0..overlap {
+            let mut val = cost_map[(x - 1, y)];
+            if y != 0 && cost_map[(x - 1, y - 1)] < val { val = cost_map[(x - 1, y - 1)]; }
+            if y != overlap - 1 && cost_map[(x - 1, y + 1)] < val { val = cost_map[(x - 1, y + 1)]; }
+            cost_map.costs[(y * size + x) as usize] = val + error(x, y);
+        }
+    }
+
+    cost_map
 }
 
-fn patch_overlap_area(patch_no: (u32, u32)) -> OverlapArea {
-    match patch_no {
-        (0, _) => OverlapArea::Top,
-        (_, 0) => OverlapArea::Left,
-        (_, _) => OverlapArea::TopLeft,
+/// Compute the minimum cost horizontal path through the overlap strip
+/// of height `overlap` starting at row `y_offset` of the error surface, in
+/// patch coordinates.
+fn minimum_cost_horizontal_path(err_surf: &ErrorSurface, y_offset: u32, overlap: u32) -> Vec<(u32, u32)> {
+    let mut v = vec!();
+    let cost_map = horizontal_cost_map(err_surf, y_offset, overlap);
+
+    // Find path starting point
+    let size = err_surf.width();
+    let column = (0..overlap).into_iter().map(|y| cost_map[(size - 1, y)]).collect::<Vec<_>>();
+    let (mut x, mut y) = (size - 1,
+                          column.into_iter().enumerate().min_by(|&(_, v1), &(_, v2)| v1.cmp(&v2)).unwrap().0 as u32);
+    v.push((x, y));
+    while x != 0 {
+        let left = cost_map[(x - 1, y)];
+        if overlap == 1 {}
+        else if y == 0 {
+            let down = cost_map[(x - 1, y + 1)];
+            if down < left { y += 1; }
+        }
+        else if y == overlap - 1 {
+            let up = cost_map[(x - 1, y - 1)];
+            if up < left { y -= 1; }
+        }
+        else {
+            let up = cost_map[(x - 1, y - 1)];
+            let down = cost_map[(x - 1, y + 1)];
+            if up < left {
+                if up < down { y -= 1; }
+            }
+            else if down < left { y += 1; }
+        }
+        x -= 1;
+        v.push((x, y));
     }
+
+    v.into_iter().map(|(x, y)| (x, y + y_offset)).collect()
 }
 
 /// Compute the error between two images in a rectangle of specified size at
-/// the specified coordinates.
+/// the specified coordinates. Coordinates on `img2` wrap around its edges. If
+/// `mask` is specified, only the pixels of `img2` for which it is non zero
+/// are compared.
 fn patch_rect_error(distance_func: DistanceFunction, img1: &RgbImage, img2: &RgbImage,
-                    coords_i1: (u32, u32), coords_i2: (u32, u32),
+                    mask: Option<&GrayImage>, coords_i1: (u32, u32), coords_i2: (u32, u32),
                     rect_size: (u32, u32)) -> f64 {
     let (x1, y1) = coords_i1;
     let (x2, y2) = coords_i2;
     let mut acc = 0.;
     for y in 0..rect_size.1 {
         for x in 0..rect_size.0 {
+            if let Some(m) = mask {
+                if m.get_pixel((x + x2) % m.width(), (y + y2) % m.height()).data[0] == 0 { continue; }
+            }
             acc += distance_func(img1.get_pixel(x + x1, y + y1),
-                                 img2.get_pixel(x + x2, y + y2));
+                                 get_pixel_wrapped(img2, x + x2, y + y2));
         }
     };
     acc
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Method used to cut the patches along their overlap area.
+pub enum SeamMethod {
+    /// Cut each overlapping side along its minimum cost path, computed by
+    /// dynamic programming, as described by Efros and Freeman.
+    MinimumErrorBoundary,
+    /// Cut the whole overlap area at once along a minimum cut, as described
+    /// by Kwatra et al. Seams left by previous patches are remembered, so
+    /// that the new patch can replace the pixels along costly old seams.
+    GraphCut
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Method used to blend the patches across the seams.
+pub enum Blending {
+    /// Hard cut along the seams
+    Cut,
+    /// Cross-fade the patches over the specified radius around the seams
+    Feather(u32),
+    /// Blend the patches by multi-band blending, as described by Burt and
+    /// Adelson, over the specified radius around the seams. Low frequencies
+    /// are blended over a wider area than high frequencies, which hides the
+    /// seams on smooth gradients without blurring the details.
+    MultiBand(u32)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Strategy used to position the patches in the synthesized image.
+pub enum PatchPlacement {
+    /// Place the patches on a regular grid, each one overlapping the patches
+    /// above it and on its left, as described by Efros and Freeman.
+    Grid,
+    /// Place each patch at the best of `offsets` random offsets around the
+    /// first empty pixel of the image, compared against all the pixels it
+    /// overlaps, as in the entire patch matching of Kwatra et al. Then
+    /// quilt `refinements` more patches over the areas whose seams cost the
+    /// most, so that they replace the worst seams.
+    Random { offsets: u32, refinements: u32 }
+}
+
 /// Describes the parameters of the `Quilter` type.
 pub struct QuilterParams {
     size: (u32, u32),
-    patch_size: u32,
-    overlap: u32,
+    patch_size: (u32, u32),
+    overlap: (u32, u32),
     seed_coords: Option<(u32, u32)>,
     selection_chance: Option<f64>,
-    distance_func: DistanceFunction
+    distance: Distance,
+    tileable: bool,
+    seam: SeamMethod,
+    blending: Blending,
+    poisson_blending: bool,
+    repetition_penalty: f64,
+    min_repeat_distance: u32,
+    transforms: TransformSet,
+    orientations: u32,
+    scale_range: (f64, f64),
+    scale_steps: u32,
+    scale_penalty: f64,
+    passes: u32,
+    reduction: f64,
+    random_seed: Option<u64>,
+    wavefront: bool,
+    placement: PatchPlacement,
+    debug: bool
 }
 
 impl QuilterParams {
     /// Create a new `QuilterParams`
     ///
     /// * `size`: Size of the synthesized image
-    /// * `patch_size`: Size of the sample patches
-    /// * `overlap`: Size of the overlapping area between consecutive patches
+    /// * `patch_size`: Width and height of the sample patches
+    /// * `overlap`: Size of the overlapping area between horizontally and
+    /// vertically consecutive patches, i.e. width of the vertical overlap
+    /// strips and height of the horizontal ones
     /// * `seed_coords`: Coordinates of the first patch used in the algorithm
     /// * `selection_chance`: Selection chance of a patch in the selection phase.
     /// If `None`, the algorithm will perform an exhaustive search. Otherwise,
-    /// represents the probability that a patch will be considered.
-    /// * `distance_func`: Distance function used by the algorithm
-    pub fn new(size: (u32, u32), patch_size: u32, overlap: u32,
+    /// represents the probability that a patch will be considered, between 0
+    /// and 1.
+    /// * `distance`: Distance used by the algorithm
+    pub fn new(size: (u32, u32), patch_size: (u32, u32), overlap: (u32, u32),
                seed_coords: Option<(u32, u32)>, selection_chance: Option<f64>,
-               distance_func: DistanceFunction) -> Result<QuilterParams> {
+               distance: Distance) -> Result<QuilterParams> {
         // Check that input size and overlap size are non zero
         match size {
             (0, _) | (_, 0) => bail!(ErrorKind::InvalidArguments("Output size can't be zero".to_owned())),
             _ => ()
         }
-        if overlap == 0 {
+        if overlap.0 == 0 || overlap.1 == 0 {
             bail!(ErrorKind::InvalidArguments("Overlap size can't be zero".to_owned()))
         }
         // Check that the patch size is in a valid range
-        if patch_size < (2 * overlap) {
+        if patch_size.0 < (2 * overlap.0) || patch_size.1 < (2 * overlap.1) {
             bail!(ErrorKind::InvalidArguments("Patch size must be at least twice the overlap area size".to_owned()))
         }
         if let Some(s) = selection_chance {
-            if s <= 0. {
-                bail!(ErrorKind::InvalidArguments("Selection chance must be strictly positive".to_owned()))
+            if !(s > 0. && s <= 1.) {
+                bail!(ErrorKind::InvalidArguments("Selection chance must be strictly positive and at most 1".to_owned()))
             }
         }
 
         Ok(QuilterParams { size: size, patch_size: patch_size, overlap: overlap,
                            seed_coords: seed_coords,
                            selection_chance: selection_chance,
-                           distance_func: distance_func })
+                           distance: distance,
+                           tileable: false,
+                           seam: SeamMethod::MinimumErrorBoundary,
+                           blending: Blending::Cut,
+                           poisson_blending: false,
+                           repetition_penalty: 0.,
+                           min_repeat_distance: 0,
+                           transforms: TransformSet::None,
+                           orientations: 1,
+                           scale_range: (1., 1.),
+                           scale_steps: 1,
+                           scale_penalty: 0.,
+                           passes: 1,
+                           reduction: 2. / 3.,
+                           random_seed: None,
+                           wavefront: true,
+                           placement: PatchPlacement::Grid,
+                           debug: false })
+    }
+
+    /// Make the synthesized image tileable. The last column and row of
+    /// patches then overlap the first ones, so that the right and bottom
+    /// edges of the image seamlessly match the left and top edges.
+    ///
+    /// In this mode, each dimension of the output size must be a multiple of
+    /// the patch size minus the overlap in this dimension, and span at least
+    /// two patches.
+    pub fn tileable(mut self, tileable: bool) -> QuilterParams {
+        self.tileable = tileable;
+        self
+    }
+
+    /// Set the method used to cut the patches. Defaults to `SeamMethod::MinimumErrorBoundary`.
+    pub fn seam(mut self, seam: SeamMethod) -> QuilterParams {
+        self.seam = seam;
+        self
+    }
+
+    /// Set the method used to blend the patches across the seams. Defaults to `Blending::Cut`.
+    pub fn blending(mut self, blending: Blending) -> QuilterParams {
+        self.blending = blending;
+        self
+    }
+
+    /// Blend the synthesized image in the gradient domain once all the
+    /// patches are quilted. The output then has the gradients of the patches,
+    /// except across the seams, which removes the low frequency steps between
+    /// patches taken from differently lit areas of the source image.
+    pub fn poisson_blending(mut self, poisson_blending: bool) -> QuilterParams {
+        self.poisson_blending = poisson_blending;
+        self
+    }
+
+    /// Penalize the candidate patches covering source pixels which have
+    /// already been used. The error of a candidate is multiplied by
+    /// `1 + penalty * reuse`, where `reuse` is the average number of times its
+    /// pixels have been copied so far. Must be non-negative, defaults to 0.
+    pub fn repetition_penalty(mut self, penalty: f64) -> QuilterParams {
+        self.repetition_penalty = penalty;
+        self
+    }
+
+    /// Forbid placing a source patch closer than the specified distance, in
+    /// pixels, from a previous placement of the same source patch, unless no
+    /// other candidate is available. Defaults to 0, i.e. no constraint.
+    pub fn min_repeat_distance(mut self, distance: u32) -> QuilterParams {
+        self.min_repeat_distance = distance;
+        self
+    }
+
+    /// Set the transforms of the source patches considered as candidates.
+    /// Defaults to `TransformSet::None`.
+    pub fn transforms(mut self, transforms: TransformSet) -> QuilterParams {
+        self.transforms = transforms;
+        self
+    }
+
+    /// Also search the candidate patches in copies of the source image
+    /// rotated clockwise by each multiple of `360° / orientations`, cropped
+    /// to the largest rectangle lying within the rotated image. Orientations
+    /// whose rectangle can't hold a patch are left out. When painting a
+    /// stroke, the patches are taken from the orientation closest to the
+    /// direction of the stroke. Defaults to 1, the source image only.
+    pub fn orientations(mut self, orientations: u32) -> QuilterParams {
+        self.orientations = orientations;
+        self
+    }
+
+    /// Search the candidate patches in `steps` versions of the source image,
+    /// resampled at scales evenly spaced between `min` and `max`. A single
+    /// step requires `min` and `max` to be equal. Seed coordinates then refer
+    /// to the source image resampled at the `min` scale. Defaults to the
+    /// source image only, at scale 1.
+    pub fn scales(mut self, min: f64, max: f64, steps: u32) -> QuilterParams {
+        self.scale_range = (min, max);
+        self.scale_steps = steps;
+        self
+    }
+
+    /// Penalize the candidate patches taken from a resampled source image.
+    /// The error of a candidate is multiplied by `1 + penalty * |scale - 1|`.
+    /// Must be non-negative, defaults to 0.
+    pub fn scale_penalty(mut self, penalty: f64) -> QuilterParams {
+        self.scale_penalty = penalty;
+        self
+    }
+
+    /// Synthesize images in several passes. After the first pass, the image
+    /// is quilted again with the block size multiplied by `reduction` on each
+    /// pass, and the patches are chosen to match both their overlap area and
+    /// the output of the previous pass. Large blocks capture the large
+    /// structures of the texture, and small ones refine its details.
+    ///
+    /// `reduction` must be between 0 and 1 when using several passes.
+    /// Defaults to a single pass.
+    pub fn passes(mut self, passes: u32, reduction: f64) -> QuilterParams {
+        self.passes = passes;
+        self.reduction = reduction;
+        self
+    }
+
+    /// Seed of the random choices of the algorithm. A given seed always
+    /// produces the same result. Defaults to a random seed.
+    pub fn random_seed(mut self, seed: u64) -> QuilterParams {
+        self.random_seed = Some(seed);
+        self
+    }
+
+    /// Quilt the patches which don't overlap each other concurrently, in
+    /// successive waves going from the top left corner of the image to its
+    /// bottom right corner. The result is the same as when quilting the
+    /// patches one at a time. Enabled by default.
+    pub fn wavefront(mut self, wavefront: bool) -> QuilterParams {
+        self.wavefront = wavefront;
+        self
+    }
+
+    /// Set the strategy used to position the patches when synthesizing an
+    /// image. Defaults to `PatchPlacement::Grid`.
+    pub fn placement(mut self, placement: PatchPlacement) -> QuilterParams {
+        self.placement = placement;
+        self
+    }
+
+    /// Record a debug report of each synthesis, which can be retrieved with
+    /// `Quilter::debug_report`. Disabled by default.
+    pub fn debug(mut self, debug: bool) -> QuilterParams {
+        self.debug = debug;
+        self
+    }
+
+    /// Scales at which the source image is resampled.
+    fn scale_list(&self) -> Vec<f64> {
+        let (min, max) = self.scale_range;
+        if self.scale_steps <= 1 { return vec!(min); }
+        (0..self.scale_steps).map(|i| min + (max - min) * i as f64 / (self.scale_steps - 1) as f64).collect()
+    }
+}
+
+/// Patch selected to be quilted at some buffer coordinates.
+struct Selection {
+    patch: Patch,
+    /// Mask of the pixels of the patch to be copied
+    mask: GrayImage,
+    /// Number of candidates among which the patch was chosen
+    candidates: usize,
+    /// Error surface of the overlap area of the patch, when debugging
+    error_surface_opt: Option<ErrorSurface>
+}
+
+/// Describes the parameters of the texture transfer mode of the `Quilter` type.
+pub struct TransferParams {
+    correspondence: CorrespondenceFunction,
+    alpha: f64,
+    passes: u32
+}
+
+impl TransferParams {
+    /// Create a new `TransferParams`
+    ///
+    /// * `correspondence`: Quantity matched between the patches and the target image
+    /// * `alpha`: Weight of the overlap error relative to the correspondence
+    /// error, between 0 and 1. When using several passes, the weight
+    /// increases linearly from 0.1 on the first pass to `alpha` on the last one.
+    /// * `passes`: Number of synthesis passes. The block size is reduced by a
+    /// third on each pass.
+    pub fn new(correspondence: CorrespondenceFunction, alpha: f64, passes: u32) -> Result<TransferParams> {
+        if alpha < 0. || alpha > 1. {
+            bail!(ErrorKind::InvalidArguments("Alpha must be between 0 and 1".to_owned()))
+        }
+        if passes == 0 {
+            bail!(ErrorKind::InvalidArguments("Number of passes can't be zero".to_owned()))
+        }
+        Ok(TransferParams { correspondence: correspondence, alpha: alpha, passes: passes })
+    }
+
+    /// Weight of the overlap error on the specified pass.
+    fn pass_alpha(&self, pass: u32) -> f64 {
+        if self.passes == 1 { self.alpha }
+        else { 0.1 + (self.alpha - 0.1) * pass as f64 / (self.passes - 1) as f64 }
+    }
+}
+
+/// State of a texture transfer pass.
+struct Transfer {
+    /// Correspondence quantity of each pixel of the source image
+    source_map: CorrespondenceMap,
+    /// Correspondence quantity of each pixel of the target image
+    target_map: CorrespondenceMap,
+    alpha: f64
+}
+
+/// Precomputed data about the source, used to compute the overlap errors of
+/// all the candidate patches at once.
+struct SourceSpectra {
+    /// Size of the transforms
+    padded: (usize, usize),
+    /// Spectrum of each color channel
+    channels: Vec<Vec<Complex>>,
+    /// Spectrum of the squared norms of the pixels
+    norms: Vec<Complex>,
+    /// Summed-area table of the squared norms of the pixels
+    norms_sat: Vec<f64>,
+    width: u32
+}
+
+impl SourceSpectra {
+    fn new(source: &RgbImage) -> SourceSpectra {
+        let (w, h) = source.dimensions();
+        let padded = fft::padded_size((w, h));
+        let channel = |c: usize| source.pixels().map(|p| p.data[c] as f64).collect::<Vec<_>>();
+        let norms = source.pixels().map(|p| p.data.iter().map(|&v| v as f64 * v as f64).sum()).collect::<Vec<f64>>();
+
+        let stride = (w + 1) as usize;
+        let mut sat = vec![0.; stride * (h + 1) as usize];
+        for y in 0..h as usize {
+            for x in 0..w as usize {
+                sat[(y + 1) * stride + x + 1] = norms[y * w as usize + x] + sat[y * stride + x + 1] +
+                                                sat[(y + 1) * stride + x] - sat[y * stride + x];
+            }
+        }
+
+        SourceSpectra { padded: padded,
+                        channels: (0..3).map(|c| fft::forward(&channel(c), (w, h), padded)).collect(),
+                        norms: fft::forward(&norms, (w, h), padded),
+                        norms_sat: sat,
+                        width: w }
+    }
+
+    /// Sum of the squared norms of the source pixels in the specified rectangle.
+    fn norms_sum(&self, coords: (u32, u32), size: (u32, u32)) -> f64 {
+        let stride = (self.width + 1) as usize;
+        let (x0, y0) = (coords.0 as usize, coords.1 as usize);
+        let (x1, y1) = (x0 + size.0 as usize, y0 + size.1 as usize);
+        self.norms_sat[y1 * stride + x1] + self.norms_sat[y0 * stride + x0] -
+        self.norms_sat[y0 * stride + x1] - self.norms_sat[y1 * stride + x0]
     }
 }
 
+/// Resampled, transformed and rotated copy of the source image, laid out in
+/// an atlas.
+struct SourceCopy {
+    transform: Transform,
+    scale: f64,
+    /// Angle of the clockwise rotation of the copy, in radians
+    angle: f64,
+    /// Size of the copy before its rotation and cropping
+    size: (u32, u32),
+    /// Rectangle of the copy in the atlas
+    rect: Rect
+}
+
+/// Coordinates in an image of the specified size of a pixel of its copy
+/// rotated clockwise by `angle` and cropped to `rotated_size` around its
+/// center.
+fn unrotate((x, y): (f64, f64), angle: f64, rotated_size: (u32, u32), size: (u32, u32)) -> (f64, f64) {
+    let (dx, dy) = (x - (rotated_size.0 as f64 - 1.) / 2., y - (rotated_size.1 as f64 - 1.) / 2.);
+    let (c, s) = (angle.cos(), angle.sin());
+    ((size.0 as f64 - 1.) / 2. + c * dx + s * dy, (size.1 as f64 - 1.) / 2. - s * dx + c * dy)
+}
+
+/// Rotate an image clockwise by the specified angle in radians, and crop it
+/// to the largest rectangle lying within the rotated image. Pixels are
+/// bilinearly interpolated.
+fn rotate_image(image: &RgbImage, angle: f64) -> RgbImage {
+    let (w, h) = image.dimensions();
+    let (wf, hf) = (w as f64, h as f64);
+    let (c, s) = (angle.cos().abs(), angle.sin().abs());
+    let (long, short) = if w >= h { (wf, hf) } else { (hf, wf) };
+    // When the short side is too short, the rectangle touches both long
+    // sides of the rotated image, otherwise it touches all of its sides
+    let (rw, rh) = if short <= 2. * s * c * long || (s - c).abs() < 1e-10 {
+        let x = short / 2.;
+        if w >= h { (x / s, x / c) } else { (x / c, x / s) }
+    } else {
+        let cos_2a = c * c - s * s;
+        ((wf * c - hf * s) / cos_2a, (hf * c - wf * s) / cos_2a)
+    };
+    // Keep the parity of the rotated sides, so that the pixels of a quarter
+    // turn aren't interpolated
+    let (pw, ph) = if s > c { (h, w) } else { (w, h) };
+    let fit = |d: f64, parity: u32| {
+        let d = (d.min(long).floor() as u32).saturating_sub(1);
+        if d % 2 != parity % 2 { d.saturating_sub(1).max(1) } else { d.max(1) }
+    };
+    let size = (fit(rw, pw), fit(rh, ph));
+    RgbImage::from_fn(size.0, size.1, |x, y| {
+        let (ux, uy) = unrotate((x as f64, y as f64), angle, size, (w, h));
+        let (ux, uy) = (ux.max(0.).min(w as f64 - 1.), uy.max(0.).min(h as f64 - 1.));
+        let (x0, y0) = (ux.floor() as u32, uy.floor() as u32);
+        let (x1, y1) = (min(x0 + 1, w - 1), min(y0 + 1, h - 1));
+        let (fx, fy) = (ux - x0 as f64, uy - y0 as f64);
+        let mut pixel = Rgb { data: [0; 3] };
+        for c in 0..3 {
+            let v = |x, y| image.get_pixel(x, y).data[c] as f64;
+            let top = v(x0, y0) * (1. - fx) + v(x1, y0) * fx;
+            let bottom = v(x0, y1) * (1. - fx) + v(x1, y1) * fx;
+            pixel.data[c] = (top * (1. - fy) + bottom * fy).round() as u8;
+        }
+        pixel
+    })
+}
+
+/// Lay out the copies of the source image resampled at each scale,
+/// transformed by each transform and rotated in each orientation in an atlas,
+/// one row per scale. The first copy is the untransformed source image at the
+/// first scale, at the origin. Rotated copies too small to hold a patch of
+/// the specified size are left out.
+fn source_atlas(source: RgbImage, scales: &[f64], transforms: TransformSet, orientations: u32, patch_size: (u32, u32))
+                -> (RgbImage, Vec<SourceCopy>) {
+    let transforms = transforms.transforms();
+    if scales == [1.] && transforms.len() == 1 && orientations == 1 {
+        let size = source.dimensions();
+        return (source, vec!(SourceCopy { transform: Transform::Identity, scale: 1., angle: 0., size: size,
+                                          rect: Rect { coords: (0, 0), size: size } }));
+    }
+    let (w, h) = source.dimensions();
+    let mut images = vec!();
+    for &scale in scales {
+        let resampled = if scale == 1. { source.clone() }
+                        else { resize(&source, ::std::cmp::max(1, (w as f64 * scale).round() as u32),
+                                      ::std::cmp::max(1, (h as f64 * scale).round() as u32), FilterType::Triangle) };
+        let mut row = vec!();
+        for t in &transforms {
+            let transformed = t.apply_image(&resampled);
+            for k in 0..orientations {
+                let angle = 2. * f64::consts::PI * k as f64 / orientations as f64;
+                let rotated = if k == 0 { transformed.clone() } else { rotate_image(&transformed, angle) };
+                if k != 0 && (rotated.width() < patch_size.0 || rotated.height() < patch_size.1) { continue; }
+                row.push((*t, scale, angle, transformed.dimensions(), rotated));
+            }
+        }
+        images.push(row);
+    }
+    let width = images.iter().map(|row| row.iter().map(|&(_, _, _, _, ref img)| img.width()).sum()).max().unwrap();
+    let height = images.iter().map(|row| row.iter().map(|&(_, _, _, _, ref img)| img.height()).max().unwrap()).sum();
+
+    let mut atlas = RgbImage::new(width, height);
+    let mut copies = vec!();
+    let mut y = 0;
+    for row in images {
+        let row_height = row.iter().map(|&(_, _, _, _, ref img)| img.height()).max().unwrap();
+        let mut x = 0;
+        for (t, scale, angle, size, img) in row {
+            let rect = Rect { coords: (0, 0), size: img.dimensions() };
+            blit_rect(&mut atlas, &img, &rect, (x, y));
+            copies.push(SourceCopy { transform: t, scale: scale, angle: angle, size: size,
+                                     rect: Rect { coords: (x, y), size: rect.size } });
+            x += rect.size.0;
+        }
+        y += row_height;
+    }
+    (atlas, copies)
+}
+
+/// Index of the copy containing the specified atlas pixel, if any.
+fn copy_index(copies: &[SourceCopy], (x, y): (u32, u32)) -> Option<usize> {
+    copies.iter().position(|c| x >= c.rect.coords.0 && y >= c.rect.coords.1 &&
+                               x < c.rect.coords.0 + c.rect.size.0 && y < c.rect.coords.1 + c.rect.size.1)
+}
+
+/// Coordinates in the source image of the specified atlas pixel.
+fn source_coords(copies: &[SourceCopy], source_size: (u32, u32), (x, y): (u32, u32)) -> (u32, u32) {
+    let copy = &copies[copy_index(copies, (x, y)).unwrap()];
+    let (x, y) = (x - copy.rect.coords.0, y - copy.rect.coords.1);
+    let (x, y) = if copy.angle == 0. { (x, y) } else {
+        let (ux, uy) = unrotate((x as f64, y as f64), copy.angle, copy.rect.size, copy.size);
+        (min(copy.size.0 - 1, ux.round().max(0.) as u32), min(copy.size.1 - 1, uy.round().max(0.) as u32))
+    };
+    let (cx, cy) = copy.transform.inverse().apply((x, y), copy.size);
+    (min(source_size.0 - 1, (cx as f64 / copy.scale) as u32), min(source_size.1 - 1, (cy as f64 / copy.scale) as u32))
+}
+
+/// Usage of the source image during a synthesis.
+struct Usage {
+    /// Number of times each source pixel has been copied, in row-major order
+    counts: Vec<u32>,
+    /// Source and buffer coordinates of each quilted patch
+    placements: Vec<((u32, u32), (u32, u32))>,
+    /// Buffer coordinates of the placements of each area of the source
+    /// image, by coordinates of the area in the source image
+    placed_at: HashMap<(u32, u32), Vec<(u32, u32)>>,
+    width: u32
+}
+
+impl Usage {
+    fn new(source_size: (u32, u32)) -> Usage {
+        Usage { counts: vec![0; (source_size.0 * source_size.1) as usize], placements: vec!(), placed_at: HashMap::new(),
+                width: source_size.0 }
+    }
+
+    /// Record the placement of a candidate patch at the specified buffer
+    /// coordinates, given the coordinates of the area of the source image it
+    /// covers.
+    fn place(&mut self, coords: (u32, u32), source_coords: (u32, u32), buf_coords: (u32, u32)) {
+        self.placements.push((coords, buf_coords));
+        self.placed_at.entry(source_coords).or_insert_with(Vec::new).push(buf_coords);
+    }
+
+    /// Summed-area table of the use counts.
+    fn counts_sat(&self) -> Vec<u64> {
+        let (w, h) = (self.width as usize, self.counts.len() / self.width as usize);
+        let stride = w + 1;
+        let mut sat = vec![0; stride * (h + 1)];
+        for y in 0..h {
+            for x in 0..w {
+                sat[(y + 1) * stride + x + 1] = self.counts[y * w + x] as u64 + sat[y * stride + x + 1] +
+                                                sat[(y + 1) * stride + x] - sat[y * stride + x];
+            }
+        }
+        sat
+    }
+
+    /// Average number of times the pixels of the specified patch have been
+    /// copied, given the summed-area table of the use counts.
+    fn reuse(&self, sat: &[u64], patch: &Patch) -> f64 {
+        let stride = (self.width + 1) as usize;
+        let (x0, y0) = (patch.coords.0 as usize, patch.coords.1 as usize);
+        let (x1, y1) = (x0 + patch.size.0 as usize, y0 + patch.size.1 as usize);
+        let sum = sat[y1 * stride + x1] + sat[y0 * stride + x0] - sat[y0 * stride + x1] - sat[y1 * stride + x0];
+        sum as f64 / (patch.size.0 * patch.size.1) as f64
+    }
+
+    /// Check whether the area of the source image at the specified
+    /// coordinates has been placed closer than `distance` from the specified
+    /// buffer coordinates, through any copy of the source image.
+    fn placed_near(&self, source_coords: (u32, u32), buf_coords: (u32, u32), distance: u32) -> bool {
+        self.placed_at.get(&source_coords).map_or(false, |placed| placed.iter().any(|&b| {
+            let (dx, dy) = (b.0 as f64 - buf_coords.0 as f64, b.1 as f64 - buf_coords.1 as f64);
+            dx * dx + dy * dy < (distance as f64) * (distance as f64)
+        }))
+    }
+
+    fn stats(&self) -> ReuseStats {
+        let mut sources: Vec<(u32, u32)> = self.placements.iter().map(|&(s, _)| s).collect();
+        sources.sort();
+        let mut max_uses = 0;
+        let mut distinct = 0;
+        let mut i = 0;
+        while i < sources.len() {
+            let run = sources[i..].iter().take_while(|&&s| s == sources[i]).count();
+            max_uses = ::std::cmp::max(max_uses, run);
+            distinct += 1;
+            i += run;
+        }
+        let used = self.counts.iter().filter(|&&c| c > 0).count();
+        ReuseStats { patches: self.placements.len(),
+                     distinct_patches: distinct,
+                     max_uses: max_uses,
+                     coverage: used as f64 / self.counts.len() as f64,
+                     mean_pixel_uses: if used == 0 { 0. }
+                                      else { self.counts.iter().map(|&c| c as f64).sum::<f64>() / used as f64 } }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Placement of a source patch in the synthesized image.
+pub struct Placement {
+    /// Coordinates of the top-left corner of the patch in the output image.
+    /// Patches may start outside of the output image when extending an image.
+    pub coords: (i64, i64),
+    /// Coordinates of the top-left corner of the area of the source image
+    /// covered by the patch
+    pub source_coords: (u32, u32),
+    /// Scale at which the source image was resampled
+    pub scale: f64,
+    /// Angle of the clockwise rotation of the source image, in radians
+    pub angle: f64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Statistics about the reuse of the source image during a synthesis.
+pub struct ReuseStats {
+    /// Number of patches quilted
+    pub patches: usize,
+    /// Number of distinct source patches used
+    pub distinct_patches: usize,
+    /// Largest number of placements of a single source patch
+    pub max_uses: usize,
+    /// Fraction of the source pixels copied at least once
+    pub coverage: f64,
+    /// Average number of times each copied source pixel was copied
+    pub mean_pixel_uses: f64
+}
+
 /// Implements the Efros and Freeman image quilting algorithm.
 pub struct Quilter {
+    /// Atlas of the resampled and transformed copies of the source image
     source: RgbImage,
+    copies: Vec<SourceCopy>,
+    source_size: (u32, u32),
+    placements: Vec<Placement>,
     buffer_opt: Option<RgbImage>,
+    /// State of each pixel of the buffer
+    state_opt: Option<GrayImage>,
+    spectra_opt: Option<SourceSpectra>,
+    origin_opt: Option<OriginMap>,
+    transfer_opt: Option<Transfer>,
+    /// Output of the previous pass, if any
+    previous_opt: Option<RgbImage>,
+    /// Orientation of the copies of the source image in which the candidates
+    /// are searched, when painting a stroke
+    stroke_angle_opt: Option<f64>,
+    /// Seed of the random number generators of the current synthesis
+    rng_seed: u64,
+    usage_opt: Option<Usage>,
+    stats_opt: Option<ReuseStats>,
+    source_map_opt: Option<SourceMap>,
+    debug_opt: Option<DebugData>,
+    report_opt: Option<DebugReport>,
     params: QuilterParams
 }
 
 impl Quilter {
     /// Create a new `Quilter`.
     pub fn new(source: RgbImage, params: QuilterParams) -> Quilter {
-        Quilter { source: source, buffer_opt: None, params: params }
+        // Invalid scales are rejected when synthesizing
+        let mut scales: Vec<f64> = params.scale_list().into_iter().filter(|&s| s > 0.).collect();
+        if scales.is_empty() { scales.push(1.); }
+        let source_size = source.dimensions();
+        let (source, copies) = source_atlas(source, &scales, params.transforms, ::std::cmp::max(1, params.orientations),
+                                            params.patch_size);
+        Quilter { source: source, copies: copies, source_size: source_size, placements: vec!(), buffer_opt: None, state_opt: None, spectra_opt: None, origin_opt: None,
+                  transfer_opt: None, previous_opt: None, stroke_angle_opt: None, rng_seed: thread_rng().gen(), usage_opt: None, stats_opt: None,
+                  source_map_opt: None, debug_opt: None, report_opt: None, params: params }
     }
 
     fn validate_params(&self, source_size: (u32, u32)) -> Result<()> {
         // Safety checks
         // Check that the image dimensions are at least as large as the patch size
         let (src_width, src_height) = source_size;
-        if self.params.patch_size > src_width || self.params.patch_size > src_height {
-            bail!(ErrorKind::InvalidArguments("Patch size must be smaller than the image smallest dimension".to_owned()))
+        let (patch_width, patch_height) = self.params.patch_size;
+        let (min_scale, max_scale) = self.params.scale_range;
+        if !(min_scale > 0.) || max_scale < min_scale || self.params.scale_steps == 0 ||
+           (self.params.scale_steps == 1 && min_scale != max_scale) {
+            bail!(ErrorKind::InvalidArguments("Invalid scale range".to_owned()))
+        }
+        if self.params.scale_penalty < 0. {
+            bail!(ErrorKind::InvalidArguments("Scale penalty must be non-negative".to_owned()))
+        }
+        if self.params.orientations == 0 {
+            bail!(ErrorKind::InvalidArguments("At least one orientation is required".to_owned()))
+        }
+        if let PatchPlacement::Random { offsets: 0, .. } = self.params.placement {
+            bail!(ErrorKind::InvalidArguments("Random placement needs at least one offset".to_owned()))
+        }
+        // Check that at least one candidate patch is valid, so that the
+        // selection of a patch always succeeds
+        if !self.copies.iter().any(|c| patch_width <= c.rect.size.0 && patch_height <= c.rect.size.1) {
+            bail!(ErrorKind::InvalidArguments("Patch size must be smaller than the image dimensions".to_owned()))
         }
         // Check that the seed patch is within bounds
         if let Some((x_seed, y_seed)) = self.params.seed_coords {
-            if (x_seed + self.params.patch_size) > src_width || (y_seed + self.params.patch_size) > src_height {
+            if (x_seed + patch_width) > src_width || (y_seed + patch_height) > src_height {
                 bail!(ErrorKind::InvalidArguments("Seed patch coordinates are out of bounds".to_owned()))
             }
         }
+        if self.params.repetition_penalty < 0. {
+            bail!(ErrorKind::InvalidArguments("Repetition penalty must be non-negative".to_owned()))
+        }
+        // Check that the patches wrap around exactly in tileable mode
+        if self.params.tileable {
+            let step = self.step();
+            let (width, height) = self.params.size;
+            if width % step.0 != 0 || height % step.1 != 0 {
+                bail!(ErrorKind::InvalidArguments("Tileable output size must be a multiple of patch size minus overlap".to_owned()))
+            }
+            if width < 2 * step.0 || height < 2 * step.1 {
+                bail!(ErrorKind::InvalidArguments("Tileable output must span at least two patches in each dimension".to_owned()))
+            }
+        }
         Ok(())
     }
 
+    /// Statistics about the reuse of the source image during the last synthesis.
+    pub fn reuse_stats(&self) -> Option<&ReuseStats> {
+        self.stats_opt.as_ref()
+    }
+
+    /// Placements of the patches quilted during the last synthesis, in quilting order.
+    pub fn placements(&self) -> &[Placement] {
+        &self.placements
+    }
+
+    /// Debug report of the last synthesis, if enabled in the parameters. With
+    /// several passes, this is the report of the last pass.
+    pub fn debug_report(&self) -> Option<&DebugReport> {
+        self.report_opt.as_ref()
+    }
+
+    /// Source pixel of each pixel of the image synthesized during the last
+    /// synthesis. Coordinates refer to the original source image, at scale 1.
+    pub fn source_map(&self) -> Option<&SourceMap> {
+        self.source_map_opt.as_ref()
+    }
+
+    /// Validate the parameters and draw the random seed of a new synthesis.
+    fn prepare(&mut self) -> Result<()> {
+        let d = self.first_copy_size();
+        try!(self.validate_params(d));
+        self.rng_seed = self.params.random_seed.unwrap_or_else(|| thread_rng().gen());
+        Ok(())
+    }
+
+    /// Random number generator of the patch quilted at the specified buffer
+    /// coordinates. Seeding one generator per patch makes the result
+    /// independent of the order in which the patches are quilted.
+    fn patch_rng(&self, (x, y): (u32, u32)) -> XorShiftRng {
+        let a = mix(self.rng_seed);
+        let b = mix(a ^ ((x as u64) << 32 | y as u64));
+        let c = mix(b);
+        // The generator can't be seeded with zeros only
+        XorShiftRng::from_seed([b as u32, (b >> 32) as u32, c as u32, (c >> 32) as u32 | 1])
+    }
+
+    /// Size of the first copy of the source image, to which the seed coordinates refer.
+    fn first_copy_size(&self) -> (u32, u32) {
+        self.copies[0].rect.size
+    }
+
+    /// Check whether the candidate patch at the specified atlas coordinates
+    /// lies within a single copy of the source image, in the orientation of
+    /// the stroke being painted if any.
+    fn candidate_valid(&self, (x, y): (u32, u32)) -> bool {
+        let (w, h) = self.params.patch_size;
+        self.copies.iter().any(|c| x >= c.rect.coords.0 && y >= c.rect.coords.1 &&
+                                   x + w <= c.rect.coords.0 + c.rect.size.0 && y + h <= c.rect.coords.1 + c.rect.size.1 &&
+                                   self.stroke_angle_opt.map_or(true, |a| c.angle == a))
+    }
+
+    /// Area of the source image covered by the specified candidate patch.
+    fn source_patch(&self, patch: &Patch) -> Patch {
+        let (x0, y0) = source_coords(&self.copies, self.source_size, patch.coords);
+        let (x1, y1) = source_coords(&self.copies, self.source_size,
+                                     (patch.coords.0 + patch.size.0 - 1, patch.coords.1 + patch.size.1 - 1));
+        Patch { coords: (min(x0, x1), min(y0, y1)),
+                size: ((x0 as i64 - x1 as i64).abs() as u32 + 1, (y0 as i64 - y1 as i64).abs() as u32 + 1) }
+    }
+
+    /// Allocate the buffer and the associated maps.
+    fn init_buffer(&mut self, size: (u32, u32)) {
+        self.buffer_opt = Some(RgbImage::new(size.0, size.1));
+        self.state_opt = Some(GrayImage::new(size.0, size.1));
+        self.origin_opt = Some(vec![None; (size.0 * size.1) as usize]);
+        self.usage_opt = Some(Usage::new(self.source_size));
+        self.debug_opt = if self.params.debug { Some(DebugData::new(size.0, size.1)) } else { None };
+    }
+
+    /// Release the maps associated to the buffer, record the placements of
+    /// the patches and the source map relative to the output image, which is
+    /// the specified rectangle of the buffer, and the reuse of the source
+    /// image.
+    fn release_buffer(&mut self, output: &Rect) {
+        let offset = output.coords;
+        self.state_opt = None;
+        let origins = self.origin_opt.take().unwrap();
+        let bw = self.buffer_opt.as_ref().unwrap().width();
+        let mut source_map = SourceMap::new(output.size.0, output.size.1);
+        for y in 0..output.size.1 {
+            for x in 0..output.size.0 {
+                if let Some(o) = origins[((offset.1 + y) * bw + offset.0 + x) as usize] {
+                    let copy = &self.copies[copy_index(&self.copies, o).unwrap()];
+                    let coords = source_coords(&self.copies, self.source_size, o);
+                    source_map.set(x, y, Some(SourcePixel { coords: coords, transform: copy.transform }));
+                }
+            }
+        }
+        self.source_map_opt = Some(source_map);
+        let usage = self.usage_opt.take().unwrap();
+        self.placements = usage.placements.iter().map(|&(s, b)| {
+            let copy = &self.copies[copy_index(&self.copies, s).unwrap()];
+            Placement { coords: (b.0 as i64 - offset.0 as i64, b.1 as i64 - offset.1 as i64),
+                        source_coords: self.source_patch(&Patch { coords: s, size: self.params.patch_size }).coords,
+                        scale: copy.scale, angle: copy.angle }
+        }).collect();
+        if let Some(debug) = self.debug_opt.take() {
+            let patches: Vec<_> = self.placements.iter().map(|p| (p.coords, self.params.patch_size)).collect();
+            let mut buffer = self.buffer_opt.as_ref().unwrap().clone();
+            let output = buffer.sub_image(offset.0, offset.1, output.size.0, output.size.1).to_image();
+            self.report_opt = Some(debug.report(&output, offset, &patches, self.params.tileable));
+        }
+        self.stats_opt = Some(usage.stats());
+    }
+
+    /// Distance between the origins of consecutive patches in each dimension.
+    fn step(&self) -> (u32, u32) {
+        (self.params.patch_size.0 - self.params.overlap.0, self.params.patch_size.1 - self.params.overlap.1)
+    }
+
     /// Synthesize an image by the image quilting algorithm.
     pub fn quilt_image(&mut self) -> Result<RgbImage> {
-        let d = self.source.dimensions();
-        try!(self.validate_params(d));
-        let (img_width, img_height) = d;
-        let step = self.params.patch_size - self.params.overlap;
+        try!(self.prepare());
+        let (passes, reduction) = (self.params.passes, self.params.reduction);
+        let block_sizes = try!(self.block_sizes(passes, reduction));
+        self.quilt_passes(&block_sizes, |_, _| ())
+    }
+
+    /// Compute the patch and overlap sizes of each pass, the block size being
+    /// multiplied by `reduction` on each pass.
+    fn block_sizes(&self, passes: u32, reduction: f64) -> Result<Vec<((u32, u32), (u32, u32))>> {
+        if passes == 0 {
+            bail!(ErrorKind::InvalidArguments("Number of passes can't be zero".to_owned()))
+        }
+        if passes > 1 && !(reduction > 0. && reduction < 1.) {
+            bail!(ErrorKind::InvalidArguments("Block size reduction factor must be between 0 and 1".to_owned()))
+        }
+        let reduce = |v: u32| (v as f64 * reduction).round() as u32;
+        let mut block_sizes = vec!((self.params.patch_size, self.params.overlap));
+        for _ in 1..passes {
+            let (patch_size, overlap) = *block_sizes.last().unwrap();
+            let patch_size = (reduce(patch_size.0), reduce(patch_size.1));
+            let overlap = (::std::cmp::max(1, reduce(overlap.0)), ::std::cmp::max(1, reduce(overlap.1)));
+            if patch_size.0 < 2 * overlap.0 || patch_size.1 < 2 * overlap.1 {
+                bail!(ErrorKind::InvalidArguments("Too many passes for the patch size".to_owned()))
+            }
+            let step = (patch_size.0 - overlap.0, patch_size.1 - overlap.1);
+            if self.params.tileable && (self.params.size.0 % step.0 != 0 || self.params.size.1 % step.1 != 0) {
+                bail!(ErrorKind::InvalidArguments("Tileable output size must be a multiple of patch size minus overlap on every pass".to_owned()))
+            }
+            block_sizes.push((patch_size, overlap));
+        }
+        Ok(block_sizes)
+    }
+
+    /// Quilt an image once with each block size. `before_pass` is called
+    /// with the index of each pass before running it.
+    fn quilt_passes<F>(&mut self, block_sizes: &[((u32, u32), (u32, u32))], mut before_pass: F) -> Result<RgbImage>
+        where F: FnMut(&mut Quilter, u32)
+    {
+        let (patch_size, overlap) = (self.params.patch_size, self.params.overlap);
+        for (pass, &(pass_patch_size, pass_overlap)) in block_sizes.iter().enumerate() {
+            self.params.patch_size = pass_patch_size;
+            self.params.overlap = pass_overlap;
+            before_pass(self, pass as u32);
+            let output = try!(self.quilt_pass());
+            self.previous_opt = Some(output);
+            if block_sizes.len() > 1 { println!("Done pass {}", pass); }
+        }
+        self.params.patch_size = patch_size;
+        self.params.overlap = overlap;
+        Ok(self.previous_opt.take().unwrap())
+    }
+
+    /// Render the target image out of the source texture, as described in
+    /// the texture transfer part of the Efros and Freeman paper. Patches are
+    /// chosen to match both their overlap area and the correspondence
+    /// quantity of the target image. The output size parameter is ignored.
+    pub fn transfer_texture(&mut self, target: &RgbImage, transfer: &TransferParams) -> Result<RgbImage> {
+        try!(self.prepare());
+        if self.params.tileable {
+            bail!(ErrorKind::InvalidArguments("Tileable mode can't be used for texture transfer".to_owned()))
+        }
+
+        // The block size is reduced by a third on each pass
+        let block_sizes = try!(self.block_sizes(transfer.passes, 2. / 3.));
+
+        let map = |img: &RgbImage| CorrespondenceMap::from_fn(img.width(), img.height(), |x, y| {
+            Luma { data: [(transfer.correspondence)(img.get_pixel(x, y))] }
+        });
+        let (source_map, target_map) = (map(&self.source), map(target));
+        let size = self.params.size;
+        self.params.size = target.dimensions();
+        let res = try!(self.quilt_passes(&block_sizes, |quilter, pass| {
+            quilter.transfer_opt = Some(Transfer { source_map: source_map.clone(), target_map: target_map.clone(),
+                                                   alpha: transfer.pass_alpha(pass) });
+        }));
+        self.transfer_opt = None;
+        self.params.size = size;
+
+        Ok(res)
+    }
+
+    /// Quilt an image of the output size with the current block size.
+    fn quilt_pass(&mut self) -> Result<RgbImage> {
+        let step = self.step();
 
         let x_patches =
-            if (self.params.size.0 % step) == 0 { self.params.size.0 / step }
-            else { self.params.size.0 / step + 1 };
+            if (self.params.size.0 % step.0) == 0 { self.params.size.0 / step.0 }
+            else { self.params.size.0 / step.0 + 1 };
         let y_patches =
-            if (self.params.size.1 % step) == 0 { self.params.size.1 / step }
-            else { self.params.size.1 / step + 1 };
-        let (buffer_width, buffer_height) = (self.params.size.0 + self.params.patch_size, self.params.size.1 + self.params.patch_size);
-        self.buffer_opt = Some(RgbImage::new(buffer_width, buffer_height));
+            if (self.params.size.1 % step.1) == 0 { self.params.size.1 / step.1 }
+            else { self.params.size.1 / step.1 + 1 };
+        // When tiling, the buffer wraps around instead of being cropped
+        let (buffer_width, buffer_height) =
+            if self.params.tileable { self.params.size }
+            else { (self.params.size.0 + self.params.patch_size.0, self.params.size.1 + self.params.patch_size.1) };
+        self.init_buffer((buffer_width, buffer_height));
+
+        // Blit the first patch. In texture transfer mode or when refining a
+        // previous pass, it has to match the target image, so it is selected
+        // like the others.
+        let random_seed = self.transfer_opt.is_none() && self.previous_opt.is_none();
+        if random_seed {
+            let mut rng = self.patch_rng((0, 0));
+            // Copies of the source image smaller than the patches are skipped
+            let (pw, ph) = self.params.patch_size;
+            let copies: Vec<&Rect> = self.copies.iter().map(|c| &c.rect).filter(|r| r.size.0 >= pw && r.size.1 >= ph).collect();
+            let copy = copies[Range::new(0, copies.len()).ind_sample(&mut rng)];
+            let patch_x_dist = Range::new(0u32, copy.size.0 - pw + 1);
+            let patch_y_dist = Range::new(0u32, copy.size.1 - ph + 1);
+            let seed = Patch { coords: if let Some(seed_coordinates) = self.params.seed_coords { seed_coordinates }
+                                       else { (copy.coords.0 + patch_x_dist.ind_sample(&mut rng),
+                                               copy.coords.1 + patch_y_dist.ind_sample(&mut rng)) },
+                               size: self.params.patch_size };
+            let mask = GrayImage::from_pixel(seed.size.0, seed.size.1, Luma { data: [255] });
+            self.place_patch(&Selection { patch: seed, mask: mask, candidates: 1, error_surface_opt: None }, (0, 0));
+        }
+
+        self.init_spectra();
+        if let PatchPlacement::Random { offsets, refinements } = self.params.placement {
+            self.quilt_random(offsets);
+            self.refine_seams(refinements);
+        } else {
+            let patches: Vec<(u32, u32)> = (0..y_patches).flat_map(|y| (0..x_patches).map(move |x| (x, y)))
+                                                        .filter(|&(x, y)| !(x == 0 && y == 0 && random_seed))
+                                                        .collect();
+            for wave in self.wavefronts(&patches, (buffer_width, buffer_height)) {
+                let selected: Vec<Selection> = wave.par_iter().map(|&(patch_x, patch_y)| {
+                    let area = patch_overlap_area((patch_x, patch_y), (x_patches, y_patches), self.params.tileable);
+                    self.select_and_cut_patch(area, (patch_x * step.0, patch_y * step.1))
+                }).collect();
+                for (&(patch_x, patch_y), selection) in wave.iter().zip(selected) {
+                    self.place_patch(&selection, (patch_x * step.0, patch_y * step.1));
+
+                    println!("Done patch ({}, {})", patch_x, patch_y);
+                }
+            }
+            // Record the placements in raster order, whatever the order of the waves
+            self.usage_opt.as_mut().unwrap().placements.sort_by_key(|&(_, b)| (b.1, b.0));
+            if let Some(ref mut debug) = self.debug_opt {
+                debug.error_surfaces.sort_by_key(|&(b, _)| (b.1, b.0));
+            }
+        }
+        let size = self.params.size;
+        if self.params.poisson_blending {
+            try!(self.poisson_blend(&Rect { coords: (0, 0), size: size }));
+        }
+
+        self.release_buffer(&Rect { coords: (0, 0), size: size });
+        let mut quilt = self.buffer_opt.take().unwrap();
+        Ok(quilt.sub_image(0, 0, size.0, size.1).to_image())
+    }
+
+    /// Cover the output image with patches, each one placed at the best of
+    /// `offsets` random offsets around the first empty pixel in raster order.
+    /// The patches are matched against all the pixels they overlap, and cut
+    /// along a minimum cut.
+    fn quilt_random(&mut self, offsets: u32) {
+        let (w, h) = self.params.size;
+        let (pw, ph) = self.params.patch_size;
+        let (ox, oy) = self.params.overlap;
+        let tileable = self.params.tileable;
+        let area = OverlapArea { whole: true, ..Default::default() };
+        let (dx_range, dy_range) = (Range::new(0, min(2 * ox, pw - 1) + 1), Range::new(0, min(2 * oy, ph - 1) + 1));
+        let mut next = 0;
+        let mut n = 0;
+        loop {
+            {
+                let state = self.state_opt.as_ref().unwrap();
+                while next < w * h && state.get_pixel(next % w, next / w).data[0] != PIXEL_EMPTY { next += 1; }
+            }
+            if next == w * h { break; }
+            let (x, y) = (next % w, next / w);
+
+            // Every offset covers the empty pixel, and overlaps the pixels
+            // before it
+            let mut rng = self.patch_rng((x, y));
+            let positions: Vec<(u32, u32)> = (0..offsets).map(|_| {
+                let (dx, dy) = (dx_range.ind_sample(&mut rng), dy_range.ind_sample(&mut rng));
+                if tileable { ((x + w - dx) % w, (y + h - dy) % h) }
+                else { (x.saturating_sub(dx), y.saturating_sub(dy)) }
+            }).collect();
+            // Overlap error of the best candidate at each offset, per known pixel
+            let errors: Vec<f64> = positions.par_iter().map(|&coords| {
+                let (candidate, _) = self.select_candidate(area, coords);
+                let known = self.known_pixels(coords);
+                if known == 0 { f64::INFINITY }
+                else { self.patch_overlap_error(area, &candidate, coords) / known as f64 }
+            }).collect();
+            let best = (0..positions.len()).fold(0, |best, i| if errors[i] < errors[best] { i } else { best });
+            let selection = self.select_and_cut_patch(area, positions[best]);
+            self.place_patch(&selection, positions[best]);
+
+            println!("Done patch {}", n);
+            n += 1;
+        }
+    }
+
+    /// Quilt `refinements` patches over the areas of the output image whose
+    /// seams cost the most, as described by Kwatra et al. Areas which overlap
+    /// a previous refinement by more than half a patch in each dimension are
+    /// skipped.
+    fn refine_seams(&mut self, refinements: u32) {
+        let (w, h) = self.params.size;
+        let (pw, ph) = self.params.patch_size;
+        let area = OverlapArea { whole: true, ..Default::default() };
+        let mut refined: Vec<(u32, u32)> = vec!();
+        for i in 0..refinements {
+            // Summed-area table of the seam costs, repeated beyond the output
+            // image when tiling so that the windows wrap around
+            let costs = self.seam_costs();
+            let (sw, sh) = ((w + pw) as usize, (h + ph) as usize);
+            let mut sat = vec![0.; (sw + 1) * (sh + 1)];
+            for y in 0..sh {
+                for x in 0..sw {
+                    let cost = if self.params.tileable { costs[(y % h as usize) * w as usize + x % w as usize] }
+                               else if x < w as usize && y < h as usize { costs[y * w as usize + x] }
+                               else { 0. };
+                    sat[(y + 1) * (sw + 1) + x + 1] = cost + sat[y * (sw + 1) + x + 1] + sat[(y + 1) * (sw + 1) + x] -
+                                                      sat[y * (sw + 1) + x];
+                }
+            }
+            let window = |x: usize, y: usize| {
+                let (x1, y1) = (x + pw as usize, y + ph as usize);
+                sat[y1 * (sw + 1) + x1] - sat[y * (sw + 1) + x1] - sat[y1 * (sw + 1) + x] + sat[y * (sw + 1) + x]
+            };
+
+            let mut best = None;
+            for y in 0..h {
+                for x in 0..w {
+                    if refined.iter().any(|&(rx, ry)| (x as i64 - rx as i64).abs() < (pw / 2) as i64 &&
+                                                      (y as i64 - ry as i64).abs() < (ph / 2) as i64) {
+                        continue;
+                    }
+                    let cost = window(x as usize, y as usize);
+                    if cost > best.map_or(0., |(c, _)| c) { best = Some((cost, (x, y))); }
+                }
+            }
+            // Stop when no seam is left to refine
+            let coords = match best { Some((_, coords)) => coords, None => break };
+            refined.push(coords);
+            let selection = self.select_and_cut_patch(area, coords);
+            self.place_patch(&selection, coords);
+
+            println!("Done refinement {}", i);
+        }
+    }
+
+    /// Compute the cost of the seams of the output image. The cost of the
+    /// seam between two neighbouring pixels copied from different source
+    /// locations is the same as in the minimum cut, and is shared between
+    /// both pixels.
+    fn seam_costs(&self) -> Vec<f64> {
+        let (w, h) = self.params.size;
+        let buffer = self.buffer_opt.as_ref().unwrap();
+        let origins = self.origin_opt.as_ref().unwrap();
+        let bw = buffer.width();
+        let dist = self.params.distance.function();
+        let mut costs = vec![0.; (w * h) as usize];
+        for y in 0..h {
+            for x in 0..w {
+                for &(dx, dy) in &[(1i64, 0i64), (0, 1)] {
+                    let (nx, ny) = (x + dx as u32, y + dy as u32);
+                    if !self.params.tileable && (nx >= w || ny >= h) { continue; }
+                    let (nx, ny) = (nx % w, ny % h);
+                    if let (Some(o1), Some(o2)) = (origins[(y * bw + x) as usize], origins[(ny * bw + nx) as usize]) {
+                        if (o1.0 as i64 + dx, o1.1 as i64 + dy) == (o2.0 as i64, o2.1 as i64) { continue; }
+                        let (old1, old2) = (buffer.get_pixel(x, y), buffer.get_pixel(nx, ny));
+                        let (a1, a2) = (self.source_at(o1, dx, dy).unwrap_or(old2), self.source_at(o2, -dx, -dy).unwrap_or(old1));
+                        let cost = (dist(old1, a2) + dist(a1, old2)) / 2.;
+                        costs[(y * w + x) as usize] += cost;
+                        costs[(ny * w + nx) as usize] += cost;
+                    }
+                }
+            }
+        }
+        costs
+    }
+
+    /// Number of pixels of the patch at the specified buffer coordinates
+    /// which are not empty.
+    fn known_pixels(&self, buf_coords: (u32, u32)) -> u32 {
+        let state = self.state_opt.as_ref().unwrap();
+        let (bw, bh) = state.dimensions();
+        let (w, h) = self.params.patch_size;
+        let mut known = 0;
+        for y in 0..h {
+            for x in 0..w {
+                if state.get_pixel((buf_coords.0 + x) % bw, (buf_coords.1 + y) % bh).data[0] != PIXEL_EMPTY { known += 1; }
+            }
+        }
+        known
+    }
+
+    /// Source pixel at the specified offset from a source location, if it
+    /// lies within the same copy of the source image.
+    fn source_at(&self, o: (u32, u32), dx: i64, dy: i64) -> Option<&Rgb<u8>> {
+        let (sw, sh) = self.source.dimensions();
+        let (x, y) = (o.0 as i64 + dx, o.1 as i64 + dy);
+        if x < 0 || y < 0 || x >= sw as i64 || y >= sh as i64 { None }
+        else if copy_index(&self.copies, o) != copy_index(&self.copies, (x as u32, y as u32)) { None }
+        else { Some(self.source.get_pixel(x as u32, y as u32)) }
+    }
+
+    /// Extend an image beyond its borders by quilting patches of the source
+    /// image around it. The original image is left untouched, and the
+    /// patches along its edges are chosen to match the pixels they overlap.
+    /// The output size parameter is ignored.
+    pub fn extend_image(&mut self, image: &RgbImage, extension: Extension) -> Result<RgbImage> {
+        try!(self.prepare());
+        if self.params.tileable {
+            bail!(ErrorKind::InvalidArguments("Tileable mode can't be used to extend an image".to_owned()))
+        }
+        let (width, height) = image.dimensions();
+        let (patch_size, overlap) = (self.params.patch_size, self.params.overlap);
+        if width < overlap.0 || height < overlap.1 {
+            bail!(ErrorKind::InvalidArguments("Image to extend must be at least as large as the overlap area".to_owned()))
+        }
+        let step = self.step();
+        let x_patches = |len: u32| (len + step.0 - 1) / step.0;
+        let y_patches = |len: u32| (len + step.1 - 1) / step.1;
+        let (out_width, out_height) = (width + extension.left + extension.right,
+                                       height + extension.top + extension.bottom);
+
+        // Leave a margin of one patch around the output, so that the patches
+        // growing outwards always fit in the buffer
+        let margin = patch_size;
+        let (buffer_width, buffer_height) = (out_width + 2 * margin.0, out_height + 2 * margin.1);
+        self.init_buffer((buffer_width, buffer_height));
+        let (ox, oy) = (margin.0 + extension.left, margin.1 + extension.top);
+        blit_rect(self.buffer_opt.as_mut().unwrap(), image,
+                  &Rect { coords: (0, 0), size: (width, height) }, (ox, oy));
+        self.set_state((ox, oy), (width, height), PIXEL_FIXED);
+
+        // Grow columns of patches on the left and right of the image, then
+        // rows of patches spanning the whole output width above and below it.
+        for i in 0..x_patches(extension.right) {
+            for j in 0..y_patches(height) {
+                let area = OverlapArea { left: true, top: j != 0, ..Default::default() };
+                self.quilt_patch(area, (ox + width - overlap.0 + i * step.0, oy + j * step.1));
+            }
+        }
+        for i in 0..x_patches(extension.left) {
+            for j in 0..y_patches(height) {
+                let area = OverlapArea { right: true, top: j != 0, ..Default::default() };
+                self.quilt_patch(area, (ox + overlap.0 - patch_size.0 - i * step.0, oy + j * step.1));
+            }
+        }
+        for j in 0..y_patches(extension.top) {
+            for i in 0..x_patches(out_width) {
+                let area = OverlapArea { bottom: true, left: i != 0, ..Default::default() };
+                self.quilt_patch(area, (margin.0 + i * step.0, oy + overlap.1 - patch_size.1 - j * step.1));
+            }
+        }
+        for j in 0..y_patches(extension.bottom) {
+            for i in 0..x_patches(out_width) {
+                let area = OverlapArea { top: true, left: i != 0, ..Default::default() };
+                self.quilt_patch(area, (margin.0 + i * step.0, oy + height - overlap.1 + j * step.1));
+            }
+        }
+        if self.params.poisson_blending {
+            try!(self.poisson_blend(&Rect { coords: margin, size: (out_width, out_height) }));
+        }
+
+        self.release_buffer(&Rect { coords: margin, size: (out_width, out_height) });
+        let mut quilt = self.buffer_opt.take().unwrap();
+        Ok(quilt.sub_image(margin.0, margin.1, out_width, out_height).to_image())
+    }
+
+    /// Fill the holes of an image by quilting patches of the source image
+    /// into them. Non zero pixels of `hole_mask` are synthesized, the others
+    /// are left untouched. The patches are chosen to match all the known
+    /// pixels they cover, and are always cut along a minimum cut around them.
+    ///
+    /// The source image shouldn't contain the holes themselves. The output
+    /// size parameter is ignored.
+    pub fn fill_holes(&mut self, image: &RgbImage, hole_mask: &GrayImage) -> Result<RgbImage> {
+        try!(self.prepare());
+        if self.params.tileable {
+            bail!(ErrorKind::InvalidArguments("Tileable mode can't be used to fill holes".to_owned()))
+        }
+        if image.dimensions() != hole_mask.dimensions() {
+            bail!(ErrorKind::InvalidArguments("Hole mask and image dimensions differ".to_owned()))
+        }
+        let (width, height) = image.dimensions();
+        let (patch_size, overlap) = (self.params.patch_size, self.params.overlap);
+        let step = self.step();
+
+        // Bounding box of the holes
+        let holes: Vec<(u32, u32)> = hole_mask.enumerate_pixels().filter(|&(_, _, p)| p.data[0] != 0)
+                                              .map(|(x, y, _)| (x, y)).collect();
+        if holes.is_empty() {
+            return Ok(image.clone());
+        }
+        let (x_min, y_min) = (holes.iter().map(|h| h.0).min().unwrap(), holes.iter().map(|h| h.1).min().unwrap());
+        let (x_max, y_max) = (holes.iter().map(|h| h.0).max().unwrap(), holes.iter().map(|h| h.1).max().unwrap());
+
+        // Leave a margin of one patch around the image, so that the patches
+        // along its edges fit in the buffer
+        let margin = patch_size;
+        self.init_buffer((width + 2 * margin.0, height + 2 * margin.1));
+        blit_rect(self.buffer_opt.as_mut().unwrap(), image,
+                  &Rect { coords: (0, 0), size: (width, height) }, margin);
+        self.set_state(margin, (width, height), PIXEL_FIXED);
+        for &(x, y) in &holes {
+            self.state_opt.as_mut().unwrap().put_pixel(margin.0 + x, margin.1 + y, Luma { data: [PIXEL_EMPTY] });
+        }
+
+        // Cover the bounding box with patches overlapping the known pixels on
+        // its top and left sides. Patches only covering known or already
+        // quilted pixels are skipped.
+        let origin = (margin.0 + x_min - overlap.0, margin.1 + y_min - overlap.1);
+        let x_patches = (x_max - x_min + overlap.0 + step.0) / step.0;
+        let y_patches = (y_max - y_min + overlap.1 + step.1) / step.1;
+        let area = OverlapArea { whole: true, ..Default::default() };
+        for patch_y in 0..y_patches {
+            for patch_x in 0..x_patches {
+                let buf_coords = (origin.0 + patch_x * step.0, origin.1 + patch_y * step.1);
+                if !self.has_empty(buf_coords) { continue; }
+                self.quilt_patch(area, buf_coords);
+
+                println!("Done patch ({}, {})", patch_x, patch_y);
+            }
+        }
+        if self.params.poisson_blending {
+            try!(self.poisson_blend(&Rect { coords: margin, size: (width, height) }));
+        }
+
+        self.release_buffer(&Rect { coords: margin, size: (width, height) });
+        let mut quilt = self.buffer_opt.take().unwrap();
+        Ok(quilt.sub_image(margin.0, margin.1, width, height).to_image())
+    }
+
+    /// Paint a stroke on a canvas with the source texture. The stroke follows
+    /// the polyline through `points`, in canvas coordinates, and covers the
+    /// pixels within `width / 2` of it. The patches are placed along the
+    /// stroke, in as many lanes as needed to cover its width, and taken from
+    /// the orientation of the source image closest to the direction of the
+    /// stroke, see `QuilterParams::orientations`.
+    ///
+    /// The patches are chosen to match the canvas around the stroke, and are
+    /// always cut along a minimum cut. Pixels of the canvas within the
+    /// overlap size of the stroke may be replaced so that it blends into the
+    /// canvas, the others are left untouched. The output size parameter is
+    /// ignored.
+    pub fn brush(&mut self, canvas: &RgbImage, points: &[(f64, f64)], width: f64) -> Result<RgbImage> {
+        try!(self.prepare());
+        if self.params.tileable {
+            bail!(ErrorKind::InvalidArguments("Tileable mode can't be used to paint strokes".to_owned()))
+        }
+        if points.is_empty() || !(width > 0.) {
+            bail!(ErrorKind::InvalidArguments("A stroke needs at least one point and a positive width".to_owned()))
+        }
+        let (cw, ch) = canvas.dimensions();
+        let (patch_size, overlap) = (self.params.patch_size, self.params.overlap);
+        let step = self.step();
+        let step = min(step.0, step.1) as f64;
+        let (radius, band) = (width / 2., ::std::cmp::max(overlap.0, overlap.1) as f64);
+
+        // A single point is painted as a dab
+        let segments: Vec<((f64, f64), (f64, f64))> = if points.len() == 1 { vec!((points[0], points[0])) }
+                                                      else { points.windows(2).map(|p| (p[0], p[1])).collect() };
+        // Distance of a point to the stroke, and direction of the nearest segment
+        let nearest = |(x, y): (f64, f64)| {
+            segments.iter().map(|&((x0, y0), (x1, y1))| {
+                let (dx, dy) = (x1 - x0, y1 - y0);
+                let length2 = dx * dx + dy * dy;
+                let t = if length2 > 0. { (((x - x0) * dx + (y - y0) * dy) / length2).max(0.).min(1.) } else { 0. };
+                ((x - x0 - t * dx).hypot(y - y0 - t * dy), dy.atan2(dx))
+            }).fold((f64::INFINITY, 0.), |best, d| if d.0 < best.0 { d } else { best })
+        };
+        // Angle of the orientation of the source image closest to a direction,
+        // among those with a copy which can hold a patch. There is at least
+        // one, see `validate_params`.
+        let angles: Vec<f64> = self.copies.iter().filter(|c| patch_size.0 <= c.rect.size.0 && patch_size.1 <= c.rect.size.1)
+                                                 .map(|c| c.angle).collect();
+        let orientation = |angle: f64| {
+            let turn = 2. * f64::consts::PI;
+            let gap = |a: f64| { let d = ((a - angle) % turn + turn) % turn; d.min(turn - d) };
+            angles.iter().fold(angles[0], |best, &a| if gap(a) < gap(best) { a } else { best })
+        };
+
+        // Leave a margin of one patch around the canvas, so that the patches
+        // along its edges fit in the buffer. Pixels of the stroke are empty,
+        // and pixels around it may be replaced.
+        let margin = patch_size;
+        self.init_buffer((cw + 2 * margin.0, ch + 2 * margin.1));
+        blit_rect(self.buffer_opt.as_mut().unwrap(), canvas, &Rect { coords: (0, 0), size: (cw, ch) }, margin);
+        self.set_state(margin, (cw, ch), PIXEL_FIXED);
+        let reach = radius + band;
+        let x_range = ((points.iter().map(|p| p.0).fold(f64::INFINITY, f64::min) - reach).floor().max(0.) as u32,
+                       (points.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max) + reach).ceil().max(0.).min(cw as f64) as u32);
+        let y_range = ((points.iter().map(|p| p.1).fold(f64::INFINITY, f64::min) - reach).floor().max(0.) as u32,
+                       (points.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max) + reach).ceil().max(0.).min(ch as f64) as u32);
+        let mut stroke = vec!();
+        for y in y_range.0..y_range.1 {
+            for x in x_range.0..x_range.1 {
+                let d = nearest((x as f64, y as f64)).0;
+                let state = if d <= radius { stroke.push((x, y)); PIXEL_EMPTY }
+                            else if d <= reach { PIXEL_QUILTED }
+                            else { continue };
+                self.state_opt.as_mut().unwrap().put_pixel(margin.0 + x, margin.1 + y, Luma { data: [state] });
+            }
+        }
+
+        // Centers of the patches along each segment, in lanes across it
+        let lanes = (width / step).ceil().max(1.) as u32;
+        let mut centers = vec!();
+        for &((x0, y0), (x1, y1)) in &segments {
+            let (dx, dy) = (x1 - x0, y1 - y0);
+            let length = dx.hypot(dy);
+            let (nx, ny) = if length > 0. { (-dy / length, dx / length) } else { (0., 1.) };
+            let steps = (length / step).ceil() as u32;
+            for i in 0..steps + 1 {
+                let t = if steps == 0 { 0. } else { i as f64 / steps as f64 };
+                for lane in 0..lanes {
+                    let offset = (lane as f64 - (lanes - 1) as f64 / 2.) * step;
+                    centers.push(((x0 + t * dx + offset * nx, y0 + t * dy + offset * ny), dy.atan2(dx)));
+                }
+            }
+        }
+        let buf_coords = |(x, y): (f64, f64)| {
+            (((x - patch_size.0 as f64 / 2.).round() + margin.0 as f64).max(0.).min((cw + margin.0) as f64) as u32,
+             ((y - patch_size.1 as f64 / 2.).round() + margin.1 as f64).max(0.).min((ch + margin.1) as f64) as u32)
+        };
+
+        let area = OverlapArea { whole: true, ..Default::default() };
+        for (i, &(center, angle)) in centers.iter().enumerate() {
+            let coords = buf_coords(center);
+            if !self.has_empty(coords) { continue; }
+            self.stroke_angle_opt = Some(orientation(angle));
+            self.quilt_patch(area, coords);
+
+            println!("Done patch {}", i);
+        }
+        // Cover the pixels of the stroke left empty between the lanes
+        for &(x, y) in &stroke {
+            if self.state_opt.as_ref().unwrap().get_pixel(margin.0 + x, margin.1 + y).data[0] != PIXEL_EMPTY { continue; }
+            let (x, y) = (x as f64, y as f64);
+            self.stroke_angle_opt = Some(orientation(nearest((x, y)).1));
+            self.quilt_patch(area, buf_coords((x, y)));
+        }
+        self.stroke_angle_opt = None;
+        if self.params.poisson_blending {
+            try!(self.poisson_blend(&Rect { coords: margin, size: (cw, ch) }));
+        }
+
+        self.release_buffer(&Rect { coords: margin, size: (cw, ch) });
+        let mut painted = self.buffer_opt.take().unwrap();
+        Ok(painted.sub_image(margin.0, margin.1, cw, ch).to_image())
+    }
+
+    /// Check whether the patch at the specified buffer coordinates covers
+    /// empty pixels.
+    fn has_empty(&self, buf_coords: (u32, u32)) -> bool {
+        let state = self.state_opt.as_ref().unwrap();
+        let (w, h) = self.params.patch_size;
+        (0..h).any(|y| (0..w).any(|x| state.get_pixel(buf_coords.0 + x, buf_coords.1 + y).data[0] == PIXEL_EMPTY))
+    }
+
+    /// Blend the specified rectangle of the buffer in the gradient domain.
+    ///
+    /// The guidance gradient between two neighbouring pixels is the gradient
+    /// of the source image at the origin of the pixels. Across a seam, the
+    /// pixels come from different places of the source image, and the guidance
+    /// gradient is the average of the gradients of the source image on both
+    /// sides of the seam. Fixed pixels keep their values. Fails if the solver
+    /// does not converge.
+    fn poisson_blend(&mut self, rect: &Rect) -> Result<()> {
+        let (w, h) = rect.size;
+        let buffer = self.buffer_opt.as_mut().unwrap();
+        let state = self.state_opt.as_ref().unwrap();
+        let origins = self.origin_opt.as_ref().unwrap();
+        let (bw, bh) = buffer.dimensions();
+        let copies = &self.copies;
+        let same_copy = |a, b| copy_index(copies, a).is_some() && copy_index(copies, a) == copy_index(copies, b);
+        let buf = |x: u32, y: u32| ((rect.coords.0 + x) % bw, (rect.coords.1 + y) % bh);
+        let origin = |x: u32, y: u32| { let (bx, by) = buf(x, y); origins[(by * bw + bx) as usize] };
+        let source = &self.source;
+        let src = |(x, y): (u32, u32), c: usize| source.get_pixel(x, y).data[c] as f64;
+
+        let mut init = FloatImage::new(w, h, 3);
+        let mut fixed = vec![false; (w * h) as usize];
+        for y in 0..h {
+            for x in 0..w {
+                let (bx, by) = buf(x, y);
+                for c in 0..3 {
+                    init.set(x, y, c, buffer.get_pixel(bx, by).data[c] as f64);
+                }
+                fixed[(y * w + x) as usize] = state.get_pixel(bx, by).data[0] != PIXEL_QUILTED;
+            }
+        }
+
+        // Guidance gradient from the pixel (x, y) to its neighbour at offset (dx, dy)
+        let guidance = |x: u32, y: u32, (dx, dy): (u32, u32), c: usize| -> f64 {
+            let (nx, ny) = ((x + dx) % w, (y + dy) % h);
+            let mut gradients = vec!();
+            if let Some((ox, oy)) = origin(x, y) {
+                if same_copy((ox, oy), (ox + dx, oy + dy)) {
+                    gradients.push(src((ox + dx, oy + dy), c) - src((ox, oy), c));
+                }
+            }
+            if let Some((ox, oy)) = origin(nx, ny) {
+                if ox >= dx && oy >= dy && same_copy((ox - dx, oy - dy), (ox, oy)) {
+                    gradients.push(src((ox, oy), c) - src((ox - dx, oy - dy), c));
+                }
+            }
+            if gradients.is_empty() { init.get(nx, ny, c) - init.get(x, y, c) }
+            else { gradients.iter().sum::<f64>() / gradients.len() as f64 }
+        };
+        let (mut grad_x, mut grad_y) = (FloatImage::new(w, h, 3), FloatImage::new(w, h, 3));
+        for y in 0..h {
+            for x in 0..w {
+                for c in 0..3 {
+                    grad_x.set(x, y, c, guidance(x, y, (1, 0), c));
+                    grad_y.set(x, y, c, guidance(x, y, (0, 1), c));
+                }
+            }
+        }
+
+        let res = try!(poisson::solve(&init, &grad_x, &grad_y, &fixed, self.params.tileable));
+        for y in 0..h {
+            for x in 0..w {
+                let (bx, by) = buf(x, y);
+                let mut pixel = *buffer.get_pixel(bx, by);
+                for c in 0..3 {
+                    pixel.data[c] = res.get(x, y, c).round().max(0.).min(255.) as u8;
+                }
+                buffer.put_pixel(bx, by, pixel);
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the state of a rectangle of buffer pixels.
+    fn set_state(&mut self, coords: (u32, u32), size: (u32, u32), state: u8) {
+        let state_map = self.state_opt.as_mut().unwrap();
+        for y in coords.1..coords.1 + size.1 {
+            for x in coords.0..coords.0 + size.0 {
+                state_map.put_pixel(x, y, Luma { data: [state] });
+            }
+        }
+    }
+
+    /// Group the patches at the specified positions, given in raster order,
+    /// into waves of patches which can be quilted concurrently.
+    ///
+    /// A patch comes after all the previous patches whose footprint, grown by
+    /// one pixel for the seam computation, intersects its own, so that the
+    /// result is the same as when quilting the patches in raster order.
+    /// Repetition constraints depend on all the previous patches, which are
+    /// then quilted one at a time.
+    fn wavefronts(&self, patches: &[(u32, u32)], buffer_size: (u32, u32)) -> Vec<Vec<(u32, u32)>> {
+        let sequential = !self.params.wavefront || self.params.repetition_penalty > 0. ||
+                         self.params.min_repeat_distance > 0;
+        let (size, step, tileable) = (self.params.patch_size, self.step(), self.params.tileable);
+        // Whether two patches starting at `a` and `b` intersect along an axis
+        let intersect = |a: u32, b: u32, len: u32, modulus: u32| {
+            if tileable { len + 1 >= modulus || (a + modulus - b) % modulus <= len || (b + modulus - a) % modulus <= len }
+            else { (a as i64 - b as i64).abs() <= len as i64 }
+        };
+
+        let mut waves: Vec<Vec<(u32, u32)>> = vec!();
+        let mut patch_waves = Vec::with_capacity(patches.len());
+        for (i, &(x, y)) in patches.iter().enumerate() {
+            let wave = if sequential { i } else {
+                patches[..i].iter().zip(&patch_waves).filter(|&(&(px, py), _)| {
+                    intersect(px * step.0, x * step.0, size.0, buffer_size.0) &&
+                    intersect(py * step.1, y * step.1, size.1, buffer_size.1)
+                }).map(|(_, &w)| w + 1).max().unwrap_or(0)
+            };
+            patch_waves.push(wave);
+            if wave == waves.len() { waves.push(vec!()); }
+            waves[wave].push((x, y));
+        }
+        waves
+    }
+
+    /// Compute the spectra of the source image if the fast candidate search
+    /// needs them.
+    fn init_spectra(&mut self) {
+        if self.fast_search() && self.spectra_opt.is_none() {
+            self.spectra_opt = Some(SourceSpectra::new(&self.source));
+        }
+    }
+
+    /// Select a patch matching the specified overlap area at the specified
+    /// buffer coordinates, and quilt it into the buffer.
+    fn quilt_patch(&mut self, area: OverlapArea, buf_coords: (u32, u32)) {
+        self.init_spectra();
+        let selection = self.select_and_cut_patch(area, buf_coords);
+        self.place_patch(&selection, buf_coords);
+    }
+
+    /// Quilt a selected patch into the buffer, recording it in the debug data.
+    fn place_patch(&mut self, selection: &Selection, buf_coords: (u32, u32)) {
+        self.record_debug(selection, buf_coords);
+        self.cut_and_blit_patch(&selection.patch, buf_coords, &selection.mask);
+    }
+
+    /// Record the seams and the number of candidates of a patch about to be
+    /// quilted, if debugging.
+    fn record_debug(&mut self, selection: &Selection, buf_coords: (u32, u32)) {
+        let debug = match self.debug_opt { Some(ref mut debug) => debug, None => return };
+        let state = self.state_opt.as_ref().unwrap();
+        let (bw, bh) = state.dimensions();
+        let (width, height) = selection.mask.dimensions();
+        let tileable = self.params.tileable;
+        // State of the pixel at the specified patch coordinates, which may be
+        // outside of the patch
+        let pixel_state = |x: i64, y: i64| {
+            let (ax, ay) = (buf_coords.0 as i64 + x, buf_coords.1 as i64 + y);
+            if !tileable && (ax < 0 || ay < 0 || ax >= bw as i64 || ay >= bh as i64) { return PIXEL_EMPTY; }
+            let (bx, by) = (((ax % bw as i64) + bw as i64) % bw as i64, ((ay % bh as i64) + bh as i64) % bh as i64);
+            state.get_pixel(bx as u32, by as u32).data[0]
+        };
+        // Whether the pixel at the specified patch coordinates takes the
+        // value of the patch
+        let copied = |x: i64, y: i64| {
+            if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 { return false; }
+            match pixel_state(x, y) {
+                PIXEL_EMPTY => true,
+                PIXEL_FIXED => false,
+                _ => selection.mask.get_pixel(x as u32, y as u32).data[0] != 0
+            }
+        };
+
+        // Copied pixels next to pixels keeping their previous value lie on a
+        // seam, and the seams they replace disappear
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                if !copied(x, y) { continue; }
+                let on_seam = [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().any(|&(dx, dy)| {
+                    !copied(x + dx, y + dy) && pixel_state(x + dx, y + dy) != PIXEL_EMPTY
+                });
+                let i = debug.index((buf_coords.0 + x as u32) % bw, (buf_coords.1 + y as u32) % bh);
+                debug.seams[i] = on_seam;
+                debug.candidates[i] = selection.candidates;
+            }
+        }
+        let surface = match selection.error_surface_opt {
+            Some(ref surface) => error_surface_image(surface),
+            None => GrayImage::new(width, height)
+        };
+        debug.error_surfaces.push((buf_coords, surface));
+    }
+
+    /// Select a patch matching the specified overlap area at the specified
+    /// buffer coordinates, and compute the mask of its pixels to be copied.
+    fn select_and_cut_patch(&self, area: OverlapArea, buf_coords: (u32, u32)) -> Selection {
+        let (candidate, candidates) = self.select_candidate(area, buf_coords);
+        // Minimum error boundaries can only be cut along the sides of a patch
+        let seam = if area.whole { SeamMethod::GraphCut } else { self.params.seam };
+        let err_surf_opt = if seam == SeamMethod::MinimumErrorBoundary || self.params.debug {
+            Some(self.patch_error_surface(area, &candidate, buf_coords))
+        } else { None };
+        let mask = match seam {
+            SeamMethod::MinimumErrorBoundary => self.patch_cut_mask(err_surf_opt.as_ref().unwrap(), area),
+            SeamMethod::GraphCut => self.graph_cut_mask(&candidate, buf_coords)
+        };
+        Selection { patch: candidate, mask: mask, candidates: candidates,
+                    error_surface_opt: if self.params.debug { err_surf_opt } else { None } }
+    }
 
-        // Blit the first patch
-        let mut rng = thread_rng();
-        let patch_x_dist = Range::new(0u32, img_width - self.params.patch_size);
-        let patch_y_dist = Range::new(0u32, img_height - self.params.patch_size);
-        blit_rect(self.buffer_opt.as_mut().unwrap(), &self.source,
-                   &Rect { coords: if let Some(seed_coordinates) = self.params.seed_coords { seed_coordinates }
-                                   else { (patch_x_dist.ind_sample(&mut rng), patch_y_dist.ind_sample(&mut rng)) },
-                            size: (self.params.patch_size, self.params.patch_size) },
-                   (0u32, 0u32));
+    /// Compute the error of the specified patch at the specified buffer
+    /// coordinates. When refining the output of a previous pass, the error
+    /// of the whole patch against that output is added to the overlap error.
+    /// In texture transfer mode, this is then weighted against the
+    /// correspondence error.
+    fn patch_error(&self, area: OverlapArea, patch: &Patch, buf_coords: (u32, u32)) -> f64 {
+        let overlap_error = self.patch_overlap_error(area, patch, buf_coords);
+        if self.transfer_opt.is_none() && self.previous_opt.is_none() {
+            return overlap_error;
+        }
 
-        for patch_y in 0..y_patches {
-            for patch_x in 0..x_patches {
-                if patch_x == 0 && patch_y == 0 { continue };
-                let area = patch_overlap_area((patch_x, patch_y));
-                let corner = (patch_x * step, patch_y * step);
-                let candidate = self.select_candidate(area, corner);
-                let err_surf = self.patch_error_surface(area, &candidate, corner);
-                self.cut_and_blit_patch(&candidate, corner, &err_surf, area);
+        let (size, overlap) = (patch.size, self.params.overlap);
+        let overlap_pixels: u32 = area.rects(size, overlap).iter().map(|r| r.size.0 * r.size.1).sum();
+        let n_pixels = (size.0 * size.1) as f64;
+        let mut block_error = if overlap_pixels == 0 { 0. } else { overlap_error / overlap_pixels as f64 };
+        // Coordinates in the output image, which is smaller than the buffer
+        // unless tiling
+        let (w, h) = self.params.size;
+        let output_coords = |x: u32, y: u32| {
+            if self.params.tileable { ((buf_coords.0 + x) % w, (buf_coords.1 + y) % h) }
+            else { (min(buf_coords.0 + x, w - 1), min(buf_coords.1 + y, h - 1)) }
+        };
 
-                println!("Done patch ({}, {})", patch_x, patch_y);
+        // Compare the whole patch against the output of the previous pass
+        if let Some(ref previous) = self.previous_opt {
+            let mut previous_error = 0.;
+            for y in 0..size.1 {
+                for x in 0..size.0 {
+                    let (px, py) = output_coords(x, y);
+                    previous_error += (self.params.distance.function())(self.source.get_pixel(patch.coords.0 + x, patch.coords.1 + y),
+                                                                  previous.get_pixel(px, py));
+                }
             }
+            block_error += previous_error / n_pixels;
         }
 
-        let mut quilt = self.buffer_opt.take().unwrap();
-        Ok(quilt.sub_image(0, 0, self.params.size.0, self.params.size.1).to_image())
+        match self.transfer_opt {
+            Some(ref transfer) => {
+                // Compare the whole patch against the target correspondence
+                // quantity
+                let mut correspondence_error = 0.;
+                for y in 0..size.1 {
+                    for x in 0..size.0 {
+                        let (tx, ty) = output_coords(x, y);
+                        correspondence_error += (transfer.source_map.get_pixel(patch.coords.0 + x, patch.coords.1 + y).data[0] -
+                                                 transfer.target_map.get_pixel(tx, ty).data[0]).abs();
+                    }
+                }
+                transfer.alpha * block_error + (1. - transfer.alpha) * correspondence_error / n_pixels
+            },
+            None => block_error
+        }
     }
 
     /// Compute the error between the specified overlap area of the specified
     /// patch and the buffer.
-    fn patch_error(&self, area: OverlapArea, patch: &Patch, buf_coords: (u32, u32)) -> f64 {
+    fn patch_overlap_error(&self, area: OverlapArea, patch: &Patch, buf_coords: (u32, u32)) -> f64 {
         let buffer = self.buffer_opt.as_ref().unwrap();
-        match area {
-            OverlapArea::Top => {
-                patch_rect_error(self.params.distance_func, &self.source,
-                                 buffer, patch.coords, buf_coords,
-                                 (self.params.overlap, patch.size))
-            }
-            OverlapArea::Left => {
-                patch_rect_error(self.params.distance_func, &self.source,
-                                 buffer, patch.coords, buf_coords,
-                                 (patch.size, self.params.overlap))
-            },
-            OverlapArea::TopLeft => {
-                patch_rect_error(self.params.distance_func, &self.source,
-                                 buffer, patch.coords, buf_coords,
-                                 (patch.size, self.params.overlap)) +
-                patch_rect_error(self.params.distance_func, &self.source,
-                                 buffer,
-                                 (patch.coords.0, patch.coords.1 + self.params.overlap),
-                                 (buf_coords.0, buf_coords.1 + self.params.overlap),
-                                 (self.params.overlap, patch.size - self.params.overlap))
-            },
+        let (size, overlap) = (patch.size, self.params.overlap);
+        area.rects(size, overlap).into_iter().map(|rect| {
+            let (x, y) = rect.coords;
+            patch_rect_error(self.params.distance.function(), &self.source, buffer, self.state_opt.as_ref(),
+                             (patch.coords.0 + x, patch.coords.1 + y),
+                             (buf_coords.0 + x, buf_coords.1 + y), rect.size)
+        }).sum()
+    }
+
+    /// Whether the overlap errors of the candidate patches can be computed
+    /// all at once, which is the case for the squared L2 distance outside of
+    /// texture transfer mode and refinement passes.
+    fn fast_search(&self) -> bool {
+        match self.params.distance {
+            Distance::L2Squared => self.transfer_opt.is_none() && self.previous_opt.is_none(),
+            _ => false
+        }
+    }
+
+    /// Compute the overlap error of every candidate patch at the specified
+    /// buffer coordinates, in row-major order of the candidate positions.
+    ///
+    /// The sum of squared differences between a candidate and the buffer
+    /// expands into the sum of the squared candidate pixels, minus twice
+    /// their correlation with the buffer, plus the sum of the squared buffer
+    /// pixels. The correlation is computed for all candidates by FFT, and the
+    /// first term by a summed-area table, or also by FFT when some pixels of
+    /// the overlap area are empty.
+    fn candidate_errors(&self, area: OverlapArea, buf_coords: (u32, u32)) -> Vec<f64> {
+        let spectra = self.spectra_opt.as_ref().unwrap();
+        let buffer = self.buffer_opt.as_ref().unwrap();
+        let state = self.state_opt.as_ref().unwrap();
+        let (size, overlap) = (self.params.patch_size, self.params.overlap);
+        let (w, h) = self.source.dimensions();
+
+        // Mask of the compared pixels, and buffer pixels under them
+        let n = (size.0 * size.1) as usize;
+        let mut mask = vec![0.; n];
+        let mut template = vec![vec![0.; n]; 3];
+        let mut buffer_norms = 0.;
+        let mut complete = true;
+        for y in 0..size.1 {
+            for x in 0..size.0 {
+                if !area.contains((x, y), size, overlap) { continue; }
+                let (bx, by) = ((buf_coords.0 + x) % buffer.width(), (buf_coords.1 + y) % buffer.height());
+                if state.get_pixel(bx, by).data[0] == PIXEL_EMPTY {
+                    complete = false;
+                    continue;
+                }
+                let i = (y * size.0 + x) as usize;
+                mask[i] = 1.;
+                for c in 0..3 {
+                    let v = buffer.get_pixel(bx, by).data[c] as f64;
+                    template[c][i] = v;
+                    buffer_norms += v * v;
+                }
+            }
+        }
+
+        let mut acc = vec![Complex::new(0., 0.); spectra.padded.0 * spectra.padded.1];
+        for c in 0..3 {
+            let kernel = fft::forward(&template[c], size, spectra.padded);
+            fft::accumulate_correlation(&mut acc, &spectra.channels[c], &kernel, -2.);
+        }
+        if !complete {
+            let kernel = fft::forward(&mask, size, spectra.padded);
+            fft::accumulate_correlation(&mut acc, &spectra.norms, &kernel, 1.);
         }
+        let correlation = fft::inverse(acc, spectra.padded);
+
+        let rects = area.rects(size, overlap);
+        let (max_x, max_y) = (w - size.0, h - size.1);
+        let mut errors = Vec::with_capacity(((max_x + 1) * (max_y + 1)) as usize);
+        for v in 0..max_y + 1 {
+            for u in 0..max_x + 1 {
+                let mut error = correlation[v as usize * spectra.padded.0 + u as usize] + buffer_norms;
+                if complete {
+                    for rect in &rects {
+                        error += spectra.norms_sum((u + rect.coords.0, v + rect.coords.1), rect.size);
+                    }
+                }
+                // Rounding errors may make perfect matches slightly negative
+                errors.push(error.max(0.));
+            }
+        }
+        errors
     }
 
     /// Find a candidate patch to be quilted at the specified coordinates on
-    /// the buffer.
-    fn select_candidate(&self, area: OverlapArea, buf_coords: (u32, u32)) -> Patch
+    /// the buffer, along with the number of candidates it was chosen among.
+    fn select_candidate(&self, area: OverlapArea, buf_coords: (u32, u32)) -> (Patch, usize)
     {
         const TOLERANCE: f64 = 0.1;
         let (w, h) = self.source.dimensions();
-        let (max_x, max_y) = (w - self.params.patch_size, h - self.params.patch_size);
+        let (max_x, max_y) = (w - self.params.patch_size.0, h - self.params.patch_size.1);
         let candidates_scores = Mutex::new(vec!());
         let current_best = Mutex::new(::std::f64::INFINITY);
-        let mut rng = thread_rng();
+        let mut rng = self.patch_rng(buf_coords);
+        let errors_opt = if self.fast_search() { Some(self.candidate_errors(area, buf_coords)) } else { None };
+        let valid = |x, y| self.candidate_valid((x, y));
+        let usage = self.usage_opt.as_ref().unwrap();
+        let (penalty, min_distance) = (self.params.repetition_penalty, self.params.min_repeat_distance);
+        let scale_penalty = self.params.scale_penalty;
+        let counts_sat_opt = if penalty > 0. { Some(usage.counts_sat()) } else { None };
+        // Patches placed too close to a previous placement of the same source
+        // patch are only selected if no other candidate is available
+        let error = |p: &Patch| {
+            if min_distance > 0 && usage.placed_near(self.source_patch(p).coords, buf_coords, min_distance) {
+                return f64::MAX;
+            }
+            let error = match errors_opt {
+                Some(ref errors) => errors[(p.coords.1 * (max_x + 1) + p.coords.0) as usize],
+                None => self.patch_error(area, p, buf_coords)
+            };
+            let error = match counts_sat_opt {
+                Some(ref sat) => error * (1. + penalty * usage.reuse(sat, &self.source_patch(p))),
+                None => error
+            };
+            if scale_penalty > 0. {
+                let scale = self.copies[copy_index(&self.copies, p.coords).unwrap()].scale;
+                error * (1. + scale_penalty * (scale - 1.).abs())
+            }
+            else { error }
+        };
         if let Some(chance) = self.params.selection_chance {
             let mut scores = candidates_scores.lock().unwrap();
             let mut best = current_best.lock().unwrap();
@@ -240,10 +2041,10 @@ impl Quilter {
                 for y in 0..max_y + 1 {
                     for x in 0..max_x + 1 {
                         let Closed01(d) = Closed01::<f64>::rand(&mut rng);
-                        if d > chance {
+                        if d <= chance && valid(x, y) {
                             let p = Patch { coords: (x, y), size: self.params.patch_size };
-                            let error = self.patch_error(area, &p, buf_coords);
-                            if error < *best * (1. + TOLERANCE) {
+                            let error = error(&p);
+                            if scores.is_empty() || error < *best * (1. + TOLERANCE) {
                                 *best = if error < *best { error } else { *best };
                                 scores.push((p, error));
                             }
@@ -255,11 +2056,12 @@ impl Quilter {
         else {
             (0..max_y + 1).into_par_iter().for_each(|y| {
                 for x in 0..max_x + 1 {
+                    if !valid(x, y) { continue; }
                     let p = Patch { coords: (x, y), size: self.params.patch_size };
-                    let error = self.patch_error(area, &p, buf_coords);
+                    let error = error(&p);
                     let mut best = current_best.lock().unwrap();
                     let mut scores = candidates_scores.lock().unwrap();
-                    if error < *best * (1. + TOLERANCE) {
+                    if scores.is_empty() || error < *best * (1. + TOLERANCE) {
                         *best = if error < *best { error } else { *best };
                         scores.push((p, error));
                     }
@@ -270,49 +2072,29 @@ impl Quilter {
         let best = current_best.lock().unwrap();
         let mut candidates: Vec<Patch> = scores.into_iter().filter_map(|(p, err)| if err > *best * (1. + TOLERANCE) { None } else { Some(p.clone()) }).collect();
         println!("Found {} candidates", candidates.len());
+        // The candidates are found in any order by the parallel search
+        candidates.sort_by_key(|p| p.coords);
         rng.shuffle(&mut candidates);
-        candidates.first().unwrap().clone()
+        // There is always a candidate, as some copy of the source image can
+        // hold a patch, see `validate_params`
+        (candidates.first().unwrap().clone(), candidates.len())
     }
 
     /// Compute the error surface of the specified patch.
     fn patch_error_surface(&self, area: OverlapArea, patch: &Patch, buf_coords: (u32, u32)) -> ErrorSurface {
-        let mut err_surf = ErrorSurface::new(self.params.patch_size, self.params.patch_size);
+        let mut err_surf = ErrorSurface::new(self.params.patch_size.0, self.params.patch_size.1);
         let (xs, ys) = buf_coords;
         let (px, py) = patch.coords;
-        let dist = self.params.distance_func;
-        match area {
-            OverlapArea::Top => {
-                for x in 0..self.params.patch_size {
-                    for y in 0..self.params.overlap {
-                        let err = dist(self.source.get_pixel(px + x, py + y),
-                                       self.buffer_opt.as_ref().unwrap().get_pixel(xs + x, ys + x));
-                        err_surf.put_pixel(x, y, Luma { data: [err] });
-                    }
-                }
-            },
-            OverlapArea::Left => {
-                for x in 0..self.params.overlap {
-                    for y in 0..self.params.patch_size {
-                        let err = dist(self.source.get_pixel(px + x, py + y),
-                                       self.buffer_opt.as_ref().unwrap().get_pixel(xs + x, ys + x));
-                        err_surf.put_pixel(x, y, Luma { data: [err] });
-                    }
-                }
-            },
-            OverlapArea::TopLeft => {
-                for x in 0..self.params.patch_size {
-                    for y in 0..self.params.overlap {
-                        let err = dist(self.source.get_pixel(px + x, py + y),
-                                       self.buffer_opt.as_ref().unwrap().get_pixel(xs + x, ys + x));
-                        err_surf.put_pixel(x, y, Luma { data: [err] });
-                    }
-                }
-                for x in 0..self.params.overlap {
-                    for y in self.params.overlap..self.params.patch_size {
-                        let err = dist(self.source.get_pixel(px + x, py + y),
-                                       self.buffer_opt.as_ref().unwrap().get_pixel(xs + x, ys + x));
-                        err_surf.put_pixel(x, y, Luma { data: [err] });
-                    }
+        let dist = self.params.distance.function();
+        let buffer = self.buffer_opt.as_ref().unwrap();
+        let state = self.state_opt.as_ref().unwrap();
+        for y in 0..self.params.patch_size.1 {
+            for x in 0..self.params.patch_size.0 {
+                let (bx, by) = ((xs + x) % buffer.width(), (ys + y) % buffer.height());
+                if area.contains((x, y), self.params.patch_size, self.params.overlap) &&
+                   state.get_pixel(bx, by).data[0] != PIXEL_EMPTY {
+                    let err = dist(self.source.get_pixel(px + x, py + y), buffer.get_pixel(bx, by));
+                    err_surf.put_pixel(x, y, Luma { data: [err] });
                 }
             }
         }
@@ -320,232 +2102,226 @@ impl Quilter {
         err_surf
     }
 
-    fn vertical_cost_map(&self, err_surf: &ErrorSurface) -> CostMap {
-        let mut cost_map = CostMap::new();
-
-        fn pixel_error(cost_map: &mut CostMap, e: &ErrorSurface, overlap: u32,
-                       x: u32, y: u32) -> OrderedFloat<f64> {
-            if cost_map.contains_key(&(x, y)) {
-                *cost_map.get(&(x, y)).unwrap()
+    /// Compute the mask of the pixels of a patch to be copied to the buffer,
+    /// by cutting it along the minimum error boundary of each overlapping side.
+    fn patch_cut_mask(&self, err_surf: &ErrorSurface, area: OverlapArea) -> GrayImage {
+        let (width, height) = self.params.patch_size;
+        let mut mask = GrayImage::from_pixel(width, height, Luma { data: [255] });
+        if area.left {
+            for (xp, yp) in minimum_cost_vertical_path(err_surf, 0, self.params.overlap.0) {
+                for x in 0..xp { mask.put_pixel(x, yp, Luma { data: [0] }); }
             }
-            else if y == 0 {
-                let val = *e.get_pixel(x, y);
-                let v = OrderedFloat::<f64>::try_from(val.data[0]).unwrap();
-                cost_map.insert((x, y), v);
-                v
+        }
+        if area.right {
+            for (xp, yp) in minimum_cost_vertical_path(err_surf, width - self.params.overlap.0, self.params.overlap.0) {
+                for x in xp + 1..width { mask.put_pixel(x, yp, Luma { data: [0] }); }
             }
-            else {
-                let mut val = pixel_error(cost_map, e, overlap, x, y - 1);
-                if x != 0 {
-                    let v = pixel_error(cost_map, e, overlap, x - 1, y - 1);
-                    if v < val { val = v };
-                }
-                if x != overlap - 1 {
-                    let v = pixel_error(cost_map, e, overlap, x + 1, y - 1);
-                    if v < val { val = v };
-                }
-                val += OrderedFloat::<f64>::try_from(e.get_pixel(x, y).data[0]).unwrap();
-                cost_map.insert((x, y), val);
-                val
+        }
+        if area.top {
+            for (xp, yp) in minimum_cost_horizontal_path(err_surf, 0, self.params.overlap.1) {
+                for y in 0..yp { mask.put_pixel(xp, y, Luma { data: [0] }); }
             }
-        };
-
-        for x in 0..self.params.overlap {
-            pixel_error(&mut cost_map, err_surf, self.params.overlap, x, self.params.patch_size - 1);
         }
-
-        cost_map
-    }
-
-    fn minimum_cost_vertical_path(&self, err_surf: &ErrorSurface) -> Vec<(u32, u32)> {
-        let mut v = vec!();
-        let cost_map = self.vertical_cost_map(err_surf);
-
-        // Find path starting point
-        let row = (0..self.params.overlap).into_iter().map(|x| cost_map[&(x, self.params.patch_size - 1)]).collect::<Vec<_>>();
-        let (mut x, mut y) = (row.into_iter().enumerate().min_by(|&(_, v1), &(_, v2)| v1.cmp(&v2)).unwrap().0 as u32,
-                              self.params.patch_size - 1);
-        v.push((x, y));
-        while y != 0 {
-            let top = cost_map[&(x, y - 1)];
-            if x == 0 {
-                let right = cost_map[&(x + 1, y - 1)];
-                if right < top { x += 1; }
-            }
-            else if x == self.params.overlap - 1 {
-                let left = cost_map[&(x - 1, y - 1)];
-                if left < top { x -= 1; }
-            }
-            else {
-                let left = cost_map[&(x - 1, y - 1)];
-                let right = cost_map[&(x + 1, y - 1)];
-                if left < top {
-                    if left < right { x -= 1; }
-                }
-                else if right < top { x += 1; }
+        if area.bottom {
+            for (xp, yp) in minimum_cost_horizontal_path(err_surf, height - self.params.overlap.1, self.params.overlap.1) {
+                for y in yp + 1..height { mask.put_pixel(xp, y, Luma { data: [0] }); }
             }
-            y -= 1;
-            v.push((x, y));
         }
 
-        v
+        mask
     }
 
-    fn horizontal_cost_map(&self, err_surf: &ErrorSurface) -> CostMap {
-        let mut cost_map = CostMap::new();
+    /// Compute the mask of the pixels of a patch to be copied to the buffer,
+    /// by cutting its whole overlap area along a minimum cut.
+    ///
+    /// The graph nodes are the non empty pixels of the buffer under the
+    /// patch, which are labeled as either keeping their current value or
+    /// taking the value of the patch. Pixels along the border of the patch
+    /// next to non empty pixels, and fixed pixels, must keep their value.
+    /// Where neighbouring pixels come from different source locations, a seam
+    /// node carries the cost of the old seam, which is only paid again if the
+    /// new patch doesn't cover it.
+    fn graph_cut_mask(&self, patch: &Patch, buf_coords: (u32, u32)) -> GrayImage {
+        const OLD: usize = 0;
+        const NEW: usize = 1;
+        let (width, height) = self.params.patch_size;
+        let buffer = self.buffer_opt.as_ref().unwrap();
+        let state = self.state_opt.as_ref().unwrap();
+        let origins = self.origin_opt.as_ref().unwrap();
+        let (bw, bh) = buffer.dimensions();
+        let dist = self.params.distance.function();
+        let mut mask = GrayImage::from_pixel(width, height, Luma { data: [255] });
 
-        fn pixel_error(cost_map: &mut CostMap, e: &ErrorSurface, overlap: u32,
-                       x: u32, y: u32) -> OrderedFloat<f64> {
-            if cost_map.contains_key(&(x, y)) {
-                *cost_map.get(&(x, y)).unwrap()
-            }
-            else if x == 0 {
-                let val = *e.get_pixel(x, y);
-                let v = OrderedFloat::<f64>::try_from(val.data[0]).unwrap();
-                cost_map.insert((x, y), v);
-                v
-            }
-            else {
-                let mut val = pixel_error(cost_map, e, overlap, x - 1, y);
-                if y != 0 {
-                    let v = pixel_error(cost_map, e, overlap, x - 1, y - 1);
-                    if v < val { val = v };
-                }
-                if y != overlap - 1 {
-                    let v = pixel_error(cost_map, e, overlap, x - 1, y + 1);
-                    if v < val { val = v };
-                }
-                val += OrderedFloat::<f64>::try_from(e.get_pixel(x, y).data[0]).unwrap();
-                cost_map.insert((x, y), val);
-                val
-            }
+        let buf = |x: i64, y: i64| ((((buf_coords.0 as i64 + x) % bw as i64 + bw as i64) % bw as i64) as u32,
+                                    (((buf_coords.1 as i64 + y) % bh as i64 + bh as i64) % bh as i64) as u32);
+        // Outside of the buffer, pixels are empty unless the buffer wraps around
+        let pixel_state = |x: i64, y: i64| {
+            let (ax, ay) = (buf_coords.0 as i64 + x, buf_coords.1 as i64 + y);
+            if !self.params.tileable && (ax < 0 || ay < 0 || ax >= bw as i64 || ay >= bh as i64) { return PIXEL_EMPTY; }
+            let (bx, by) = buf(x, y);
+            state.get_pixel(bx, by).data[0]
         };
+        let old = |x: u32, y: u32| { let (bx, by) = buf(x as i64, y as i64); buffer.get_pixel(bx, by) };
+        let new = |x: u32, y: u32| self.source.get_pixel(patch.coords.0 + x, patch.coords.1 + y);
+        let origin = |x: u32, y: u32| { let (bx, by) = buf(x as i64, y as i64); origins[(by * bw + bx) as usize] };
 
-        for y in 0..self.params.overlap {
-            pixel_error(&mut cost_map, err_surf, self.params.overlap, self.params.patch_size - 1, y);
-        }
-
-        cost_map
-    }
-
-    fn minimum_cost_horizontal_path(&self, err_surf: &ErrorSurface) -> Vec<(u32, u32)> {
-        let mut v = vec!();
-        let cost_map = self.horizontal_cost_map(err_surf);
-
-        // Find path starting point
-        let column = (0..self.params.overlap).into_iter().map(|y| cost_map[&(self.params.patch_size - 1, y)]).collect::<Vec<_>>();
-        let (mut x, mut y) = (self.params.patch_size - 1,
-                              column.into_iter().enumerate().min_by(|&(_, v1), &(_, v2)| v1.cmp(&v2)).unwrap().0 as u32);
-        v.push((x, y));
-        while x != 0 {
-            let left = cost_map[&(x - 1, y)];
-            if y == 0 {
-                let down = cost_map[&(x - 1, y + 1)];
-                if down < left { y += 1; }
-            }
-            else if y == self.params.overlap - 1 {
-                let up = cost_map[&(x - 1, y - 1)];
-                if up < left { y -= 1; }
-            }
-            else {
-                let up = cost_map[&(x - 1, y - 1)];
-                let down = cost_map[&(x - 1, y + 1)];
-                if up < left {
-                    if up < down { y -= 1; }
+        let mut graph = FlowGraph::new(2);
+        let mut nodes = vec![None; (width * height) as usize];
+        for y in 0..height {
+            for x in 0..width {
+                if pixel_state(x as i64, y as i64) != PIXEL_EMPTY {
+                    nodes[(y * width + x) as usize] = Some(graph.add_node());
                 }
-                else if down < left { y += 1; }
             }
-            x -= 1;
-            v.push((x, y));
         }
+        if nodes.iter().all(|n| n.is_none()) { return mask; }
 
-        v
-    }
+        for y in 0..height {
+            for x in 0..width {
+                let u = match nodes[(y * width + x) as usize] { Some(u) => u, None => continue };
+                let cost = dist(old(x, y), new(x, y));
+                if pixel_state(x as i64, y as i64) == PIXEL_FIXED {
+                    graph.add_edge(OLD, u, f64::INFINITY, 0.);
+                }
+                for &(dx, dy) in &[(1i64, 0i64), (0, 1), (-1, 0), (0, -1)] {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                        if pixel_state(nx, ny) != PIXEL_EMPTY {
+                            graph.add_edge(OLD, u, f64::INFINITY, 0.);
+                        }
+                        continue;
+                    }
+                    let (nx, ny) = (nx as u32, ny as u32);
+                    let v = match nodes[(ny * width + nx) as usize] {
+                        Some(v) => v,
+                        None => {
+                            // Empty pixels always take the value of the patch
+                            graph.add_edge(u, NEW, cost, 0.);
+                            continue;
+                        }
+                    };
+                    if dx < 0 || dy < 0 { continue; }
 
-    fn cut_and_blit_vertical(&mut self, patch: &Patch, buf_coords: (u32, u32),
-                             path: Vec<(u32, u32)>) {
-        let buffer = self.buffer_opt.as_mut().unwrap();
-        for (xp, yp) in path {
-            if yp + patch.coords.1 < buffer.height() {
-                for x in 0..self.params.overlap {
-                    if x >= xp && x < buffer.width()  {
-                        buffer.put_pixel(buf_coords.0 + x, buf_coords.1 + yp, *self.source.get_pixel(patch.coords.0 + x, patch.coords.1 + yp));
+                    let neighbour_cost = dist(old(nx, ny), new(nx, ny));
+                    match (origin(x, y), origin(nx, ny)) {
+                        (Some(o1), Some(o2)) if (o1.0 as i64 + dx, o1.1 as i64 + dy) != (o2.0 as i64, o2.1 as i64) => {
+                            // Old seam: compare the new patch to each of the
+                            // two old patches on both sides of it
+                            let (a1, a2) = (self.source_at(o1, dx, dy).unwrap_or(old(nx, ny)),
+                                            self.source_at(o2, -dx, -dy).unwrap_or(old(x, y)));
+                            let m1 = cost + dist(a1, new(nx, ny));
+                            let m2 = dist(a2, new(x, y)) + neighbour_cost;
+                            let seam_cost = dist(old(x, y), a2) + dist(a1, old(nx, ny));
+                            let s = graph.add_node();
+                            graph.add_edge(u, s, m1, m1);
+                            graph.add_edge(s, v, m2, m2);
+                            graph.add_edge(s, NEW, seam_cost, 0.);
+                        },
+                        _ => graph.add_edge(u, v, cost + neighbour_cost, cost + neighbour_cost)
                     }
                 }
             }
         }
-    }
 
-    fn cut_and_blit_horizontal(&mut self, patch: &Patch, buf_coords: (u32, u32),
-                               path: Vec<(u32, u32)>) {
-        let buffer = self.buffer_opt.as_mut().unwrap();
-        for (xp, yp) in path {
-            if xp + patch.coords.0 < buffer.width() {
-                for y in 0..self.params.overlap {
-                    if y >= yp && y < buffer.height()  {
-                        buffer.put_pixel(buf_coords.0 + xp, buf_coords.1 + y, *self.source.get_pixel(patch.coords.0 + xp, patch.coords.1 + y));
-                    }
+        let old_side = graph.min_cut(OLD, NEW);
+        for y in 0..height {
+            for x in 0..width {
+                if let Some(u) = nodes[(y * width + x) as usize] {
+                    if old_side[u] { mask.put_pixel(x, y, Luma { data: [0] }); }
                 }
             }
         }
+
+        mask
     }
 
-    fn cut_and_blit_corner(&mut self, patch: &Patch, buf_coords: (u32, u32),
-                           hpath: Vec<(u32, u32)>, vpath: Vec<(u32, u32)>) {
-        let overlap = self.params.overlap;
-        let mut do_pixel = |x, y| {
-            let buffer = self.buffer_opt.as_mut().unwrap();
-            let hpos = hpath.iter().find(|&&(xx, _)| xx == x).unwrap();
-            let vpos = vpath.iter().find(|&&(_, yy)| yy == y).unwrap();
-            if y >= hpos.1 && x >= vpos.0 {
-                buffer.put_pixel(buf_coords.0 + x, buf_coords.1 + y,
-                                 *self.source.get_pixel(patch.coords.0 + x, patch.coords.1 + y));
-            }
+    /// Copy the pixels of the patch selected by the mask to the buffer,
+    /// blending them with the buffer around the seams. Fixed pixels are never
+    /// overwritten, and empty pixels are always written.
+    fn cut_and_blit_patch(&mut self, patch: &Patch, buf_coords: (u32, u32), mask: &GrayImage) {
+        let (width, height) = mask.dimensions();
+        let source_patch = self.source_patch(patch);
+        let buffer = self.buffer_opt.as_mut().unwrap();
+        let state = self.state_opt.as_mut().unwrap();
+        let origins = self.origin_opt.as_mut().unwrap();
+        let usage = self.usage_opt.as_mut().unwrap();
+        let (copies, source_size) = (&self.copies, self.source_size);
+        let (bw, bh) = buffer.dimensions();
+        let buf = |x: u32, y: u32| ((buf_coords.0 + x) % bw, (buf_coords.1 + y) % bh);
+        let source = &self.source;
+        usage.place(patch.coords, source_patch.coords, buf_coords);
+        let new = |x: u32, y: u32| source.get_pixel(patch.coords.0 + x, patch.coords.1 + y);
+
+        // Weight of the patch in each pixel, before and after blending
+        let mut cut = FloatImage::new(width, height, 1);
+        for (x, y, m) in mask.enumerate_pixels() {
+            let (bx, by) = buf(x, y);
+            let weight = match state.get_pixel(bx, by).data[0] {
+                PIXEL_EMPTY => 1.,
+                PIXEL_FIXED => 0.,
+                _ => if m.data[0] != 0 { 1. } else { 0. }
+            };
+            cut.set(x, y, 0, weight);
+        }
+        let mut alpha = match self.params.blending {
+            Blending::Cut => cut.clone(),
+            Blending::Feather(radius) | Blending::MultiBand(radius) => box_blur(&cut, radius)
         };
-        for x in 0..overlap {
-            for y in 0..overlap {
-                do_pixel(x, y);
+        for (x, y) in (0..height).flat_map(|y| (0..width).map(move |x| (x, y))) {
+            let (bx, by) = buf(x, y);
+            match state.get_pixel(bx, by).data[0] {
+                PIXEL_EMPTY => alpha.set(x, y, 0, 1.),
+                PIXEL_FIXED => alpha.set(x, y, 0, 0.),
+                _ => ()
             }
         }
-    }
 
-    fn cut_and_blit_patch(&mut self, patch: &Patch, buf_coords: (u32, u32),
-                          err_surf: &ErrorSurface, area: OverlapArea) {
-        let overlap = self.params.overlap;
-        match area {
-            OverlapArea::Left => {
-                let path = self.minimum_cost_vertical_path(err_surf);
-                self.cut_and_blit_vertical(patch, buf_coords, path);
-                let mut buffer = self.buffer_opt.as_mut().unwrap();
-                blit_rect(buffer, &self.source,
-                          &Rect { coords: (patch.coords.0 + overlap, patch.coords.1),
-                                  size: (self.params.patch_size - overlap, self.params.patch_size) },
-                          (buf_coords.0 + overlap, buf_coords.1));
-            },
-            OverlapArea::Top => {
-                let path = self.minimum_cost_horizontal_path(err_surf);
-                self.cut_and_blit_horizontal(patch, buf_coords, path);
-                let mut buffer = self.buffer_opt.as_mut().unwrap();
-                blit_rect(buffer, &self.source,
-                          &Rect { coords: (patch.coords.0, patch.coords.1 + overlap),
-                                  size: (self.params.patch_size, self.params.patch_size - overlap) },
-                          (buf_coords.0, buf_coords.1 + overlap));
+        // Multi-band blending of the patch and the buffer under it. Empty
+        // pixels of the buffer take the value of the patch.
+        let blended_opt = match self.params.blending {
+            Blending::MultiBand(radius) => {
+                let (mut old, mut new_img) = (FloatImage::new(width, height, 3), FloatImage::new(width, height, 3));
+                for y in 0..height {
+                    for x in 0..width {
+                        let (bx, by) = buf(x, y);
+                        let empty = state.get_pixel(bx, by).data[0] == PIXEL_EMPTY;
+                        for c in 0..3 {
+                            let n = new(x, y).data[c] as f64;
+                            new_img.set(x, y, c, n);
+                            old.set(x, y, c, if empty { n } else { buffer.get_pixel(bx, by).data[c] as f64 });
+                        }
+                    }
+                }
+                let levels = 32 - radius.leading_zeros() + 1;
+                Some(multi_band_blend(&old, &new_img, &cut, levels))
             },
-            OverlapArea::TopLeft => {
-                let (vpath, vpath_corner): (Vec<_>, Vec<_>) = self.minimum_cost_vertical_path(err_surf)
-                                                                  .into_iter()
-                                                                  .partition(|&(_, y)| y >= overlap);
-                let (hpath, hpath_corner): (Vec<_>, Vec<_>) = self.minimum_cost_horizontal_path(err_surf)
-                                                                  .into_iter()
-                                                                  .partition(|&(x, _)| x >= overlap);
-                self.cut_and_blit_vertical(patch, buf_coords, vpath);
-                self.cut_and_blit_horizontal(patch, buf_coords, hpath);
-                self.cut_and_blit_corner(patch, buf_coords, hpath_corner, vpath_corner);
-                let mut buffer = self.buffer_opt.as_mut().unwrap();
-                blit_rect(buffer, &self.source,
-                          &Rect { coords: (patch.coords.0 + overlap, patch.coords.1 + overlap),
-                                  size: (self.params.patch_size - overlap, self.params.patch_size - overlap) },
-                          (buf_coords.0 + overlap, buf_coords.1 + overlap));
+            _ => None
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let a = alpha.get(x, y, 0);
+                if a <= 0. { continue; }
+                let (bx, by) = buf(x, y);
+                let old = *buffer.get_pixel(bx, by);
+                let mut pixel = *new(x, y);
+                if a < 1. {
+                    for c in 0..3 {
+                        let v = match blended_opt {
+                            Some(ref blended) => blended.get(x, y, c),
+                            None => a * pixel.data[c] as f64 + (1. - a) * old.data[c] as f64
+                        };
+                        pixel.data[c] = v.round().max(0.).min(255.) as u8;
+                    }
+                }
+                buffer.put_pixel(bx, by, pixel);
+                state.put_pixel(bx, by, Luma { data: [PIXEL_QUILTED] });
+                if a >= 0.5 {
+                    let (sx, sy) = (patch.coords.0 + x, patch.coords.1 + y);
+                    origins[(by * bw + bx) as usize] = Some((sx, sy));
+                    let (ux, uy) = source_coords(copies, source_size, (sx, sy));
+                    usage.counts[(uy * usage.width + ux) as usize] += 1;
+                }
             }
         }
     }
@@ -554,8 +2330,15 @@ impl Quilter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use correspondence::luminance;
     use distance::l1;
 
+    /// Random noise image, always the same for a given size.
+    fn noise(width: u32, height: u32) -> RgbImage {
+        let mut rng = XorShiftRng::from_seed([width, height, 1, 2]);
+        RgbImage::from_fn(width, height, |_, _| Rgb { data: [rng.gen(), rng.gen(), rng.gen()] })
+    }
+
     #[test]
     fn test_patch_rect_error() {
         let mut i1 = RgbImage::new(11, 11);
@@ -566,8 +2349,14 @@ mod tests {
         i1.put_pixel(5, 7, Rgb { data: [7, 7, 7] });
         i1.put_pixel(7, 5, Rgb { data: [7, 7, 7] });
 
-        let f = patch_rect_error(l1, &i1, &i2, (4, 4), (0, 0), (3u32, 3u32));
+        let f = patch_rect_error(l1, &i1, &i2, None, (4, 4), (0, 0), (3u32, 3u32));
         assert_relative_eq!(f, 120.);
+
+        // Masked out pixels aren't compared
+        let mut mask = GrayImage::from_pixel(11, 11, Luma { data: [255] });
+        mask.put_pixel(2, 2, Luma { data: [0] });
+        let f = patch_rect_error(l1, &i1, &i2, Some(&mask), (4, 4), (0, 0), (3u32, 3u32));
+        assert_relative_eq!(f, 60.);
     }
 
     #[test]
@@ -578,15 +2367,663 @@ mod tests {
             source.put_pixel(0, y, Rgb { data: [255, 0, 0] });
         }
 
-        let params = QuilterParams::new((100, 100), 5, 1, None, None, l1).unwrap();
+        let params = QuilterParams::new((100, 100), (5, 5), (1, 1), None, None, Distance::L1).unwrap();
         let mut quilter = Quilter::new(source, params);
-        let patch = Patch { coords: (0, 0), size: 5 };
+        let patch = Patch { coords: (0, 0), size: (5, 5) };
         quilter.buffer_opt = Some(RgbImage::new(11, 11));
+        quilter.state_opt = Some(GrayImage::from_pixel(11, 11, Luma { data: [PIXEL_QUILTED] }));
 
-        let err_surf = quilter.patch_error_surface(OverlapArea::Left, &patch, (0, 0));
+        let area = OverlapArea { left: true, ..Default::default() };
+        let err_surf = quilter.patch_error_surface(area, &patch, (0, 0));
         for y in 0..5 {
             let val = err_surf.get_pixel(0, y).data[0];
             assert!(val == 255.);
         }
     }
+
+    #[test]
+    fn test_patch_overlap_area_tileable() {
+        let area = patch_overlap_area((0, 0), (4, 4), true);
+        assert!(!area.top && !area.left && !area.bottom && !area.right);
+        let area = patch_overlap_area((3, 1), (4, 4), true);
+        assert!(area.top && area.left && !area.bottom && area.right);
+        let area = patch_overlap_area((3, 3), (4, 4), true);
+        assert!(area.top && area.left && area.bottom && area.right);
+        let area = patch_overlap_area((3, 3), (4, 4), false);
+        assert!(area.top && area.left && !area.bottom && !area.right);
+    }
+
+    #[test]
+    fn test_tileable_size_validation() {
+        let quilter = |size| {
+            let params = QuilterParams::new(size, (10, 10), (2, 2), None, None, Distance::L1).unwrap().tileable(true);
+            Quilter::new(RgbImage::new(20, 20), params)
+        };
+        assert!(quilter((30, 30)).validate_params((20, 20)).is_err());
+        assert!(quilter((8, 16)).validate_params((20, 20)).is_err());
+        assert!(quilter((32, 32)).validate_params((20, 20)).is_ok());
+    }
+
+    #[test]
+    fn test_extend_image() {
+        let mut source = RgbImage::new(16, 16);
+        for (x, y, p) in source.enumerate_pixels_mut() {
+            *p = Rgb { data: [(x * 16) as u8, (y * 16) as u8, 128] };
+        }
+        let image = source.sub_image(4, 4, 8, 8).to_image();
+        let extension = Extension { left: 3, top: 0, right: 5, bottom: 7 };
+
+        let params = QuilterParams::new((1, 1), (6, 6), (2, 2), None, None, Distance::L1).unwrap();
+        let res = Quilter::new(source, params).extend_image(&image, extension).unwrap();
+        assert_eq!(res.dimensions(), (16, 15));
+        // The original image is left untouched
+        for (x, y, p) in image.enumerate_pixels() {
+            assert_eq!(res.get_pixel(x + 3, y), p);
+        }
+        // And no pixel is left empty
+        assert!(res.pixels().all(|p| p.data[2] == 128));
+    }
+
+    #[test]
+    fn test_fill_holes() {
+        let source = noise(24, 24);
+        let mut image = source.clone();
+        let mut hole_mask = GrayImage::new(24, 24);
+        for y in 5..17 {
+            for x in 7..13 {
+                image.put_pixel(x, y, Rgb { data: [0, 0, 0] });
+                hole_mask.put_pixel(x, y, Luma { data: [255] });
+            }
+        }
+
+        // Only the original location of the patches matches the pixels
+        // around the hole
+        for &distance in &[Distance::L1, Distance::L2Squared] {
+            let params = QuilterParams::new((1, 1), (8, 8), (2, 2), None, None, distance).unwrap();
+            let res = Quilter::new(source.clone(), params).fill_holes(&image, &hole_mask).unwrap();
+            assert_eq!(res.into_raw(), source.clone().into_raw());
+        }
+
+        let params = QuilterParams::new((1, 1), (8, 8), (2, 2), None, None, Distance::L1).unwrap();
+        assert!(Quilter::new(source.clone(), params).fill_holes(&image, &GrayImage::new(8, 8)).is_err());
+        let params = QuilterParams::new((1, 1), (8, 8), (2, 2), None, None, Distance::L1).unwrap();
+        let res = Quilter::new(source, params).fill_holes(&image, &GrayImage::new(24, 24)).unwrap();
+        assert_eq!(res.into_raw(), image.into_raw());
+    }
+
+    #[test]
+    fn test_brush() {
+        // Horizontal stripes, painted along a vertical stroke
+        let source = RgbImage::from_fn(24, 24, |_, y| if (y / 3) % 2 == 0 { Rgb { data: [255, 255, 255] } }
+                                                      else { Rgb { data: [0, 0, 0] } });
+        let canvas = RgbImage::from_pixel(48, 48, Rgb { data: [128, 128, 128] });
+        let params = QuilterParams::new((1, 1), (10, 10), (3, 3), None, None, Distance::L1).unwrap().orientations(4);
+        let mut quilter = Quilter::new(source, params);
+        let res = quilter.brush(&canvas, &[(24., 4.), (24., 44.)], 8.).unwrap();
+
+        // The stroke is covered by vertical stripes, and the canvas away from
+        // it is left untouched
+        for y in 0..48 {
+            for x in 0..48 {
+                let d = if y < 4 { (x as f64 - 24.).hypot(y as f64 - 4.) }
+                        else if y > 44 { (x as f64 - 24.).hypot(y as f64 - 44.) }
+                        else { (x as f64 - 24.).abs() };
+                let pixel = res.get_pixel(x, y).data;
+                if d <= 4. { assert!(pixel == [0, 0, 0] || pixel == [255, 255, 255]); }
+                if d > 7. { assert_eq!(pixel, [128, 128, 128]); }
+                if d <= 4. && y >= 4 && y < 44 { assert_eq!(pixel, res.get_pixel(x, y + 1).data); }
+            }
+        }
+        let angle = quilter.placements()[0].angle;
+        assert_relative_eq!(angle, f64::consts::PI / 2.);
+        assert!(quilter.placements().iter().all(|p| p.angle == angle));
+
+        // The diagonal orientations of a narrow source can't hold a patch, and
+        // a diagonal stroke takes its patches from the nearest other one
+        let source = RgbImage::from_fn(40, 10, |x, y| Rgb { data: [(x * 6) as u8, (y * 20) as u8, 0] });
+        let params = QuilterParams::new((1, 1), (8, 8), (2, 2), None, None, Distance::L1).unwrap().orientations(8);
+        let mut quilter = Quilter::new(source, params);
+        assert_eq!(quilter.copies.len(), 4);
+        assert!(quilter.copies.iter().all(|c| (c.angle / f64::consts::FRAC_PI_4).round() % 2. == 0.));
+        quilter.brush(&canvas, &[(4., 4.), (40., 40.)], 8.).unwrap();
+        assert!(!quilter.placements().is_empty());
+
+        let params = QuilterParams::new((1, 1), (10, 10), (3, 3), None, None, Distance::L1).unwrap();
+        assert!(Quilter::new(res, params).brush(&canvas, &[], 8.).is_err());
+    }
+
+    #[test]
+    fn test_rotated_copies() {
+        let source = RgbImage::from_fn(20, 10, |x, y| Rgb { data: [x as u8, y as u8, 0] });
+        let params = QuilterParams::new((1, 1), (4, 4), (1, 1), None, None, Distance::L1).unwrap().orientations(4);
+        let quilter = Quilter::new(source, params);
+        assert_eq!(quilter.copies.len(), 4);
+        // A quarter turn maps the source into a 10x20 rectangle, cropped to
+        // keep whole pixels
+        let copy = &quilter.copies[1];
+        assert_eq!((copy.rect.coords, copy.rect.size, copy.size), ((20, 0), (8, 18), (20, 10)));
+        for y in 0..18 {
+            for x in 0..8 {
+                let (sx, sy) = source_coords(&quilter.copies, (20, 10), (20 + x, y));
+                let pixel = quilter.source.get_pixel(20 + x, y).data;
+                assert_eq!((sx, sy), (pixel[0] as u32, pixel[1] as u32));
+                // Clockwise quarter turn
+                assert_eq!((sx as i64, sy as i64), (y as i64 + 1, 8 - x as i64));
+            }
+        }
+    }
+
+    #[test]
+    fn test_source_map() {
+        let mut source = noise(16, 16);
+        // Rendering the source image with the map gives back the synthesized image
+        let params = QuilterParams::new((30, 20), (8, 8), (2, 2), None, None, Distance::L1).unwrap()
+                                   .transforms(TransformSet::All);
+        let mut quilter = Quilter::new(source.clone(), params);
+        let res = quilter.quilt_image().unwrap();
+        let map = quilter.source_map().unwrap();
+        assert_eq!(map.dimensions(), (30, 20));
+        for (x, y, p) in res.enumerate_pixels() {
+            let s = map.get(x, y).unwrap();
+            let copy = quilter.copies.iter().find(|c| c.transform == s.transform).unwrap();
+            let (tx, ty) = s.transform.apply(s.coords, (16, 16));
+            assert_eq!(quilter.source.get_pixel(copy.rect.coords.0 + tx, copy.rect.coords.1 + ty), p);
+        }
+
+        // Pixels which aren't synthesized have no source
+        let image = source.sub_image(4, 4, 8, 8).to_image();
+        let params = QuilterParams::new((1, 1), (6, 6), (2, 2), None, None, Distance::L1).unwrap();
+        let mut quilter = Quilter::new(source.clone(), params);
+        let res = quilter.extend_image(&image, Extension { left: 0, top: 0, right: 4, bottom: 0 }).unwrap();
+        let map = quilter.source_map().unwrap();
+        assert!(map.get(7, 7).is_none());
+        assert_eq!(map.render(&source, &res).into_raw(), res.into_raw());
+    }
+
+    #[test]
+    fn test_debug_report() {
+        let source = noise(16, 16);
+        let params = QuilterParams::new((20, 14), (8, 8), (2, 2), None, None, Distance::L1).unwrap();
+        let mut quilter = Quilter::new(source.clone(), params);
+        quilter.quilt_image().unwrap();
+        assert!(quilter.debug_report().is_none());
+
+        let params = QuilterParams::new((20, 14), (8, 8), (2, 2), None, None, Distance::L1).unwrap().debug(true);
+        let mut quilter = Quilter::new(source, params);
+        let res = quilter.quilt_image().unwrap();
+        let report = quilter.debug_report().unwrap();
+        assert_eq!(report.error_surfaces.len(), quilter.placements().len());
+        assert!(report.error_surfaces.iter().all(|s| s.dimensions() == (8, 8)));
+        // The seed patch doesn't overlap anything
+        assert!(report.error_surfaces[0].pixels().all(|p| p.data[0] == 0));
+        for img in &[&report.seams, &report.outlines, &report.candidates] {
+            assert_eq!(img.dimensions(), res.dimensions());
+        }
+        // Random patches never match exactly, so there are seams between them
+        assert!(report.seams.pixels().any(|p| p.data == [255, 0, 0]));
+        assert_eq!(report.outlines.get_pixel(6, 6).data, [0, 255, 0]);
+        assert!(report.candidates.pixels().all(|p| p.data != [0, 0, 0]));
+    }
+
+    #[test]
+    fn test_rectangular_patches() {
+        let mut source = RgbImage::new(16, 16);
+        for (x, y, p) in source.enumerate_pixels_mut() {
+            *p = Rgb { data: [(x * 16) as u8, (y * 16) as u8, 128] };
+        }
+
+        for &seam in &[SeamMethod::MinimumErrorBoundary, SeamMethod::GraphCut] {
+            let params = QuilterParams::new((23, 11), (8, 4), (3, 1), None, None, Distance::L1).unwrap().seam(seam);
+            let res = Quilter::new(source.clone(), params).quilt_image().unwrap();
+            assert_eq!(res.dimensions(), (23, 11));
+            assert!(res.pixels().all(|p| p.data[2] == 128));
+
+            // Steps are 5 pixels horizontally and 3 vertically
+            let params = QuilterParams::new((20, 9), (8, 4), (3, 1), None, None, Distance::L1).unwrap().seam(seam).tileable(true);
+            let res = Quilter::new(source.clone(), params).quilt_image().unwrap();
+            assert!(res.pixels().all(|p| p.data[2] == 128));
+
+            let image = source.sub_image(4, 4, 8, 8).to_image();
+            let extension = Extension { left: 4, top: 2, right: 7, bottom: 5 };
+            let params = QuilterParams::new((1, 1), (8, 4), (3, 1), None, None, Distance::L1).unwrap().seam(seam);
+            let res = Quilter::new(source.clone(), params).extend_image(&image, extension).unwrap();
+            assert_eq!(res.dimensions(), (19, 15));
+            assert!(res.pixels().all(|p| p.data[2] == 128));
+        }
+    }
+
+    #[test]
+    fn test_graph_cut_mask() {
+        // The first overlap column differs from the patch, the second one
+        // matches it: the cut should go between the second column and the
+        // rest of the patch.
+        let source = RgbImage::from_fn(6, 6, |x, _| Rgb { data: [(x * 10) as u8; 3] });
+        let params = QuilterParams::new((1, 1), (6, 6), (2, 2), None, None, Distance::L1).unwrap().seam(SeamMethod::GraphCut);
+        let mut quilter = Quilter::new(source, params);
+        let mut buffer = RgbImage::new(10, 8);
+        let mut state = GrayImage::new(10, 8);
+        for y in 1..7 {
+            for x in 0..4 {
+                buffer.put_pixel(x, y, Rgb { data: [if x == 3 { 10 } else { 255 }; 3] });
+                state.put_pixel(x, y, Luma { data: [PIXEL_QUILTED] });
+            }
+        }
+        quilter.buffer_opt = Some(buffer);
+        quilter.state_opt = Some(state);
+        quilter.origin_opt = Some(vec![None; 80]);
+        quilter.usage_opt = Some(Usage::new(quilter.source_size));
+
+        let mask = quilter.graph_cut_mask(&Patch { coords: (0, 0), size: (6, 6) }, (2, 1));
+        for (x, _, p) in mask.enumerate_pixels() {
+            assert_eq!(p.data[0], if x < 2 { 0 } else { 255 });
+        }
+    }
+
+    #[test]
+    fn test_feather_blending() {
+        // A white patch is cut into a black buffer along its middle column.
+        // Feathering blends them into a monotonic transition around the seam.
+        let source = RgbImage::from_pixel(8, 4, Rgb { data: [255; 3] });
+        let params = QuilterParams::new((1, 1), (8, 4), (4, 1), None, None, Distance::L1).unwrap()
+                                   .blending(Blending::Feather(2));
+        let mut quilter = Quilter::new(source, params);
+        quilter.buffer_opt = Some(RgbImage::new(8, 4));
+        quilter.state_opt = Some(GrayImage::from_pixel(8, 4, Luma { data: [PIXEL_QUILTED] }));
+        quilter.origin_opt = Some(vec![None; 32]);
+        quilter.usage_opt = Some(Usage::new(quilter.source_size));
+
+        let mask = GrayImage::from_fn(8, 4, |x, _| Luma { data: [if x < 4 { 0 } else { 255 }] });
+        quilter.cut_and_blit_patch(&Patch { coords: (0, 0), size: (8, 4) }, (0, 0), &mask);
+        let buffer = quilter.buffer_opt.as_ref().unwrap();
+        for y in 0..4 {
+            let row: Vec<u8> = (0..8).map(|x| buffer.get_pixel(x, y).data[0]).collect();
+            assert!(row.windows(2).all(|w| w[0] <= w[1]));
+            assert_eq!((row[0], row[7]), (0, 255));
+            assert!(row[3] > 0 && row[4] < 255);
+        }
+    }
+
+    #[test]
+    fn test_poisson_blending() {
+        // Patches of a horizontal ramp are quilted with steps between them,
+        // which gradient domain blending turns back into a ramp.
+        let source = RgbImage::from_fn(64, 64, |x, _| Rgb { data: [(x * 2) as u8; 3] });
+        let params = QuilterParams::new((40, 30), (12, 12), (3, 3), None, None, Distance::L1).unwrap()
+                                   .poisson_blending(true);
+        let res = Quilter::new(source, params).quilt_image().unwrap();
+        for (x, y, p) in res.enumerate_pixels() {
+            if x > 0 {
+                let d = p.data[0] as i32 - res.get_pixel(x - 1, y).data[0] as i32;
+                assert!(d >= 1 && d <= 3);
+            }
+            if y > 0 {
+                let d = p.data[0] as i32 - res.get_pixel(x, y - 1).data[0] as i32;
+                assert!(d.abs() <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reuse_tracking() {
+        // The source has 81 possible patches and the output needs 49, which
+        // can all be distinct with a large enough repeat distance.
+        let source = noise(16, 16);
+        let params = QuilterParams::new((40, 40), (8, 8), (2, 2), None, None, Distance::L1).unwrap()
+                                   .repetition_penalty(1.)
+                                   .min_repeat_distance(1000);
+        let mut quilter = Quilter::new(source, params);
+        assert!(quilter.reuse_stats().is_none());
+        quilter.quilt_image().unwrap();
+        let stats = quilter.reuse_stats().unwrap();
+        assert_eq!(stats.patches, 49);
+        assert_eq!(stats.distinct_patches, 49);
+        assert_eq!(stats.max_uses, 1);
+        assert!(stats.coverage > 0. && stats.coverage <= 1.);
+
+        // Repeats are detected through the transformed copies too
+        let params = QuilterParams::new((40, 40), (8, 8), (2, 2), None, None, Distance::L1).unwrap()
+                                   .transforms(TransformSet::Flips)
+                                   .min_repeat_distance(1000);
+        let mut quilter = Quilter::new(noise(16, 16), params);
+        quilter.quilt_image().unwrap();
+        let mut sources: Vec<_> = quilter.placements().iter().map(|p| p.source_coords).collect();
+        sources.sort();
+        sources.dedup();
+        assert_eq!(sources.len(), 49);
+
+        let mut usage = Usage::new((4, 4));
+        usage.counts = vec!(0, 1, 2, 0,
+                            0, 1, 1, 0,
+                            0, 0, 0, 0,
+                            3, 0, 0, 0);
+        usage.place((1, 0), (1, 0), (0, 0));
+        usage.place((1, 0), (1, 0), (20, 0));
+        usage.place((0, 2), (0, 2), (10, 0));
+        let sat = usage.counts_sat();
+        assert_relative_eq!(usage.reuse(&sat, &Patch { coords: (1, 0), size: (2, 2) }), 1.25);
+        assert_relative_eq!(usage.reuse(&sat, &Patch { coords: (0, 2), size: (2, 2) }), 0.75);
+        assert!(usage.placed_near((1, 0), (5, 0), 6));
+        assert!(!usage.placed_near((1, 0), (10, 0), 6));
+        assert!(!usage.placed_near((2, 0), (0, 0), 6));
+        let stats = usage.stats();
+        assert_eq!((stats.patches, stats.distinct_patches, stats.max_uses), (3, 2, 2));
+        assert_relative_eq!(stats.coverage, 5. / 16.);
+        assert_relative_eq!(stats.mean_pixel_uses, 8. / 5.);
+
+        let params = QuilterParams::new((40, 40), (8, 8), (2, 2), None, None, Distance::L1).unwrap().repetition_penalty(-1.);
+        assert!(Quilter::new(RgbImage::new(16, 16), params).quilt_image().is_err());
+    }
+
+    #[test]
+    fn test_transformed_candidates() {
+        // The source is a horizontal ramp, and the overlap area a vertical
+        // one, which only rotated patches match exactly
+        let source = RgbImage::from_fn(16, 12, |x, _| Rgb { data: [(x * 10) as u8; 3] });
+        let params = QuilterParams::new((8, 8), (8, 8), (2, 2), None, None, Distance::L1).unwrap()
+                                   .transforms(TransformSet::Rotations);
+        let mut quilter = Quilter::new(source, params);
+        assert_eq!(quilter.source.dimensions(), (56, 16));
+        assert_eq!(quilter.copies[1].rect.coords, (16, 0));
+        assert_eq!(quilter.source.get_pixel(16, 15).data[0], 150);
+        assert!(quilter.candidate_valid((8, 0)) && !quilter.candidate_valid((9, 0)) && !quilter.candidate_valid((0, 5)));
+        assert_eq!(source_coords(&quilter.copies, (16, 12), (16, 15)), (15, 11));
+
+        quilter.init_buffer((8, 8));
+        for y in 0..8 {
+            for x in 0..2 {
+                quilter.buffer_opt.as_mut().unwrap().put_pixel(x, y, Rgb { data: [(y * 10) as u8; 3] });
+                quilter.state_opt.as_mut().unwrap().put_pixel(x, y, Luma { data: [PIXEL_QUILTED] });
+            }
+        }
+        quilter.quilt_patch(OverlapArea { left: true, ..Default::default() }, (0, 0));
+        let buffer = quilter.buffer_opt.as_ref().unwrap();
+        for (_, y, p) in buffer.enumerate_pixels() {
+            assert_eq!(p.data[0], (y * 10) as u8);
+        }
+        let placed = quilter.usage_opt.as_ref().unwrap().placements[0].0;
+        assert!(copy_index(&quilter.copies, placed) != Some(0));
+    }
+
+    #[test]
+    fn test_scales() {
+        let source = noise(16, 12);
+        let params = QuilterParams::new((20, 20), (8, 8), (2, 2), None, None, Distance::L1).unwrap()
+                                   .scales(0.5, 1.5, 3)
+                                   .transforms(TransformSet::Flips);
+        let quilter = Quilter::new(source.clone(), params);
+        let sizes: Vec<_> = quilter.copies.iter().map(|c| (c.scale, c.rect.size)).collect();
+        assert_eq!(sizes, vec!((0.5, (8, 6)), (0.5, (8, 6)), (0.5, (8, 6)), (0.5, (8, 6)),
+                               (1., (16, 12)), (1., (16, 12)), (1., (16, 12)), (1., (16, 12)),
+                               (1.5, (24, 18)), (1.5, (24, 18)), (1.5, (24, 18)), (1.5, (24, 18))));
+        assert_eq!(quilter.copies[5].rect.coords, (16, 6));
+        assert_eq!(source_coords(&quilter.copies, (16, 12), (50, 20)), (1, 10));
+        assert_eq!(source_coords(&quilter.copies, (16, 12), (74, 20)), (14, 10));
+
+        // Copies which can't hold a patch are skipped, unless none can
+        let params = QuilterParams::new((20, 20), (8, 8), (2, 2), None, None, Distance::L1).unwrap().scales(0.5, 1.5, 3);
+        let mut quilter = Quilter::new(source.clone(), params);
+        quilter.quilt_image().unwrap();
+        assert!(quilter.placements().iter().all(|p| p.scale > 0.5));
+        let params = QuilterParams::new((20, 20), (8, 8), (2, 2), None, None, Distance::L1).unwrap().scales(0.25, 0.5, 2);
+        assert!(Quilter::new(source.clone(), params).quilt_image().is_err());
+        // A copy of the size of the patches holds a single one
+        let params = QuilterParams::new((20, 20), (8, 6), (2, 2), None, None, Distance::L1).unwrap().scales(0.5, 0.5, 1);
+        let mut quilter = Quilter::new(source.clone(), params);
+        quilter.quilt_image().unwrap();
+        assert!(quilter.placements().iter().all(|p| p.source_coords == (0, 0)));
+        let params = QuilterParams::new((20, 20), (8, 8), (2, 2), None, None, Distance::L1).unwrap().scales(1.5, 1., 2);
+        assert!(Quilter::new(source.clone(), params).quilt_image().is_err());
+
+        // The scale of each patch is recorded, and a large penalty restricts
+        // the candidates to the nominal scale
+        let params = QuilterParams::new((20, 20), (8, 8), (2, 2), None, None, Distance::L1).unwrap()
+                                   .scales(2., 2., 1);
+        let mut quilter = Quilter::new(source.clone(), params);
+        quilter.quilt_image().unwrap();
+        assert_eq!(quilter.placements().len(), 16);
+        assert!(quilter.placements().iter().all(|p| p.scale == 2.));
+        assert_eq!(quilter.placements()[5].coords, (6, 6));
+        let params = QuilterParams::new((20, 20), (8, 8), (2, 2), Some((3, 2)), None, Distance::L1).unwrap()
+                                   .scales(1., 2., 3)
+                                   .scale_penalty(1e9);
+        let mut quilter = Quilter::new(source, params);
+        quilter.quilt_image().unwrap();
+        assert!(quilter.placements().iter().all(|p| p.scale == 1.));
+        assert_eq!(quilter.placements()[0].source_coords, (3, 2));
+    }
+
+    #[test]
+    fn test_refinement_passes() {
+        let source = noise(16, 16);
+        let params = QuilterParams::new((20, 20), (8, 8), (2, 2), None, None, Distance::L1).unwrap().passes(3, 0.75);
+        let mut quilter = Quilter::new(source.clone(), params);
+        assert_eq!(quilter.block_sizes(3, 0.75).unwrap(), vec!(((8, 8), (2, 2)), ((6, 6), (2, 2)), ((5, 5), (2, 2))));
+        assert!(quilter.block_sizes(0, 0.75).is_err());
+        assert!(quilter.block_sizes(2, 1.).is_err());
+        assert!(quilter.block_sizes(5, 0.5).is_err());
+
+        // The placements are those of the last pass
+        let res = quilter.quilt_image().unwrap();
+        assert_eq!(res.dimensions(), (20, 20));
+        assert_eq!(quilter.placements().len(), 49);
+        assert_eq!(quilter.params.patch_size, (8, 8));
+
+        // Each pass must tile the output
+        let params = QuilterParams::new((24, 24), (8, 8), (2, 2), None, None, Distance::L1).unwrap()
+                                   .tileable(true)
+                                   .passes(2, 0.9);
+        assert!(Quilter::new(source, params).quilt_image().is_err());
+    }
+
+    #[test]
+    fn test_wavefronts() {
+        let source = noise(24, 24);
+        let params = QuilterParams::new((40, 40), (8, 8), (2, 2), None, None, Distance::L1).unwrap();
+        let quilter = Quilter::new(source.clone(), params);
+        let patches: Vec<(u32, u32)> = (0..7).flat_map(|y| (0..7).map(move |x| (x, y))).skip(1).collect();
+        let waves = quilter.wavefronts(&patches, (48, 48));
+        assert_eq!(waves.len(), 18);
+        assert_eq!(waves[0], vec!((1, 0)));
+        assert_eq!(waves[1], vec!((2, 0), (0, 1)));
+        assert_eq!(waves[2], vec!((3, 0), (1, 1)));
+
+        // Concurrent quilting gives the same result as sequential quilting
+        for &(tileable, seam) in &[(false, SeamMethod::MinimumErrorBoundary), (true, SeamMethod::GraphCut)] {
+            let quilt = |wavefront: bool| {
+                let params = QuilterParams::new((36, 36), (8, 8), (2, 2), None, None, Distance::L1).unwrap()
+                                           .tileable(tileable)
+                                           .seam(seam)
+                                           .random_seed(42)
+                                           .wavefront(wavefront);
+                let mut quilter = Quilter::new(source.clone(), params);
+                let res = quilter.quilt_image().unwrap();
+                (res.into_raw(), quilter.placements().to_vec())
+            };
+            let sequential = quilt(false);
+            assert!(sequential == quilt(true));
+            assert!(sequential == quilt(true));
+        }
+    }
+
+    #[test]
+    fn test_random_placement() {
+        let source = noise(24, 24);
+        for &tileable in &[false, true] {
+            let quilt = |refinements: u32| {
+                let params = QuilterParams::new((36, 36), (8, 8), (2, 2), None, None, Distance::L1).unwrap()
+                                           .tileable(tileable)
+                                           .random_seed(7)
+                                           .placement(PatchPlacement::Random { offsets: 4, refinements: refinements });
+                let mut quilter = Quilter::new(source.clone(), params);
+                let res = quilter.quilt_image().unwrap();
+                (res, quilter.placements().to_vec(), quilter.source_map().unwrap().clone())
+            };
+            let (res, placements, map) = quilt(0);
+            // Every pixel is copied from the source, and the patches are off the grid
+            for y in 0..36 {
+                for x in 0..36 {
+                    let p = map.get(x, y).unwrap();
+                    assert_eq!(res.get_pixel(x, y), source.get_pixel(p.coords.0, p.coords.1));
+                }
+            }
+            assert!(placements.iter().any(|p| p.coords.0 % 6 != 0 || p.coords.1 % 6 != 0));
+            assert!(quilt(0).1 == placements);
+
+            // Refinements quilt more patches over the existing ones
+            let refined = quilt(3).1;
+            assert_eq!(refined.len(), placements.len() + 3);
+            assert!(refined[..placements.len()] == placements[..]);
+        }
+
+        let params = QuilterParams::new((36, 36), (8, 8), (2, 2), None, None, Distance::L1).unwrap()
+                                   .placement(PatchPlacement::Random { offsets: 0, refinements: 0 });
+        assert!(Quilter::new(source, params).quilt_image().is_err());
+    }
+
+    #[test]
+    fn test_transfer_texture() {
+        // Left half of the source is black, right half is white. With the
+        // correspondence error only, a black target is rendered with black
+        // patches only.
+        let source = RgbImage::from_fn(16, 16, |x, _| if x < 8 { Rgb { data: [0, 0, 0] } }
+                                                     else { Rgb { data: [255, 255, 255] } });
+        let target = RgbImage::new(12, 12);
+        let params = QuilterParams::new((1, 1), (4, 4), (1, 1), None, None, Distance::L1).unwrap();
+        let transfer = TransferParams::new(luminance, 0., 2).unwrap();
+        let res = Quilter::new(source, params).transfer_texture(&target, &transfer).unwrap();
+        assert_eq!(res.dimensions(), (12, 12));
+        assert!(res.pixels().all(|p| p.data == [0, 0, 0]));
+
+        assert!(TransferParams::new(luminance, 1.5, 1).is_err());
+        assert!(TransferParams::new(luminance, 0.5, 0).is_err());
+    }
+
+    #[test]
+    fn test_selection_chance() {
+        let source = noise(16, 16);
+        let params = QuilterParams::new((20, 20), (8, 8), (2, 2), None, Some(0.5), Distance::L1).unwrap();
+        let res = Quilter::new(source.clone(), params).quilt_image().unwrap();
+        assert_eq!(res.dimensions(), (20, 20));
+        let params = QuilterParams::new((20, 20), (8, 8), (2, 2), None, Some(1.), Distance::L1).unwrap();
+        assert!(Quilter::new(source, params).quilt_image().is_ok());
+        assert!(QuilterParams::new((20, 20), (8, 8), (2, 2), None, Some(1.5), Distance::L1).is_err());
+    }
+
+    #[test]
+    fn test_candidate_errors() {
+        let mut rng = thread_rng();
+        let mut noise = |_, _| Rgb { data: [rng.gen(), rng.gen(), rng.gen()] };
+        let source = RgbImage::from_fn(20, 17, &mut noise);
+        let params = QuilterParams::new((1, 1), (7, 5), (2, 1), None, None, Distance::L2Squared).unwrap();
+        let mut quilter = Quilter::new(source, params);
+        quilter.spectra_opt = Some(SourceSpectra::new(&quilter.source));
+        quilter.buffer_opt = Some(RgbImage::from_fn(14, 14, &mut noise));
+        quilter.state_opt = Some(GrayImage::from_pixel(14, 14, Luma { data: [PIXEL_QUILTED] }));
+        let area = OverlapArea { top: true, left: true, right: true, ..Default::default() };
+
+        // With a complete overlap area, then with empty pixels in it. The
+        // patch wraps around the buffer edges.
+        for &empty in &[false, true] {
+            if empty {
+                let state = quilter.state_opt.as_mut().unwrap();
+                state.put_pixel(11, 10, Luma { data: [PIXEL_EMPTY] });
+                state.put_pixel(1, 12, Luma { data: [PIXEL_EMPTY] });
+            }
+            let errors = quilter.candidate_errors(area, (10, 10));
+            assert_eq!(errors.len(), 14 * 13);
+            for y in 0..13 {
+                for x in 0..14 {
+                    let expected = quilter.patch_error(area, &Patch { coords: (x, y), size: (7, 5) }, (10, 10));
+                    assert_relative_eq!(errors[(y * 14 + x) as usize], expected, max_relative = 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_minimum_cost_paths() {
+        // Cheap diagonal through a 3 pixels wide vertical strip
+        let mut err_surf = ErrorSurface::from_pixel(6, 6, Luma { data: [10.] });
+        for &(x, y) in &[(0, 0), (1, 1), (2, 2), (2, 3), (1, 4), (1, 5)] {
+            err_surf.put_pixel(x, y, Luma { data: [1.] });
+        }
+        assert_eq!(minimum_cost_vertical_path(&err_surf, 0, 3),
+                   vec!((1, 5), (1, 4), (2, 3), (2, 2), (1, 1), (0, 0)));
+
+        // Same path through the transposed surface
+        let transposed = ErrorSurface::from_fn(6, 6, |x, y| *err_surf.get_pixel(y, x));
+        assert_eq!(minimum_cost_horizontal_path(&transposed, 0, 3),
+                   vec!((5, 1), (4, 1), (3, 2), (2, 2), (1, 1), (0, 0)));
+    }
+}
+
+#[cfg(all(feature = "nightly", test))]
+mod benches {
+    use super::*;
+    use test::Bencher;
+
+    const PATCH_SIZE: u32 = 512;
+    const OVERLAP: u32 = 96;
+
+    /// Quilter with a noisy source and a buffer whose top and left overlap
+    /// areas are filled, ready to quilt a patch at (step, step).
+    fn quilter() -> Quilter {
+        let mut rng = thread_rng();
+        let size = 2 * PATCH_SIZE;
+        let mut noise = |_, _| Rgb { data: [rng.gen(), rng.gen(), rng.gen()] };
+        let source = RgbImage::from_fn(PATCH_SIZE, PATCH_SIZE, &mut noise);
+        let params = QuilterParams::new((size, size), (PATCH_SIZE, PATCH_SIZE), (OVERLAP, OVERLAP), None, None, Distance::L1).unwrap();
+        let mut quilter = Quilter::new(source, params);
+        quilter.buffer_opt = Some(RgbImage::from_fn(size, size, &mut noise));
+        quilter.state_opt = Some(GrayImage::from_pixel(size, size, Luma { data: [PIXEL_QUILTED] }));
+        quilter
+    }
+
+    /// The seam step runs once per quilted patch: error surface, minimum
+    /// cost paths and cut mask. Takes about 2 ms, down from 24 ms with the
+    /// former recursive computation of the minimum cost paths.
+    #[bench]
+    fn bench_seam(b: &mut Bencher) {
+        let quilter = quilter();
+        let area = OverlapArea { top: true, left: true, ..Default::default() };
+        let patch = Patch { coords: (0, 0), size: (PATCH_SIZE, PATCH_SIZE) };
+        let coords = (PATCH_SIZE - OVERLAP, PATCH_SIZE - OVERLAP);
+        b.iter(|| {
+            let err_surf = quilter.patch_error_surface(area, &patch, coords);
+            quilter.patch_cut_mask(&err_surf, area)
+        });
+    }
+
+    /// The candidate selection evaluates this once per source position for
+    /// every quilted patch. The seam step should cost about as much as a few
+    /// candidates.
+    #[bench]
+    fn bench_candidate_error(b: &mut Bencher) {
+        let quilter = quilter();
+        let area = OverlapArea { top: true, left: true, ..Default::default() };
+        let patch = Patch { coords: (0, 0), size: (PATCH_SIZE, PATCH_SIZE) };
+        let coords = (PATCH_SIZE - OVERLAP, PATCH_SIZE - OVERLAP);
+        b.iter(|| quilter.patch_error(area, &patch, coords));
+    }
+
+    /// Whole synthesis of a 3x3 grid of patches with the fast candidate
+    /// search, eight of which are cut. Patches are a quarter of the size of
+    /// the other benchmarks so that it runs enough times, benchmark.sh times
+    /// the same synthesis at full size.
+    ///
+    /// Measured with a 928x928 noise source, the full size synthesis takes
+    /// 5.1 s, of which the 8 seams take about 16 ms (0.3%): the candidate
+    /// search dominates. This benchmark takes about 200 ms.
+    #[bench]
+    fn bench_quilt_image(b: &mut Bencher) {
+        let (patch_size, overlap) = (PATCH_SIZE / 4, OVERLAP / 4);
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let source = RgbImage::from_fn(patch_size * 3 / 2, patch_size * 3 / 2, |_, _| Rgb { data: [rng.gen(), rng.gen(), rng.gen()] });
+        let size = 2 * patch_size - overlap;
+        b.iter(|| {
+            let params = QuilterParams::new((size, size), (patch_size, patch_size), (overlap, overlap), None, None,
+                                            Distance::L2Squared).unwrap().random_seed(0);
+            Quilter::new(source.clone(), params).quilt_image().unwrap()
+        });
+    }
 }
@@ -0,0 +1,311 @@
+//! Generation of Wang tile sets from an exemplar, as described by Cohen et al.
+use image::*;
+use rand::{Rng, SeedableRng, XorShiftRng, thread_rng};
+use rayon::prelude::*;
+
+use std::f64;
+
+use distance::DistanceFunction;
+use errors::*;
+use super::quilt::{ErrorSurface, minimum_cost_vertical_path};
+
+/// Describes the parameters of the `WangTiler` type.
+pub struct WangTilerParams {
+    tile_size: u32,
+    colors: (u32, u32),
+    overlap: u32,
+    distance_func: DistanceFunction,
+    random_seed: Option<u64>
+}
+
+impl WangTilerParams {
+    /// Create a new `WangTilerParams`
+    ///
+    /// * `tile_size`: Width and height of the tiles. Must be even.
+    /// * `colors`: Number of colors of the horizontal (north and south) and
+    ///   vertical (east and west) edges. The set contains one tile for each
+    ///   combination of edge colors.
+    /// * `overlap`: Maximum distance between the seams inside a tile and its
+    ///   diagonals
+    /// * `distance_func`: Distance function used to compute the seams
+    pub fn new(tile_size: u32, colors: (u32, u32), overlap: u32, distance_func: DistanceFunction)
+               -> Result<WangTilerParams> {
+        if tile_size == 0 || tile_size % 2 != 0 {
+            bail!(ErrorKind::InvalidArguments("Tile size must be even".to_owned()))
+        }
+        if colors.0 == 0 || colors.1 == 0 {
+            bail!(ErrorKind::InvalidArguments("Each edge direction needs at least one color".to_owned()))
+        }
+        Ok(WangTilerParams { tile_size: tile_size, colors: colors, overlap: overlap, distance_func: distance_func,
+                             random_seed: None })
+    }
+
+    /// Seed of the choice of the edge samples. A given seed always produces
+    /// the same tile set. Defaults to a random seed.
+    pub fn random_seed(mut self, seed: u64) -> WangTilerParams {
+        self.random_seed = Some(seed);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Tile of a Wang tile set. Tiles with the same color on facing edges join
+/// seamlessly.
+pub struct WangTile {
+    /// Color of the north edge
+    pub north: u32,
+    /// Color of the east edge
+    pub east: u32,
+    /// Color of the south edge
+    pub south: u32,
+    /// Color of the west edge
+    pub west: u32,
+    /// Tile image
+    pub image: RgbImage
+}
+
+#[derive(Debug, Clone)]
+/// Complete set of Wang tiles, with one tile for each combination of edge
+/// colors.
+///
+/// The tiles are packed in an atlas whose rows correspond to the pairs of
+/// north and south colors and whose columns correspond to the pairs of west
+/// and east colors. The tile of colors `(n, e, s, w)` lies in row
+/// `n * horizontal_colors + s` and column `w * vertical_colors + e`, and the
+/// tiles are stored in the same row-major order.
+pub struct WangTileSet {
+    tile_size: u32,
+    colors: (u32, u32),
+    tiles: Vec<WangTile>
+}
+
+impl WangTileSet {
+    /// Tiles of the set, in the row-major order of the atlas.
+    pub fn tiles(&self) -> &[WangTile] {
+        &self.tiles
+    }
+
+    /// Width and height of the tiles.
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+
+    /// Number of columns and rows of tiles in the atlas.
+    pub fn atlas_layout(&self) -> (u32, u32) {
+        (self.colors.1 * self.colors.1, self.colors.0 * self.colors.0)
+    }
+
+    /// Pixel coordinates of the top left corner of a tile in the atlas.
+    pub fn atlas_coords(&self, index: usize) -> (u32, u32) {
+        let columns = self.atlas_layout().0;
+        ((index as u32 % columns) * self.tile_size, (index as u32 / columns) * self.tile_size)
+    }
+
+    /// Pack the tiles into a single image.
+    pub fn atlas(&self) -> RgbImage {
+        let (columns, rows) = self.atlas_layout();
+        let mut atlas = RgbImage::new(columns * self.tile_size, rows * self.tile_size);
+        for (i, tile) in self.tiles.iter().enumerate() {
+            let (ax, ay) = self.atlas_coords(i);
+            for (x, y, pixel) in tile.image.enumerate_pixels() {
+                atlas.put_pixel(ax + x, ay + y, *pixel);
+            }
+        }
+        atlas
+    }
+
+    /// Describe the tile set in JSON: the tile size, the number of colors of
+    /// each edge direction, the atlas layout, and the edge colors and atlas
+    /// coordinates of each tile.
+    pub fn to_json(&self) -> String {
+        let (columns, rows) = self.atlas_layout();
+        let tiles = self.tiles.iter().enumerate().map(|(i, tile)| {
+            let (x, y) = self.atlas_coords(i);
+            format!("    {{ \"index\": {}, \"north\": {}, \"east\": {}, \"south\": {}, \"west\": {}, \"x\": {}, \"y\": {} }}",
+                    i, tile.north, tile.east, tile.south, tile.west, x, y)
+        }).collect::<Vec<_>>();
+
+        format!("{{\n  \"tile_size\": {},\n  \"horizontal_colors\": {},\n  \"vertical_colors\": {},\n  \
+                 \"atlas\": {{ \"columns\": {}, \"rows\": {}, \"width\": {}, \"height\": {} }},\n  \"tiles\": [\n{}\n  ]\n}}\n",
+                self.tile_size, self.colors.0, self.colors.1, columns, rows, columns * self.tile_size,
+                rows * self.tile_size, tiles.join(",\n"))
+    }
+}
+
+/// Generates Wang tile sets with the method of Cohen et al.
+///
+/// One square sample of the source image is chosen for each edge color. A
+/// tile is made of four triangles separated by its diagonals, each copied
+/// from the sample of the color of the facing edge, so that the two halves of
+/// a sample meet on the edge shared by neighbouring tiles. Inside a tile,
+/// the triangles are cut along minimum error boundaries near the diagonals,
+/// which meet the diagonals at the center and at the corners of the tile so
+/// that the edges are left untouched.
+pub struct WangTiler {
+    source: RgbImage,
+    params: WangTilerParams
+}
+
+impl WangTiler {
+    /// Create a new `WangTiler` with the specified source image.
+    pub fn new(source: RgbImage, params: WangTilerParams) -> WangTiler {
+        WangTiler { source: source, params: params }
+    }
+
+    /// Generate a complete tile set.
+    pub fn generate(&mut self) -> Result<WangTileSet> {
+        let size = self.params.tile_size;
+        if size > self.source.width() || size > self.source.height() {
+            bail!(ErrorKind::InvalidArguments("Tile size must not exceed the source image size".to_owned()))
+        }
+
+        let seed = self.params.random_seed.unwrap_or_else(|| thread_rng().gen());
+        let mut rng = XorShiftRng::from_seed([seed as u32, (seed >> 32) as u32, 0x2545f491, 0x4f6cdd1d]);
+        let (hc, vc) = self.params.colors;
+        let (hs, vs) = (self.samples(hc, &mut rng), self.samples(vc, &mut rng));
+
+        let (columns, rows) = (vc * vc, hc * hc);
+        let tiles = (0..columns * rows).into_par_iter().map(|i| {
+            let (column, row) = (i % columns, i / columns);
+            let (north, south, west, east) = (row / hc, row % hc, column / vc, column % vc);
+            let image = self.tile(&hs[north as usize], &vs[east as usize], &hs[south as usize], &vs[west as usize]);
+            WangTile { north: north, east: east, south: south, west: west, image: image }
+        }).collect();
+
+        Ok(WangTileSet { tile_size: size, colors: self.params.colors, tiles: tiles })
+    }
+
+    /// Pick random square samples of the source image.
+    fn samples(&self, n: u32, rng: &mut XorShiftRng) -> Vec<RgbImage> {
+        let size = self.params.tile_size;
+        (0..n).map(|_| {
+            let x = rng.gen_range(0, self.source.width() - size + 1);
+            let y = rng.gen_range(0, self.source.height() - size + 1);
+            RgbImage::from_fn(size, size, |dx, dy| *self.source.get_pixel(x + dx, y + dy))
+        }).collect()
+    }
+
+    /// Maximum distance between a seam and the diagonal in the specified row
+    /// of the upper half of a tile. The seams start on the diagonals at the
+    /// corners and at the center of the tile, and don't cut the tile edges.
+    fn seam_range(&self, row: u32) -> i64 {
+        let half = self.params.tile_size / 2;
+        self.params.overlap.min(row.saturating_sub(1)).min(half - 1 - row) as i64
+    }
+
+    /// Compute the seam between two triangles of the upper half of a tile,
+    /// near the diagonal whose first column on the right side is given for
+    /// each row. Returns the first column on the right side of the seam in
+    /// each row.
+    fn seam<F, G, H>(&self, left: F, right: G, diagonal: H) -> Vec<u32>
+        where F: Fn(u32, u32) -> Rgb<u8>, G: Fn(u32, u32) -> Rgb<u8>, H: Fn(u32) -> u32 {
+        let half = self.params.tile_size / 2;
+        let overlap = self.params.overlap as i64;
+        let dist = self.params.distance_func;
+
+        // Error surface sheared along the diagonal
+        let err_surf = ErrorSurface::from_fn(2 * overlap as u32 + 1, half, |u, row| {
+            let d = u as i64 - overlap;
+            if d.abs() > self.seam_range(row) { return Luma { data: [f64::INFINITY] }; }
+            let x = (diagonal(row) as i64 + d) as u32;
+            Luma { data: [dist(&left(x, row), &right(x, row))] }
+        });
+
+        let mut columns = vec![0; half as usize];
+        for (u, row) in minimum_cost_vertical_path(&err_surf, 0, 2 * overlap as u32 + 1) {
+            columns[row as usize] = (diagonal(row) as i64 + u as i64 - overlap) as u32;
+        }
+        columns
+    }
+
+    /// Assemble a tile from the samples of its edge colors.
+    fn tile(&self, north: &RgbImage, east: &RgbImage, south: &RgbImage, west: &RgbImage) -> RgbImage {
+        let size = self.params.tile_size;
+        let half = size / 2;
+        // Pixels of each sample, in tile coordinates
+        let n = |x: u32, y: u32| *north.get_pixel(x, y + half);
+        let s = |x: u32, y: u32| *south.get_pixel(x, y - half);
+        let w = |x: u32, y: u32| *west.get_pixel(x + half, y);
+        let e = |x: u32, y: u32| *east.get_pixel(x - half, y);
+        // Row of the lower half of the tile mirroring a row of its upper half
+        let mirror = |row: u32| size - 1 - row;
+
+        let north_west = self.seam(&w, &n, |row| row);
+        let north_east = self.seam(&n, &e, |row| size - row);
+        let south_west = self.seam(|x, row| w(x, mirror(row)), |x, row| s(x, mirror(row)), |row| row);
+        let south_east = self.seam(|x, row| s(x, mirror(row)), |x, row| e(x, mirror(row)), |row| size - row);
+
+        RgbImage::from_fn(size, size, |x, y| {
+            if y < half {
+                let row = y as usize;
+                if x < north_west[row] { w(x, y) } else if x < north_east[row] { n(x, y) } else { e(x, y) }
+            }
+            else {
+                let row = mirror(y) as usize;
+                if x < south_west[row] { w(x, y) } else if x < south_east[row] { s(x, y) } else { e(x, y) }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use distance::l1;
+
+    #[test]
+    fn test_wang_tiles() {
+        // Each source pixel holds its coordinates, so that the continuity of
+        // the tiles across their edges can be checked
+        let source = RgbImage::from_fn(64, 64, |x, y| Rgb { data: [x as u8, y as u8, 0] });
+        let params = WangTilerParams::new(16, (2, 2), 3, l1).unwrap().random_seed(7);
+        let set = WangTiler::new(source, params).generate().unwrap();
+        assert_eq!(set.tiles().len(), 16);
+        assert_eq!(set.atlas_layout(), (4, 4));
+        assert_eq!(set.atlas().dimensions(), (64, 64));
+        assert_eq!(set.atlas_coords(6), (32, 16));
+        let tile = &set.tiles()[6];
+        assert_eq!((tile.north, tile.east, tile.south, tile.west), (0, 0, 1, 1));
+        assert!(set.to_json().contains("{ \"index\": 6, \"north\": 0, \"east\": 0, \"south\": 1, \"west\": 1, \"x\": 32, \"y\": 16 }"));
+
+        // Neighbouring pixels across matching edges are neighbours in the source
+        for a in set.tiles() {
+            for b in set.tiles() {
+                if a.south == b.north {
+                    for x in 1..15 {
+                        let (p, q) = (a.image.get_pixel(x, 15).data, b.image.get_pixel(x, 0).data);
+                        assert_eq!((p[0], p[1] + 1), (q[0], q[1]));
+                    }
+                }
+                if a.east == b.west {
+                    for y in 1..15 {
+                        let (p, q) = (a.image.get_pixel(15, y).data, b.image.get_pixel(0, y).data);
+                        assert_eq!((p[0] + 1, p[1]), (q[0], q[1]));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_seams_follow_diagonals() {
+        // Without any overlap, the triangles are cut along the diagonals
+        let source = RgbImage::from_fn(8, 8, |x, y| Rgb { data: [x as u8, y as u8, 0] });
+        let params = WangTilerParams::new(8, (1, 1), 0, l1).unwrap();
+        let tiler = WangTiler::new(source, params);
+        let north = RgbImage::from_pixel(8, 8, Rgb { data: [1, 0, 0] });
+        let east = RgbImage::from_pixel(8, 8, Rgb { data: [2, 0, 0] });
+        let south = RgbImage::from_pixel(8, 8, Rgb { data: [3, 0, 0] });
+        let west = RgbImage::from_pixel(8, 8, Rgb { data: [4, 0, 0] });
+        let tile = tiler.tile(&north, &east, &south, &west);
+        let rows = (0..8).map(|y| (0..8).map(|x| tile.get_pixel(x, y).data[0]).collect::<Vec<_>>()).collect::<Vec<_>>();
+        assert_eq!(rows, vec!(vec!(1, 1, 1, 1, 1, 1, 1, 1),
+                              vec!(4, 1, 1, 1, 1, 1, 1, 2),
+                              vec!(4, 4, 1, 1, 1, 1, 2, 2),
+                              vec!(4, 4, 4, 1, 1, 2, 2, 2),
+                              vec!(4, 4, 4, 3, 3, 2, 2, 2),
+                              vec!(4, 4, 3, 3, 3, 3, 2, 2),
+                              vec!(4, 3, 3, 3, 3, 3, 3, 2),
+                              vec!(3, 3, 3, 3, 3, 3, 3, 3)));
+    }
+}
@@ -0,0 +1,240 @@
+//! Implementation of the Hertzmann et al. image analogies algorithm.
+use image::RgbImage;
+use image::imageops::{resize, FilterType};
+use rayon::prelude::*;
+
+use std::cmp::min;
+
+use common::OrderedFloat;
+use distance::l2;
+use errors::*;
+
+/// Size of the neighbourhoods compared at the coarser level of the pyramid.
+const COARSE_WINDOW_SIZE: u32 = 3;
+
+/// Build a gaussian pyramid of the specified image. The first level is the
+/// image itself, and each subsequent level is half the size of the previous one.
+fn gaussian_pyramid(img: &RgbImage, levels: u32) -> Vec<RgbImage> {
+    let mut pyramid = vec!(img.clone());
+    for _ in 1..levels {
+        let next = {
+            let prev = pyramid.last().unwrap();
+            resize(prev, prev.width() / 2, prev.height() / 2, FilterType::Triangle)
+        };
+        pyramid.push(next);
+    }
+    pyramid
+}
+
+/// Parameters of the image analogies algorithm.
+pub struct ImageAnalogyParams {
+    levels: u32,
+    window_size: u32,
+    coherence: f64
+}
+
+impl ImageAnalogyParams {
+    /// Create a new `ImageAnalogyParams`
+    ///
+    /// * `levels`: number of levels of the gaussian pyramids
+    /// * `window_size`: size of the neighbourhoods compared at each level. Must be an odd number.
+    /// * `coherence`: coherence weight. Higher values favor copying contiguous
+    /// regions of the filtered exemplar over closer neighbourhood matches.
+    pub fn new(levels: u32, window_size: u32, coherence: f64) -> Result<ImageAnalogyParams> {
+        if levels == 0 {
+            bail!(ErrorKind::InvalidArguments("Number of levels can't be zero".to_owned()))
+        }
+        if window_size % 2 == 0 {
+            bail!(ErrorKind::InvalidArguments("window_size must be odd".to_owned()))
+        }
+        if coherence < 0. {
+            bail!(ErrorKind::InvalidArguments("Coherence weight can't be negative".to_owned()))
+        }
+        Ok(ImageAnalogyParams { levels: levels, window_size: window_size, coherence: coherence })
+    }
+}
+
+/// Images of one level of the pyramids, along with the level above it, if any.
+struct Level<'a> {
+    a: &'a RgbImage,
+    a_filtered: &'a RgbImage,
+    b: &'a RgbImage,
+    b_filtered: &'a RgbImage,
+    coarse: Option<(&'a RgbImage, &'a RgbImage, &'a RgbImage, &'a RgbImage)>
+}
+
+/// Implements the Hertzmann et al. image analogies algorithm: given an
+/// unfiltered image A and its filtered version A', synthesize the filtered
+/// version B' of a new image B such that A : A' :: B : B'.
+pub struct ImageAnalogy {
+    params: ImageAnalogyParams,
+    unfiltered: RgbImage,
+    filtered: RgbImage
+}
+
+impl ImageAnalogy {
+    /// Create a new `ImageAnalogy` from an unfiltered exemplar and its
+    /// filtered version.
+    pub fn new(unfiltered: RgbImage, filtered: RgbImage, params: ImageAnalogyParams) -> Result<ImageAnalogy> {
+        if unfiltered.dimensions() != filtered.dimensions() {
+            bail!(ErrorKind::InvalidArguments("Unfiltered and filtered exemplar dimensions differ".to_owned()))
+        }
+        Ok(ImageAnalogy { params: params, unfiltered: unfiltered, filtered: filtered })
+    }
+
+    /// Apply the filter of the exemplar pair to the target image.
+    pub fn apply(&self, target: &RgbImage) -> Result<RgbImage> {
+        let coarsest = 1 << (self.params.levels - 1);
+        for &(w, h) in &[self.unfiltered.dimensions(), target.dimensions()] {
+            if w < coarsest || h < coarsest {
+                bail!(ErrorKind::InvalidArguments("Images are too small for the number of pyramid levels".to_owned()))
+            }
+        }
+
+        let a = gaussian_pyramid(&self.unfiltered, self.params.levels);
+        let a_filtered = gaussian_pyramid(&self.filtered, self.params.levels);
+        let b = gaussian_pyramid(target, self.params.levels);
+        let mut b_filtered: Vec<RgbImage> = b.iter().map(|img| RgbImage::new(img.width(), img.height())).collect();
+
+        // Synthesize from the coarsest level to the finest
+        for l in (0..self.params.levels as usize).rev() {
+            let mut level_img = RgbImage::new(b[l].width(), b[l].height());
+            let mut source_map = vec!((0, 0); (b[l].width() * b[l].height()) as usize);
+            let kappa = 1. + self.params.coherence / (1 << l) as f64;
+            for y in 0..b[l].height() {
+                for x in 0..b[l].width() {
+                    let p = {
+                        let level = Level { a: &a[l], a_filtered: &a_filtered[l], b: &b[l], b_filtered: &level_img,
+                                            coarse: if l + 1 < a.len() { Some((&a[l + 1], &a_filtered[l + 1], &b[l + 1], &b_filtered[l + 1])) }
+                                                    else { None } };
+                        self.best_match(&level, &source_map, (x, y), kappa)
+                    };
+                    level_img.put_pixel(x, y, *a_filtered[l].get_pixel(p.0, p.1));
+                    source_map[(y * b[l].width() + x) as usize] = p;
+                }
+            }
+            b_filtered[l] = level_img;
+            println!("Done level {}", l);
+        }
+
+        Ok(b_filtered.swap_remove(0))
+    }
+
+    /// Find the pixel of A' to be copied at the specified pixel of B', by
+    /// comparing the best approximate match to the best coherent match.
+    fn best_match(&self, level: &Level, source_map: &[(u32, u32)], q: (u32, u32), kappa: f64) -> (u32, u32) {
+        let (aw, ah) = level.a.dimensions();
+        let bw = level.b.width();
+
+        // Best approximate match, by exhaustive search
+        let app = (0..ah).into_par_iter().flat_map(|y| (0..aw).into_par_iter().map(move |x| (x, y)))
+                         .filter_map(|p| self.feature_distance(level, p, q).map(|d| (p, OrderedFloat::try_from(d).unwrap())))
+                         .min_by_key(|&(_, d)| d);
+        let (p_app, d_app) = match app {
+            Some((p, d)) => (p, d.as_float()),
+            None => return (min(q.0, aw - 1), min(q.1, ah - 1))
+        };
+
+        // Best coherent match: continue the source of the already synthesized
+        // pixels of the causal neighbourhood
+        let d = (self.params.window_size / 2) as i64;
+        let (qx, qy) = (q.0 as i64, q.1 as i64);
+        let mut coh: Option<((u32, u32), f64)> = None;
+        for y in -d..1 {
+            for x in -d..d + 1 {
+                if y == 0 && x >= 0 { break; }
+                let (rx, ry) = (qx + x, qy + y);
+                if rx < 0 || ry < 0 || rx >= bw as i64 { continue; }
+                let s = source_map[(ry as u32 * bw + rx as u32) as usize];
+                let (px, py) = (s.0 as i64 - x, s.1 as i64 - y);
+                if px < 0 || py < 0 || px >= aw as i64 || py >= ah as i64 { continue; }
+                let p = (px as u32, py as u32);
+                if let Some(dist) = self.feature_distance(level, p, q) {
+                    if coh.map_or(true, |(_, best)| dist < best) {
+                        coh = Some((p, dist));
+                    }
+                }
+            }
+        }
+
+        match coh {
+            Some((p_coh, d_coh)) if d_coh <= d_app * kappa => p_coh,
+            _ => p_app
+        }
+    }
+
+    /// Compute the distance between the feature vectors of pixel `p` of the
+    /// exemplar pair and pixel `q` of the target pair. The feature vector
+    /// contains the full neighbourhood in A, the causal neighbourhood in A',
+    /// and the full neighbourhoods in both images at the coarser level.
+    fn feature_distance(&self, level: &Level, p: (u32, u32), q: (u32, u32)) -> Option<f64> {
+        let mut error = 0.;
+        let mut n = 0;
+        {
+            let mut window = |a: &RgbImage, b: &RgbImage, p: (u32, u32), q: (u32, u32), size: u32, causal: bool| {
+                let d = (size / 2) as i64;
+                let (px, py, qx, qy) = (p.0 as i64, p.1 as i64, q.0 as i64, q.1 as i64);
+                for y in -d..d + 1 {
+                    for x in -d..d + 1 {
+                        if causal && (y > 0 || (y == 0 && x >= 0)) { return; }
+                        let (ax, ay, bx, by) = (px + x, py + y, qx + x, qy + y);
+                        if ax < 0 || ay < 0 || ax >= a.width() as i64 || ay >= a.height() as i64 { continue; }
+                        if bx < 0 || by < 0 || bx >= b.width() as i64 || by >= b.height() as i64 { continue; }
+                        error += l2(a.get_pixel(ax as u32, ay as u32), b.get_pixel(bx as u32, by as u32));
+                        n += 1;
+                    }
+                }
+            };
+
+            window(level.a, level.b, p, q, self.params.window_size, false);
+            window(level.a_filtered, level.b_filtered, p, q, self.params.window_size, true);
+            if let Some((ca, caf, cb, cbf)) = level.coarse {
+                let half = |c: (u32, u32), img: &RgbImage| (min(c.0 / 2, img.width() - 1), min(c.1 / 2, img.height() - 1));
+                let (cp, cq) = (half(p, ca), half(q, cb));
+                window(ca, cb, cp, cq, COARSE_WINDOW_SIZE, false);
+                window(caf, cbf, cp, cq, COARSE_WINDOW_SIZE, false);
+            }
+        }
+
+        match n {
+            0 => None,
+            _ => Some(error / n as f64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    fn invert(img: &RgbImage) -> RgbImage {
+        RgbImage::from_fn(img.width(), img.height(), |x, y| {
+            let p = img.get_pixel(x, y);
+            Rgb { data: [255 - p.data[0], 255 - p.data[1], 255 - p.data[2]] }
+        })
+    }
+
+    #[test]
+    fn test_analogy() {
+        // Learn color inversion, and apply it to a crop of the unfiltered exemplar
+        let a = RgbImage::from_fn(8, 8, |x, y| Rgb { data: [(x * 30) as u8, (y * 30) as u8, 100] });
+        let b = RgbImage::from_fn(5, 5, |x, y| *a.get_pixel(x + 2, y + 1));
+
+        let params = ImageAnalogyParams::new(1, 3, 0.5).unwrap();
+        let analogy = ImageAnalogy::new(a.clone(), invert(&a), params).unwrap();
+        assert_eq!(analogy.apply(&b).unwrap().into_raw(), invert(&b).into_raw());
+
+        let params = ImageAnalogyParams::new(2, 3, 0.5).unwrap();
+        let analogy = ImageAnalogy::new(a.clone(), invert(&a), params).unwrap();
+        assert_eq!(analogy.apply(&b).unwrap().dimensions(), (5, 5));
+    }
+
+    #[test]
+    fn test_analogy_too_many_levels() {
+        let a = RgbImage::new(8, 8);
+        let params = ImageAnalogyParams::new(5, 3, 0.5).unwrap();
+        let analogy = ImageAnalogy::new(a.clone(), a.clone(), params).unwrap();
+        assert!(analogy.apply(&a).is_err());
+    }
+}
@@ -1,3 +1,5 @@
+mod analogy;
 mod search;
 
-pub use self::search::{PixelSearchParams, PixelSearch};
+pub use self::analogy::{ImageAnalogyParams, ImageAnalogy};
+pub use self::search::{PixelSearchParams, PixelSearch, SynthesisOrder};
@@ -1,20 +1,35 @@
-use image::{RgbImage, GrayImage, Luma, Rgb};
+use image::{RgbImage, GrayImage, Luma};
 use imageproc::drawing::draw_filled_rect_mut;
 use imageproc::rect::Rect as IPRect;
 use num_traits::Zero;
 use rand::{thread_rng, random, Rng};
+use rand::distributions::{Range, IndependentSample};
 use rayon::prelude::*;
 
 use std::cmp::min;
 
-use common::{OrderedFloat, blit_rect, Rect};
+use common::{OrderedFloat, blit_rect, Rect, Extension, SourceMap, SourcePixel, Transform, TransformSet};
 use distance::l2;
 use errors::*;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Order in which the pixels of the output image are synthesized.
+pub enum SynthesisOrder {
+    /// Grow the image outwards from a seed patch in the center of the image,
+    /// as described by Efros and Leung.
+    OnionPeeling,
+    /// Synthesize the pixels in raster scan order from an image initialized
+    /// with noise, using a causal L-shaped neighbourhood, as described by Wei
+    /// and Levoy.
+    Scanline
+}
+
 pub struct PixelSearchParams {
     size: (u32, u32),
     window_size: u32,
-    seed_coords: Option<(u32, u32)>
+    seed_coords: Option<(u32, u32)>,
+    order: SynthesisOrder,
+    transforms: TransformSet
 }
 
 /// Parameters of the Efros and Leung algorithm.
@@ -28,7 +43,21 @@ impl PixelSearchParams {
         if window_size % 2 == 0 {
             bail!(ErrorKind::InvalidArguments("window_size must be odd".to_owned()));
         }
-        Ok(PixelSearchParams { size: size, window_size: window_size, seed_coords: seed_coords })
+        Ok(PixelSearchParams { size: size, window_size: window_size, seed_coords: seed_coords,
+                               order: SynthesisOrder::OnionPeeling, transforms: TransformSet::None })
+    }
+
+    /// Set the order in which pixels are synthesized. Defaults to `SynthesisOrder::OnionPeeling`.
+    pub fn order(mut self, order: SynthesisOrder) -> PixelSearchParams {
+        self.order = order;
+        self
+    }
+
+    /// Set the transforms of the source neighbourhoods compared to the
+    /// neighbourhood of each synthesized pixel. Defaults to `TransformSet::None`.
+    pub fn transforms(mut self, transforms: TransformSet) -> PixelSearchParams {
+        self.transforms = transforms;
+        self
     }
 }
 
@@ -36,7 +65,10 @@ impl PixelSearchParams {
 pub struct PixelSearch {
     params: PixelSearchParams,
     source: RgbImage,
-    buffer_opt: Option<RgbImage>
+    source_mask: Option<GrayImage>,
+    buffer_opt: Option<RgbImage>,
+    source_map_opt: Option<SourceMap>,
+    transforms: Vec<Transform>
 }
 
 impl PixelSearch {
@@ -47,7 +79,38 @@ impl PixelSearch {
                 bail!(ErrorKind::InvalidArguments("Seed patch is outside source image".to_owned()));
             }
         }
-        Ok(PixelSearch { source: source, params: params, buffer_opt: None })
+        let transforms = params.transforms.transforms();
+        Ok(PixelSearch { source: source, params: params, source_mask: None, buffer_opt: None, source_map_opt: None,
+                         transforms: transforms })
+    }
+
+    /// Source pixel of each pixel of the image synthesized during the last
+    /// synthesis. The transform is the one under which the neighbourhood of
+    /// the source pixel matched best.
+    pub fn source_map(&self) -> Option<&SourceMap> {
+        self.source_map_opt.as_ref()
+    }
+
+    /// Exclude the pixels of the source image which are non zero in `mask`
+    /// from the neighbourhoods which may be sampled. This allows filling the
+    /// holes of an image with the image itself as source, see
+    /// `fill_image_holes`.
+    pub fn set_source_mask(&mut self, mask: GrayImage) {
+        self.source_mask = Some(mask);
+    }
+
+    /// Fill the holes of an image by sampling from the image itself, outside
+    /// of the holes. Use `set_source_mask` and `fill_holes` instead to also
+    /// get the source map.
+    ///
+    /// * `image`: image to be filled
+    /// * `hole_mask`: mask of the holes. Non zero pixels are synthesized.
+    /// * `window_size`: size of the search window. Must be an odd number.
+    pub fn fill_image_holes(image: &RgbImage, hole_mask: &GrayImage, window_size: u32) -> Result<RgbImage> {
+        let params = PixelSearchParams::new(image.dimensions(), window_size, None)?;
+        let mut ps = PixelSearch::new(image.clone(), params)?;
+        ps.set_source_mask(hole_mask.clone());
+        ps.fill_holes(image, hole_mask)
     }
 
     fn mask_on(mask: &GrayImage, x: u32, y: u32) -> bool {
@@ -61,32 +124,156 @@ impl PixelSearch {
         (if y != mask.height() - 1 { Self::mask_on(mask, x, y + 1) } else { false })
     }
 
-    /// Synthesize an image using the Efros and Leung method.
+    // Check whether a whole window of the source image lies outside of the
+    // holes of the mask. Otherwise, no neighbourhood may be sampled from the
+    // source image.
+    fn has_unmasked_window(mask: &GrayImage, window_size: u32) -> bool {
+        let (w, h) = mask.dimensions();
+        if w < window_size || h < window_size { return false; }
+        // Summed-area table of the masked pixels
+        let (sw, ws) = ((w + 1) as usize, window_size as usize);
+        let mut sat = vec![0u32; sw * (h + 1) as usize];
+        for y in 0..h as usize {
+            for x in 0..w as usize {
+                let masked = Self::mask_on(mask, x as u32, y as u32) as u32;
+                sat[(y + 1) * sw + x + 1] = masked + sat[y * sw + x + 1] + sat[(y + 1) * sw + x] - sat[y * sw + x];
+            }
+        }
+        (0..(h - window_size + 1) as usize).any(|y| (0..(w - window_size + 1) as usize).any(|x| {
+            sat[(y + ws) * sw + x + ws] + sat[y * sw + x] == sat[y * sw + x + ws] + sat[(y + ws) * sw + x]
+        }))
+    }
+
+    // Check whether a source pixel may be sampled, i.e. is not part of a hole
+    fn source_pixel_valid(&self, x: u32, y: u32) -> bool {
+        match self.source_mask {
+            Some(ref mask) => !Self::mask_on(mask, x, y),
+            None => true
+        }
+    }
+
+    /// Synthesize an image using the Efros and Leung method, or the Wei and
+    /// Levoy scanline method depending on the synthesis order.
     pub fn synthesize(&mut self) -> RgbImage {
+        match self.params.order {
+            SynthesisOrder::OnionPeeling => self.synthesize_onion_peeling(),
+            SynthesisOrder::Scanline => self.synthesize_scanline()
+        }
+    }
+
+    fn synthesize_onion_peeling(&mut self) -> RgbImage {
         let (w, h) = self.params.size;
         self.buffer_opt = Some(RgbImage::new(w, h));
+        self.source_map_opt = Some(SourceMap::new(w, h));
         let mut mask = GrayImage::new(w, h);
         draw_filled_rect_mut(&mut mask, IPRect::at((w / 2 - 1) as i32, (h / 2 - 1) as i32).of_size(3, 3), Luma { data: [255] });
 
         // Copy the initial seed to the center of the buffer and grow an image from there
         let (sx, sy) = (random::<u32>() % (self.source.width() - 3), random::<u32>() % (self.source.height() - 3));
-        blit_rect(self.buffer_opt.as_mut().unwrap(), &self.source, &Rect { coords: (sx, sy), size: (3, 3) }, (w / 2 - 1, w / 2 - 1));
+        blit_rect(self.buffer_opt.as_mut().unwrap(), &self.source, &Rect { coords: (sx, sy), size: (3, 3) }, (w / 2 - 1, h / 2 - 1));
+        for y in 0..3 {
+            for x in 0..3 {
+                let source = SourcePixel { coords: (sx + x, sy + y), transform: Transform::Identity };
+                self.source_map_opt.as_mut().unwrap().set(w / 2 - 1 + x, h / 2 - 1 + y, Some(source));
+            }
+        }
+
+        self.grow(&mut mask);
+        self.buffer_opt.take().unwrap()
+    }
 
+    /// Fill the holes of an image using the Efros and Leung method, growing
+    /// inwards from the boundaries of the holes. Neighbourhoods are sampled
+    /// from the source image. The synthesis size and order parameters are
+    /// ignored.
+    ///
+    /// * `image`: image to be filled
+    /// * `hole_mask`: mask of the holes. Non zero pixels are synthesized.
+    pub fn fill_holes(&mut self, image: &RgbImage, hole_mask: &GrayImage) -> Result<RgbImage> {
+        if image.dimensions() != hole_mask.dimensions() {
+            bail!(ErrorKind::InvalidArguments("Hole mask and image dimensions differ".to_owned()));
+        }
+        if hole_mask.pixels().all(|p| !p.data[0].is_zero()) {
+            bail!(ErrorKind::InvalidArguments("Hole mask covers the whole image".to_owned()));
+        }
+        if let Some(ref mask) = self.source_mask {
+            if mask.dimensions() != self.source.dimensions() {
+                bail!(ErrorKind::InvalidArguments("Source mask and source image dimensions differ".to_owned()));
+            }
+            if !Self::has_unmasked_window(mask, self.params.window_size) {
+                bail!(ErrorKind::InvalidArguments("No window of the source image lies outside of the holes".to_owned()));
+            }
+        }
+
+        // Known pixels are the ones outside of the holes
+        let mut mask = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+            if Self::mask_on(hole_mask, x, y) { Luma { data: [0] } } else { Luma { data: [255] } }
+        });
+        self.buffer_opt = Some(image.clone());
+        self.source_map_opt = Some(SourceMap::new(image.width(), image.height()));
+
+        self.grow(&mut mask);
+        Ok(self.buffer_opt.take().unwrap())
+    }
+
+    /// Extend an image beyond its borders using the Efros and Leung method.
+    /// The original image is left untouched, and the new pixels are grown
+    /// outwards from its edges. Neighbourhoods are sampled from the source
+    /// image, which may be the image itself.
+    pub fn extend_image(&mut self, image: &RgbImage, extension: Extension) -> Result<RgbImage> {
+        let (w, h) = image.dimensions();
+        let (out_w, out_h) = (w + extension.left + extension.right, h + extension.top + extension.bottom);
+        let mut canvas = RgbImage::new(out_w, out_h);
+        blit_rect(&mut canvas, image, &Rect { coords: (0, 0), size: (w, h) }, (extension.left, extension.top));
+        let hole_mask = GrayImage::from_fn(out_w, out_h, |x, y| {
+            let inside = x >= extension.left && x < extension.left + w && y >= extension.top && y < extension.top + h;
+            Luma { data: [if inside { 0 } else { 255 }] }
+        });
+
+        self.fill_holes(&canvas, &hole_mask)
+    }
+
+    // Synthesize the pixels which are off in the mask, growing from the ones which are on
+    fn grow(&mut self, mask: &mut GrayImage) {
         let mut n_pixels = mask.enumerate_pixels().filter(|&(_, _, p)| p.data[0].is_zero()).count();
         while n_pixels > 0 {
             // Find the next pixel to synthesize
             let next_pixel = mask.enumerate_pixels().collect::<Vec<_>>().into_par_iter()
-                                 .filter_map(|(x, y, p)| if p.data[0].is_zero() && Self::is_edge_pixel(&mask, x, y) { Some((x, y)) } else { None })
-                                 .map(|c| { (c, self.pixel_num_neigbours(&mask, c)) })
+                                 .filter_map(|(x, y, p)| if p.data[0].is_zero() && Self::is_edge_pixel(mask, x, y) { Some((x, y)) } else { None })
+                                 .map(|c| { (c, self.pixel_num_neigbours(mask, c)) })
                                  .max_by_key(|&(_, n)| n).unwrap().0;
 
             // Synthesize the pixel and mark it as done
-            let pixel = self.synthesize_pixel(&mask, next_pixel);
-            self.buffer_opt.as_mut().unwrap().put_pixel(next_pixel.0, next_pixel.1, pixel);
+            let source = self.synthesize_pixel(mask, next_pixel);
+            self.put_pixel(next_pixel, source);
             mask.put_pixel(next_pixel.0, next_pixel.1, Luma { data: [1] });
             n_pixels -= 1;
             println!("{} pixels left", n_pixels);
         }
+    }
+
+    fn synthesize_scanline(&mut self) -> RgbImage {
+        let (w, h) = self.params.size;
+
+        // Initialize the buffer with pixels picked at random in the source, so
+        // that the noise has the same histogram as the source
+        let mut rng = thread_rng();
+        let x_dist = Range::new(0, self.source.width());
+        let y_dist = Range::new(0, self.source.height());
+        let mut buffer = RgbImage::new(w, h);
+        for pixel in buffer.pixels_mut() {
+            *pixel = *self.source.get_pixel(x_dist.ind_sample(&mut rng), y_dist.ind_sample(&mut rng));
+        }
+        self.buffer_opt = Some(buffer);
+        self.source_map_opt = Some(SourceMap::new(w, h));
+
+        for y in 0..h {
+            for x in 0..w {
+                let source = self.select_pixel(|n| self.causal_neighbourhood_error((x, y), n));
+                self.put_pixel((x, y), source);
+            }
+            println!("{} rows left", h - y - 1);
+        }
 
         self.buffer_opt.take().unwrap()
     }
@@ -97,7 +284,7 @@ impl PixelSearch {
         let xs = if coords.0 <= d { 0 } else { coords.0 - d };
         let ys = if coords.1 <= d { 0 } else { coords.1 - d };
         let xe = min(mask.width() - 1, coords.0 + d) + 1; // +1 because for takes [a,b) ranges
-        let ye = min(mask.width() - 1, coords.1 + d) + 1;
+        let ye = min(mask.height() - 1, coords.1 + d) + 1;
 
         let mut neighbours = 0;
         for x in xs..xe {
@@ -111,42 +298,73 @@ impl PixelSearch {
         neighbours
     }
 
+    // Copy a source pixel to the buffer and record it in the source map
+    fn put_pixel(&mut self, coords: (u32, u32), source: SourcePixel) {
+        let pixel = *self.source.get_pixel(source.coords.0, source.coords.1);
+        self.buffer_opt.as_mut().unwrap().put_pixel(coords.0, coords.1, pixel);
+        self.source_map_opt.as_mut().unwrap().set(coords.0, coords.1, Some(source));
+    }
+
     // Synthesize one single pixel
-    fn synthesize_pixel(&self, mask: &GrayImage, coords: (u32, u32)) -> Rgb<u8> {
+    fn synthesize_pixel(&self, mask: &GrayImage, coords: (u32, u32)) -> SourcePixel {
+        self.select_pixel(|n| self.neighbourhood_error(mask, coords, n))
+    }
+
+    // Pick a source pixel whose neighbourhood is similar to the one of the
+    // pixel being synthesized, as measured by the `error` function, which
+    // also gives the transform of the best matching neighbourhood
+    fn select_pixel<F>(&self, error: F) -> SourcePixel
+        where F: Fn((u32, u32)) -> Option<(f64, Transform)> + Sync
+    {
         // Find all similar neighbourhoods and pick one wihin 10% tolerance
         let mut errors = self.source.enumerate_pixels().collect::<Vec<_>>().into_par_iter()
+                                    .filter(|&(x, y, _)| self.source_pixel_valid(x, y))
                                     .filter_map(|(x, y, _)|
-                                                if let Some(err) = self.neighbourhood_error(mask, coords, (x, y)) {
-                                                    Some((x, y, OrderedFloat::try_from(err).unwrap()))
+                                                if let Some((err, t)) = error((x, y)) {
+                                                    Some((x, y, OrderedFloat::try_from(err).unwrap(), t))
                                                 }
                                                 else { None })
                                     .collect::<Vec<_>>();
-        errors.sort_by_key(|&(_, _, e)| e);
+        errors.sort_by_key(|&(_, _, e, _)| e);
         let bound = 1.1 * errors[0].2.as_float();
-        let mut filtered_errors = errors.into_iter().take_while(|&(_, _, e)| e.as_float() <= bound).collect::<Vec<_>>();
+        let mut filtered_errors = errors.into_iter().take_while(|&(_, _, e, _)| e.as_float() <= bound).collect::<Vec<_>>();
         thread_rng().shuffle(&mut filtered_errors);
-        let (x, y, _) = filtered_errors.pop().unwrap();
-        *self.source.get_pixel(x, y)
+        let (x, y, _, t) = filtered_errors.pop().unwrap();
+        SourcePixel { coords: (x, y), transform: t }
+    }
+
+    // Compute the error between the specified neighbourhood and the specified
+    // pixel, as the smallest error among the transforms of the neighbourhood,
+    // along with the transform giving it
+    fn neighbourhood_error(&self, mask: &GrayImage, pixel: (u32, u32), neighbourhood: (u32, u32)) -> Option<(f64, Transform)> {
+        min_error(self.transforms.iter().map(|&t| {
+            self.transformed_neighbourhood_error(mask, pixel, neighbourhood, t).map(|e| (e, t))
+        }))
     }
 
-    // Compute the error between the specified neighbourhood and the specified pixel
-    fn neighbourhood_error(&self, mask: &GrayImage, pixel: (u32, u32), neighbourhood: (u32, u32)) -> Option<f64> {
+    // Compute the error between the specified neighbourhood, transformed
+    // around its center, and the specified pixel
+    fn transformed_neighbourhood_error(&self, mask: &GrayImage, pixel: (u32, u32), neighbourhood: (u32, u32),
+                                       transform: Transform) -> Option<f64> {
         let d = ((self.params.window_size - 1) / 2) as i32;
+        let (mw, mh) = (mask.width() as i32, mask.height() as i32);
+        let (sw, sh) = (self.source.width() as i32, self.source.height() as i32);
 
         let (px, py) = (pixel.0 as i32, pixel.1 as i32);
         let (nx, ny) = (neighbourhood.0 as i32, neighbourhood.1 as i32);
 
-        let xs = min(min(d, px), min(d, nx));
-        let ys = min(min(d, py), min(d, ny));
-        let xe = min(min(d, self.source.width() as i32 - nx - 1), min(d, mask.width() as i32 - px - 1));
-        let ye = min(min(d, self.source.height() as i32 - ny - 1), min(d, mask.height() as i32 - py - 1));
         let mut error = 0.;
         let mut i = 0;
-        for y in -ys..ye + 1 {
-            for x in -xs..xe + 1 {
-                let (pxx, pyy) = ((px + x) as u32, (py + y) as u32);
-                let (nxx, nyy) = ((nx + x) as u32, (ny + y) as u32);
+        for y in -d..d + 1 {
+            for x in -d..d + 1 {
+                let (pxx, pyy) = (px + x, py + y);
+                let (ox, oy) = transform.apply_offset((x, y));
+                let (nxx, nyy) = (nx + ox, ny + oy);
+                if pxx < 0 || pyy < 0 || pxx >= mw || pyy >= mh { continue; }
+                if nxx < 0 || nyy < 0 || nxx >= sw || nyy >= sh { continue; }
+                let (pxx, pyy, nxx, nyy) = (pxx as u32, pyy as u32, nxx as u32, nyy as u32);
                 if Self::mask_on(mask, pxx, pyy) {
+                    if !self.source_pixel_valid(nxx, nyy) { return None; }
                     error += l2(self.source.get_pixel(nxx, nyy),
                                 self.buffer_opt.as_ref().unwrap().get_pixel(pxx, pyy));
                     i += 1;
@@ -159,4 +377,169 @@ impl PixelSearch {
             _ => Some(error / i as f64)
         }
     }
+
+    // Compute the error between the causal neighbourhood of the specified
+    // pixel and the specified neighbourhood. The causal neighbourhood is the
+    // L-shaped part of the window which precedes the pixel in scanline order.
+    // Coordinates wrap around the edges of the buffer.
+    fn causal_neighbourhood_error(&self, pixel: (u32, u32), neighbourhood: (u32, u32)) -> Option<(f64, Transform)> {
+        min_error(self.transforms.iter().map(|&t| {
+            self.transformed_causal_neighbourhood_error(pixel, neighbourhood, t).map(|e| (e, t))
+        }))
+    }
+
+    // Compute the error between the causal neighbourhood of the specified
+    // pixel and the specified neighbourhood, transformed around its center
+    fn transformed_causal_neighbourhood_error(&self, pixel: (u32, u32), neighbourhood: (u32, u32),
+                                              transform: Transform) -> Option<f64> {
+        let d = ((self.params.window_size - 1) / 2) as i32;
+        let buffer = self.buffer_opt.as_ref().unwrap();
+        let (w, h) = (buffer.width() as i32, buffer.height() as i32);
+        let (sw, sh) = (self.source.width() as i32, self.source.height() as i32);
+
+        let (px, py) = (pixel.0 as i32, pixel.1 as i32);
+        let (nx, ny) = (neighbourhood.0 as i32, neighbourhood.1 as i32);
+
+        let mut error = 0.;
+        let mut i = 0;
+        for y in -d..1 {
+            for x in -d..d + 1 {
+                if y == 0 && x >= 0 { break; }
+                let (ox, oy) = transform.apply_offset((x, y));
+                let (nxx, nyy) = (nx + ox, ny + oy);
+                if nxx < 0 || nyy < 0 || nxx >= sw || nyy >= sh { continue; }
+                if !self.source_pixel_valid(nxx as u32, nyy as u32) { return None; }
+                let (pxx, pyy) = ((px + x + w) % w, (py + y + h) % h);
+                error += l2(self.source.get_pixel(nxx as u32, nyy as u32),
+                            buffer.get_pixel(pxx as u32, pyy as u32));
+                i += 1;
+            }
+        }
+
+        match i {
+            0 => None,
+            _ => Some(error / i as f64)
+        }
+    }
+}
+
+// Smallest of the defined errors and its transform, if any
+fn min_error<I>(errors: I) -> Option<(f64, Transform)> where I: Iterator<Item = Option<(f64, Transform)>> {
+    errors.fold(None, |acc, e| match (acc, e) {
+        (Some(a), Some(e)) => Some(if e.0 < a.0 { e } else { a }),
+        (None, e) => e,
+        (a, None) => a
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_causal_neighbourhood_error() {
+        let source = RgbImage::new(5, 5);
+        let params = PixelSearchParams::new((5, 5), 3, None).unwrap().order(SynthesisOrder::Scanline);
+        let mut ps = PixelSearch::new(source, params).unwrap();
+        // Pixels at or after (2, 2) in scanline order are not part of its causal neighbourhood
+        let mut buffer = RgbImage::new(5, 5);
+        buffer.put_pixel(2, 2, Rgb { data: [255, 255, 255] });
+        buffer.put_pixel(3, 2, Rgb { data: [255, 255, 255] });
+        buffer.put_pixel(1, 3, Rgb { data: [255, 255, 255] });
+        ps.buffer_opt = Some(buffer);
+        assert_eq!(ps.causal_neighbourhood_error((2, 2), (2, 2)), Some((0., Transform::Identity)));
+
+        // Whereas pixels before it are
+        ps.buffer_opt.as_mut().unwrap().put_pixel(1, 2, Rgb { data: [0, 0, 3] });
+        assert_eq!(ps.causal_neighbourhood_error((2, 2), (2, 2)), Some((3. / 4., Transform::Identity)));
+
+        // The source neighbourhood is clipped to the source image
+        assert_eq!(ps.causal_neighbourhood_error((2, 2), (0, 0)), None);
+    }
+
+    #[test]
+    fn test_transformed_neighbourhoods() {
+        // The buffer is the mirror image of the source, whose neighbourhoods
+        // only match once flipped
+        let source = RgbImage::from_fn(5, 5, |x, _| Rgb { data: [(x * 10) as u8; 3] });
+        let buffer = RgbImage::from_fn(5, 5, |x, _| Rgb { data: [((4 - x) * 10) as u8; 3] });
+        let params = PixelSearchParams::new((5, 5), 3, None).unwrap();
+        let mut ps = PixelSearch::new(source.clone(), params).unwrap();
+        ps.buffer_opt = Some(buffer.clone());
+        let mask = GrayImage::from_pixel(5, 5, Luma { data: [255] });
+        assert!(ps.causal_neighbourhood_error((2, 2), (2, 2)).unwrap().0 > 0.);
+        assert!(ps.neighbourhood_error(&mask, (2, 2), (2, 2)).unwrap().0 > 0.);
+
+        let params = PixelSearchParams::new((5, 5), 3, None).unwrap().transforms(TransformSet::Flips);
+        let mut ps = PixelSearch::new(source, params).unwrap();
+        ps.buffer_opt = Some(buffer);
+        assert_eq!(ps.causal_neighbourhood_error((2, 2), (2, 2)), Some((0., Transform::FlipHorizontal)));
+        assert_eq!(ps.neighbourhood_error(&mask, (2, 2), (2, 2)), Some((0., Transform::FlipHorizontal)));
+    }
+
+    #[test]
+    fn test_fill_image_holes() {
+        // Fill a hole in a two-tone image: only the color surrounding the hole
+        // can be sampled
+        let mut image = RgbImage::from_pixel(12, 8, Rgb { data: [200, 0, 0] });
+        let mut hole_mask = GrayImage::new(12, 8);
+        for y in 0..8 {
+            for x in 0..4 {
+                image.put_pixel(x, y, Rgb { data: [0, 0, 200] });
+            }
+        }
+        for y in 3..5 {
+            for x in 7..9 {
+                image.put_pixel(x, y, Rgb { data: [0, 255, 0] });
+                hole_mask.put_pixel(x, y, Luma { data: [255] });
+            }
+        }
+
+        let filled = PixelSearch::fill_image_holes(&image, &hole_mask, 3).unwrap();
+        for y in 3..5 {
+            for x in 7..9 {
+                assert_eq!(*filled.get_pixel(x, y), Rgb { data: [200, 0, 0] });
+            }
+        }
+        assert_eq!(*filled.get_pixel(0, 0), Rgb { data: [0, 0, 200] });
+
+        // The source map of the filled pixels points outside of the hole
+        let params = PixelSearchParams::new((12, 8), 3, None).unwrap();
+        let mut ps = PixelSearch::new(image.clone(), params).unwrap();
+        ps.set_source_mask(hole_mask.clone());
+        assert_eq!(ps.fill_holes(&image, &hole_mask).unwrap().into_raw(), filled.into_raw());
+        for y in 3..5 {
+            for x in 7..9 {
+                let (sx, sy) = ps.source_map().unwrap().get(x, y).unwrap().coords;
+                assert!(hole_mask.get_pixel(sx, sy).data[0] == 0 && sx >= 4);
+            }
+        }
+
+        // Every window of the source image overlaps a hole
+        let mut hole_mask = GrayImage::new(12, 8);
+        for x in 0..12 {
+            hole_mask.put_pixel(x, 4, Luma { data: [255] });
+        }
+        assert!(PixelSearch::fill_image_holes(&image, &hole_mask, 3).is_ok());
+        assert!(PixelSearch::fill_image_holes(&image, &hole_mask, 5).is_err());
+        assert!(PixelSearch::fill_image_holes(&image, &GrayImage::new(12, 8), 9).is_err());
+    }
+
+    #[test]
+    fn test_extend_image() {
+        let image = RgbImage::from_pixel(4, 4, Rgb { data: [10, 20, 30] });
+        let params = PixelSearchParams::new((1, 1), 3, None).unwrap();
+        let mut ps = PixelSearch::new(image.clone(), params).unwrap();
+        let extension = Extension { left: 1, top: 2, right: 0, bottom: 3 };
+        let res = ps.extend_image(&image, extension).unwrap();
+        assert_eq!(res.dimensions(), (5, 9));
+        assert!(res.pixels().all(|p| *p == Rgb { data: [10, 20, 30] }));
+
+        // Only the new pixels have a source pixel
+        let map = ps.source_map().unwrap();
+        assert!(map.get(1, 2).is_none());
+        assert!(map.get(0, 0).is_some());
+        assert_eq!(map.get(0, 8).unwrap().transform, Transform::Identity);
+    }
 }
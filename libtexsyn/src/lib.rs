@@ -1,5 +1,8 @@
-//#[macro_use]
-//extern crate approx;
+#![cfg_attr(feature = "nightly", feature(test))]
+
+#[cfg(test)]
+#[macro_use]
+extern crate approx;
 //extern crate conv;
 #[macro_use]
 extern crate error_chain;
@@ -9,10 +12,14 @@ extern crate image as img;
 extern crate num_traits;
 extern crate rand;
 extern crate rayon;
+#[cfg(all(feature = "nightly", test))]
+extern crate test;
 
 mod common;
+pub mod correspondence;
 pub mod distance;
 pub mod errors;
+mod fft;
 pub mod generators;
 
 pub mod image {
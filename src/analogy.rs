@@ -0,0 +1,61 @@
+#[macro_use]
+extern crate clap;
+extern crate libtexsyn;
+
+use clap::{Arg, App};
+
+use libtexsyn::generators::per_pixel::{ImageAnalogy, ImageAnalogyParams};
+use libtexsyn::image::*;
+
+fn main() {
+    let matches = App::new("Analogy").version(crate_version!())
+                                     .arg(Arg::with_name("unfiltered")
+                                              .help("Unfiltered exemplar image (A)")
+                                              .index(1)
+                                              .required(true))
+                                     .arg(Arg::with_name("filtered")
+                                              .help("Filtered exemplar image (A')")
+                                              .index(2)
+                                              .required(true))
+                                     .arg(Arg::with_name("target")
+                                              .help("Target image (B)")
+                                              .index(3)
+                                              .required(true))
+                                     .arg(Arg::with_name("output")
+                                              .help("Output image (B')")
+                                              .default_value("analogy.png")
+                                              .index(4))
+                                     .arg(Arg::with_name("levels")
+                                              .help("Number of pyramid levels")
+                                              .takes_value(true)
+                                              .short("l")
+                                              .long("levels")
+                                              .default_value("3"))
+                                     .arg(Arg::with_name("window-size")
+                                              .help("Neighbourhood size. Must be odd.")
+                                              .takes_value(true)
+                                              .short("W")
+                                              .long("winsize")
+                                              .default_value("5"))
+                                     .arg(Arg::with_name("coherence")
+                                              .help("Coherence weight")
+                                              .takes_value(true)
+                                              .short("k")
+                                              .long("coherence")
+                                              .default_value("2"))
+                                     .get_matches();
+
+    let unfiltered = open(matches.value_of("unfiltered").unwrap()).unwrap();
+    let filtered = open(matches.value_of("filtered").unwrap()).unwrap();
+    let target = open(matches.value_of("target").unwrap()).unwrap();
+    let out_file = matches.value_of("output").unwrap();
+    let levels = value_t!(matches, "levels", u32).unwrap();
+    let winsize = value_t!(matches, "window-size", u32).unwrap();
+    let coherence = value_t!(matches, "coherence", f64).unwrap();
+
+    let params = ImageAnalogyParams::new(levels, winsize, coherence).unwrap();
+    let analogy = ImageAnalogy::new(unfiltered.to_rgb(), filtered.to_rgb(), params).unwrap();
+
+    let res = analogy.apply(&target.to_rgb()).unwrap();
+    res.save(out_file).unwrap();
+}
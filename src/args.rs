@@ -0,0 +1,48 @@
+//! Parsing of the command line arguments shared by the binaries.
+#![allow(dead_code)]
+
+use clap::{ArgMatches, Error, ErrorKind};
+use libtexsyn::generators::Extension;
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Parse the value of the specified argument as a list of numbers separated
+/// by `separator`, whose length is one of `lengths`. Exits with a usage error
+/// describing the expected `format` if the value is invalid.
+pub fn list<T: FromStr>(matches: &ArgMatches, name: &str, separator: char, lengths: &[usize], format: &str) -> Option<Vec<T>> {
+    matches.value_of(name).map(|s| parse_list(s, name, separator, lengths, format))
+}
+
+/// Parse a part of the value of the specified argument as a list of numbers,
+/// like `list`.
+pub fn parse_list<T: FromStr>(s: &str, name: &str, separator: char, lengths: &[usize], format: &str) -> Vec<T> {
+    if let Ok(v) = s.split(separator).map(|n| n.trim().parse::<T>()).collect::<Result<Vec<_>, _>>() {
+        if lengths.contains(&v.len()) { return v; }
+    }
+    Error::with_description(&format!("Invalid value '{}' for '--{}': expected {}", s, name, format),
+                            ErrorKind::InvalidValue).exit()
+}
+
+/// Parse a pair of dimensions, given either as a single value used for both
+/// or as AxB.
+pub fn dimensions(matches: &ArgMatches, name: &str) -> Option<(u32, u32)> {
+    list::<u32>(matches, name, 'x', &[1, 2], "a single value or two values separated by 'x'").map(|v| (v[0], v[v.len() - 1]))
+}
+
+/// Unwrap a result depending on the values of the arguments, exiting with a
+/// usage error if they were rejected.
+pub fn check<T, E: Display>(result: Result<T, E>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => Error::with_description(&e.to_string(), ErrorKind::InvalidValue).exit()
+    }
+}
+
+/// Parse the amounts of pixels by which to extend an image, given as
+/// LEFT,TOP,RIGHT,BOTTOM.
+pub fn extension(matches: &ArgMatches, name: &str) -> Option<Extension> {
+    list::<u32>(matches, name, ',', &[4], "LEFT,TOP,RIGHT,BOTTOM").map(|v| {
+        Extension { left: v[0], top: v[1], right: v[2], bottom: v[3] }
+    })
+}
@@ -0,0 +1,93 @@
+#[macro_use]
+extern crate clap;
+extern crate libtexsyn;
+
+mod args;
+
+use clap::{Arg, App};
+
+use libtexsyn::generators::patch::{HexTiler, HexTilerParams};
+use libtexsyn::distance::{l1, l2, l2_squared};
+use libtexsyn::image::*;
+
+use std::fs::create_dir_all;
+use std::path::Path;
+
+fn main() {
+    let matches = App::new("Hex").version(crate_version!())
+                                 .arg(Arg::with_name("input")
+                                          .help("Input image")
+                                          .index(1)
+                                          .required(true))
+                                 .arg(Arg::with_name("output")
+                                          .help("Output directory, where the tiles and the mask of the hexagon are written")
+                                          .default_value("hex")
+                                          .index(2))
+                                 .arg(Arg::with_name("width")
+                                          .help("Tile width. Must be even.")
+                                          .takes_value(true)
+                                          .short("w")
+                                          .long("width")
+                                          .default_value("128"))
+                                 .arg(Arg::with_name("colors")
+                                          .help("Number of colors of the edges")
+                                          .takes_value(true)
+                                          .short("c")
+                                          .long("colors")
+                                          .default_value("2"))
+                                 .arg(Arg::with_name("overlap")
+                                          .help("Maximum distance between the seams inside the tiles and the segments from their center to their vertices")
+                                          .takes_value(true)
+                                          .short("o")
+                                          .long("overlap")
+                                          .default_value("12"))
+                                 .arg(Arg::with_name("distance")
+                                          .help("Distance function used to compute the seams")
+                                          .takes_value(true)
+                                          .short("d")
+                                          .long("distance")
+                                          .possible_values(&["l1", "l2", "l2_squared"])
+                                          .default_value("l1"))
+                                 .arg(Arg::with_name("random-seed")
+                                          .help("Seed of the choice of the edge samples, to reproduce a tile set")
+                                          .takes_value(true)
+                                          .long("random-seed"))
+                                 .arg(Arg::with_name("composite")
+                                          .help("Also save a random arrangement of COLUMNSxROWS tiles, to check that they join seamlessly")
+                                          .takes_value(true)
+                                          .value_name("COLUMNSxROWS")
+                                          .long("composite"))
+                                 .get_matches();
+
+    let in_file = matches.value_of("input").unwrap();
+    let out_dir = Path::new(matches.value_of("output").unwrap());
+    let width = value_t_or_exit!(matches, "width", u32);
+    let colors = value_t_or_exit!(matches, "colors", u32);
+    let overlap = value_t_or_exit!(matches, "overlap", u32);
+    let dist = match matches.value_of("distance").unwrap() {
+        "l1" => l1,
+        "l2" => l2,
+        "l2_squared" => l2_squared,
+        _ => unreachable!()
+    };
+    let random_seed = matches.value_of("random-seed").map(|_| value_t_or_exit!(matches, "random-seed", u64));
+    let composite = args::list::<u32>(&matches, "composite", 'x', &[2], "COLUMNSxROWS").map(|v| (v[0], v[1]));
+
+    let img = open(in_file).unwrap();
+    let params = args::check(HexTilerParams::new(width, colors, overlap, dist));
+    let params = if let Some(seed) = random_seed { params.random_seed(seed) } else { params };
+    let mut tiler = HexTiler::new(img.to_rgb(), params);
+
+    let set = args::check(tiler.generate());
+    create_dir_all(out_dir).unwrap();
+    for (i, tile) in set.tiles().iter().enumerate() {
+        tile.image.save(out_dir.join(format!("tile_{:03}.png", i))).unwrap();
+    }
+    set.mask().save(out_dir.join("mask.png")).unwrap();
+    println!("{} tiles written to {}", set.tiles().len(), out_dir.display());
+
+    if let Some((columns, rows)) = composite {
+        let seed = random_seed.unwrap_or(0);
+        args::check(set.composite(columns, rows, seed)).save(out_dir.join("composite.png")).unwrap();
+    }
+}
@@ -0,0 +1,45 @@
+#[macro_use]
+extern crate clap;
+extern crate libtexsyn;
+
+use clap::{Arg, App};
+
+use libtexsyn::generators::patch::{Inpainter, InpainterParams};
+use libtexsyn::distance::l2;
+use libtexsyn::image::*;
+
+fn main() {
+    let matches = App::new("Inpaint").version(crate_version!())
+                                     .arg(Arg::with_name("input")
+                                              .help("Input image")
+                                              .index(1)
+                                              .required(true))
+                                     .arg(Arg::with_name("mask")
+                                              .help("Hole mask. Non black pixels are filled.")
+                                              .index(2)
+                                              .required(true))
+                                     .arg(Arg::with_name("output")
+                                              .help("Output image")
+                                              .default_value("inpaint.png")
+                                              .index(3))
+                                     .arg(Arg::with_name("patchsize")
+                                              .help("Patch size. Must be odd.")
+                                              .takes_value(true)
+                                              .short("p")
+                                              .long("patchsize")
+                                              .default_value("9"))
+                                     .get_matches();
+
+    let in_file = matches.value_of("input").unwrap();
+    let mask_file = matches.value_of("mask").unwrap();
+    let out_file = matches.value_of("output").unwrap();
+    let patch_size = value_t!(matches, "patchsize", u32).unwrap();
+
+    let img = open(in_file).unwrap();
+    let mask = open(mask_file).unwrap();
+    let params = InpainterParams::new(patch_size, l2).unwrap();
+    let mut inpainter = Inpainter::new(img.to_rgb(), params);
+
+    let res = inpainter.inpaint(&mask.to_luma()).unwrap();
+    res.save(out_file).unwrap();
+}
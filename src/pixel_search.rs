@@ -2,9 +2,12 @@
 extern crate clap;
 extern crate libtexsyn;
 
+mod args;
+
 use clap::{Arg, App};
 
-use libtexsyn::generators::per_pixel::{PixelSearch, PixelSearchParams};
+use libtexsyn::generators::TransformSet;
+use libtexsyn::generators::per_pixel::{PixelSearch, PixelSearchParams, SynthesisOrder};
 use libtexsyn::image::*;
 
 fn main() {
@@ -41,6 +44,31 @@ fn main() {
                                                   .short("W")
                                                   .long("winsize")
                                                   .default_value("15"))
+                                         .arg(Arg::with_name("scanline")
+                                                  .help("Synthesize pixels in scanline order from noise instead of growing the image from a seed")
+                                                  .long("scanline"))
+                                         .arg(Arg::with_name("transforms")
+                                                  .help("Also compare flipped or rotated source neighbourhoods. Suited to isotropic textures.")
+                                                  .takes_value(true)
+                                                  .long("transforms")
+                                                  .possible_values(&["none", "flips", "rotations", "all"])
+                                                  .default_value("none"))
+                                         .arg(Arg::with_name("fill")
+                                                  .help("Fill the holes of the input image instead of synthesizing a new image. Non black pixels of the mask are filled.")
+                                                  .takes_value(true)
+                                                  .value_name("MASK")
+                                                  .long("fill"))
+                                         .arg(Arg::with_name("extend")
+                                                  .help("Extend the input image by the specified amounts of pixels on each side instead of synthesizing a new image")
+                                                  .takes_value(true)
+                                                  .value_name("LEFT,TOP,RIGHT,BOTTOM")
+                                                  .long("extend")
+                                                  .conflicts_with("fill"))
+                                         .arg(Arg::with_name("source-map")
+                                                  .help("Also save a map of the input pixel each output pixel was copied from")
+                                                  .takes_value(true)
+                                                  .value_name("FILE")
+                                                  .long("source-map"))
                                          .get_matches();
 
     let in_file = matches.value_of("input").unwrap();
@@ -49,11 +77,28 @@ fn main() {
     let (width, height) = if let Ok(s) = size { (s, s) }
                           else { (value_t!(matches, "width", u32).unwrap(), value_t!(matches, "height", u32).unwrap()) };
     let winsize = value_t!(matches, "window-size", u32).unwrap();
+    let order = if matches.is_present("scanline") { SynthesisOrder::Scanline } else { SynthesisOrder::OnionPeeling };
+    let transforms = match matches.value_of("transforms").unwrap() {
+        "flips" => TransformSet::Flips,
+        "rotations" => TransformSet::Rotations,
+        "all" => TransformSet::All,
+        _ => TransformSet::None
+    };
+    let extension = args::extension(&matches, "extend");
 
     let img = open(in_file).unwrap();
-    let params = PixelSearchParams::new((width, height), winsize, None).unwrap();
+    let params = PixelSearchParams::new((width, height), winsize, None).unwrap().order(order).transforms(transforms);
     let mut ps = PixelSearch::new(img.to_rgb(), params).unwrap();
-
-    let res = ps.synthesize();
+    let res = if let Some(mask_file) = matches.value_of("fill") {
+        // The holes are filled by sampling the image itself, outside of them
+        let mask = open(mask_file).unwrap().to_luma();
+        ps.set_source_mask(mask.clone());
+        ps.fill_holes(&img.to_rgb(), &mask).unwrap()
+    }
+    else if let Some(extension) = extension { ps.extend_image(&img.to_rgb(), extension).unwrap() }
+    else { ps.synthesize() };
+    if let Some(map_file) = matches.value_of("source-map") {
+        ps.source_map().unwrap().to_image(img.dimensions()).save(map_file).unwrap();
+    }
     res.save(out_file).unwrap();
 }
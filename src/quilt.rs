@@ -2,12 +2,19 @@
 extern crate clap;
 extern crate libtexsyn;
 
+mod args;
+
 use clap::{Arg, App};
 
-use libtexsyn::generators::patch::{Quilter, QuilterParams};
-use libtexsyn::distance::l1;
+use libtexsyn::generators::TransformSet;
+use libtexsyn::generators::patch::{Quilter, QuilterParams, Blending, PatchPlacement, SeamMethod, TransferParams};
+use libtexsyn::correspondence::luminance;
+use libtexsyn::distance::Distance;
 use libtexsyn::image::*;
 
+use std::fs::create_dir_all;
+use std::path::Path;
+
 fn main() {
     let matches = App::new("Quilt").version(crate_version!())
                                    .arg(Arg::with_name("input")
@@ -37,17 +44,168 @@ fn main() {
                                             .conflicts_with("height")
                                             .default_value("1024"))
                                    .arg(Arg::with_name("blocksize")
-                                            .help("Patch size")
+                                            .help("Patch size, either square or as WIDTHxHEIGHT")
                                             .takes_value(true)
                                             .short("b")
                                             .long("blocksize")
                                             .default_value("64"))
                                    .arg(Arg::with_name("overlap")
-                                            .help("Overlap area size")
+                                            .help("Overlap area size, either the same in both directions or as HORIZONTALxVERTICAL")
                                             .takes_value(true)
                                             .short("o")
                                             .long("overlap")
                                             .default_value("12"))
+                                   .arg(Arg::with_name("distance")
+                                            .help("Distance function used to compare patches. l2_squared is much faster on large input images.")
+                                            .takes_value(true)
+                                            .short("d")
+                                            .long("distance")
+                                            .possible_values(&["l1", "l2", "l2_squared"])
+                                            .default_value("l1"))
+                                   .arg(Arg::with_name("tileable")
+                                            .help("Make the output image tileable. The output size must then be a multiple of blocksize - overlap.")
+                                            .short("t")
+                                            .long("tileable"))
+                                   .arg(Arg::with_name("graphcut")
+                                            .help("Cut the patches along a minimum graph cut instead of minimum error boundaries")
+                                            .short("g")
+                                            .long("graphcut"))
+                                   .arg(Arg::with_name("placement")
+                                            .help("Place the patches on a regular grid, or at random offsets chosen to match everything they overlap")
+                                            .takes_value(true)
+                                            .long("placement")
+                                            .possible_values(&["grid", "random"])
+                                            .default_value("grid"))
+                                   .arg(Arg::with_name("offsets")
+                                            .help("Number of random offsets tried for each patch with random placement")
+                                            .takes_value(true)
+                                            .long("offsets")
+                                            .default_value("8"))
+                                   .arg(Arg::with_name("refinements")
+                                            .help("Number of patches quilted over the costliest seams after random placement")
+                                            .takes_value(true)
+                                            .long("refinements")
+                                            .default_value("0"))
+                                   .arg(Arg::with_name("blend")
+                                            .help("Blend the patches across the seams instead of cutting them")
+                                            .takes_value(true)
+                                            .long("blend")
+                                            .possible_values(&["feather", "multiband"]))
+                                   .arg(Arg::with_name("blend-radius")
+                                            .help("Radius of the blending area around the seams")
+                                            .takes_value(true)
+                                            .long("blend-radius")
+                                            .default_value("4"))
+                                   .arg(Arg::with_name("poisson")
+                                            .help("Blend the output image in the gradient domain to remove the illumination steps between patches")
+                                            .long("poisson"))
+                                   .arg(Arg::with_name("repetition-penalty")
+                                            .help("Penalty applied to the candidate patches covering already used source pixels")
+                                            .takes_value(true)
+                                            .long("repetition-penalty")
+                                            .default_value("0"))
+                                   .arg(Arg::with_name("min-repeat-distance")
+                                            .help("Minimum distance in pixels between two placements of the same source patch")
+                                            .takes_value(true)
+                                            .long("min-repeat-distance")
+                                            .default_value("0"))
+                                   .arg(Arg::with_name("transforms")
+                                            .help("Also use flipped or rotated source patches. Suited to isotropic textures.")
+                                            .takes_value(true)
+                                            .long("transforms")
+                                            .possible_values(&["none", "flips", "rotations", "all"])
+                                            .default_value("none"))
+                                   .arg(Arg::with_name("orientations")
+                                            .help("Also use patches of the input image rotated in N evenly spaced orientations. Brush strokes take their patches from the orientation closest to their direction.")
+                                            .takes_value(true)
+                                            .value_name("N")
+                                            .long("orientations")
+                                            .default_value("1"))
+                                   .arg(Arg::with_name("scales")
+                                            .help("Also use patches of the input image resampled at STEPS scales between MIN and MAX")
+                                            .takes_value(true)
+                                            .value_name("MIN,MAX,STEPS")
+                                            .long("scales"))
+                                   .arg(Arg::with_name("scale-penalty")
+                                            .help("Penalty applied to the candidate patches resampled away from the nominal scale")
+                                            .takes_value(true)
+                                            .long("scale-penalty")
+                                            .default_value("0"))
+                                   .arg(Arg::with_name("source-map")
+                                            .help("Also save a map of the input pixel each output pixel was copied from")
+                                            .takes_value(true)
+                                            .value_name("FILE")
+                                            .long("source-map"))
+                                   .arg(Arg::with_name("debug")
+                                            .help("Save debug images to the specified directory: seams, patch outlines, candidate counts and the error surface of each patch")
+                                            .takes_value(true)
+                                            .value_name("DIR")
+                                            .long("debug"))
+                                   .arg(Arg::with_name("random-seed")
+                                            .help("Seed of the random choices, to reproduce a previous result")
+                                            .takes_value(true)
+                                            .long("random-seed"))
+                                   .arg(Arg::with_name("extend")
+                                            .help("Extend the input image by the specified amounts of pixels on each side instead of synthesizing a new image")
+                                            .takes_value(true)
+                                            .value_name("LEFT,TOP,RIGHT,BOTTOM")
+                                            .long("extend"))
+                                   .arg(Arg::with_name("transfer")
+                                            .help("Render the target image with the input texture instead of synthesizing a new image. The output has the size of the target image.")
+                                            .takes_value(true)
+                                            .value_name("TARGET")
+                                            .long("transfer")
+                                            .conflicts_with("extend"))
+                                   .arg(Arg::with_name("fill")
+                                            .help("Fill the holes of the specified image instead of synthesizing a new image. The output has the size of this image.")
+                                            .takes_value(true)
+                                            .value_name("IMAGE")
+                                            .long("fill")
+                                            .requires("hole-mask")
+                                            .conflicts_with_all(&["extend", "transfer"]))
+                                   .arg(Arg::with_name("hole-mask")
+                                            .help("Mask of the holes to fill, whose non black pixels are synthesized")
+                                            .takes_value(true)
+                                            .value_name("MASK")
+                                            .long("hole-mask"))
+                                   .arg(Arg::with_name("brush")
+                                            .help("Paint a stroke with the input texture on the specified canvas instead of synthesizing a new image. The output has the size of the canvas.")
+                                            .takes_value(true)
+                                            .value_name("CANVAS")
+                                            .long("brush")
+                                            .requires("stroke")
+                                            .conflicts_with_all(&["extend", "transfer", "fill"]))
+                                   .arg(Arg::with_name("stroke")
+                                            .help("Points of the polyline followed by the stroke")
+                                            .takes_value(true)
+                                            .value_name("X,Y;X,Y;...")
+                                            .long("stroke"))
+                                   .arg(Arg::with_name("stroke-width")
+                                            .help("Width of the stroke")
+                                            .takes_value(true)
+                                            .long("stroke-width")
+                                            .default_value("32"))
+                                   .arg(Arg::with_name("alpha")
+                                            .help("Texture transfer overlap error weight, between 0 and 1")
+                                            .takes_value(true)
+                                            .short("a")
+                                            .long("alpha")
+                                            .default_value("0.5"))
+                                   .arg(Arg::with_name("passes")
+                                            .help("Number of passes, each one refining the previous one with smaller patches")
+                                            .takes_value(true)
+                                            .short("p")
+                                            .long("passes")
+                                            .default_value("1"))
+                                   .arg(Arg::with_name("reduction")
+                                            .help("Factor by which the patch size is reduced on each pass, between 0 and 1. Texture transfer always uses 2/3.")
+                                            .takes_value(true)
+                                            .long("reduction")
+                                            .default_value("0.67"))
+                                   .arg(Arg::with_name("verbose")
+                                            .help("Print statistics about the reuse of the input image")
+                                            .short("v")
+                                            .long("verbose"))
                                    .get_matches();
 
     let in_file = matches.value_of("input").unwrap();
@@ -55,13 +213,122 @@ fn main() {
     let size = value_t!(matches, "size", u32);
     let (width, height) = if let Ok(s) = size { (s, s) }
                           else { (value_t!(matches, "width", u32).unwrap(), value_t!(matches, "height", u32).unwrap()) };
-    let blocksize = value_t!(matches, "blocksize", u32).unwrap();
-    let overlap = value_t!(matches, "overlap", u32).unwrap();
+    let blocksize = args::dimensions(&matches, "blocksize").unwrap();
+    let overlap = args::dimensions(&matches, "overlap").unwrap();
+    let distance = match matches.value_of("distance").unwrap() {
+        "l2" => Distance::L2,
+        "l2_squared" => Distance::L2Squared,
+        _ => Distance::L1
+    };
+    let tileable = matches.is_present("tileable");
+    let seam = if matches.is_present("graphcut") { SeamMethod::GraphCut } else { SeamMethod::MinimumErrorBoundary };
+    let placement = match matches.value_of("placement").unwrap() {
+        "random" => PatchPlacement::Random { offsets: value_t!(matches, "offsets", u32).unwrap(),
+                                             refinements: value_t!(matches, "refinements", u32).unwrap() },
+        _ => PatchPlacement::Grid
+    };
+    let blend_radius = value_t!(matches, "blend-radius", u32).unwrap();
+    let blending = match matches.value_of("blend") {
+        Some("feather") => Blending::Feather(blend_radius),
+        Some("multiband") => Blending::MultiBand(blend_radius),
+        _ => Blending::Cut
+    };
+    let poisson = matches.is_present("poisson");
+    let repetition_penalty = value_t!(matches, "repetition-penalty", f64).unwrap();
+    let min_repeat_distance = value_t!(matches, "min-repeat-distance", u32).unwrap();
+    let transforms = match matches.value_of("transforms").unwrap() {
+        "flips" => TransformSet::Flips,
+        "rotations" => TransformSet::Rotations,
+        "all" => TransformSet::All,
+        _ => TransformSet::None
+    };
+    let scales = args::list::<f64>(&matches, "scales", ',', &[3], "MIN,MAX,STEPS").map(|v| {
+        let steps = if v[2] >= 0. && v[2].fract() == 0. { Ok(v[2] as u32) }
+                    else { Err("The number of scales must be an integer") };
+        (v[0], v[1], args::check(steps))
+    }).unwrap_or((1., 1., 1));
+    let scale_penalty = value_t!(matches, "scale-penalty", f64).unwrap();
+    let orientations = value_t!(matches, "orientations", u32).unwrap();
+    let random_seed = value_t!(matches, "random-seed", u64).ok();
+    let extension = args::extension(&matches, "extend");
+    let alpha = value_t!(matches, "alpha", f64).unwrap();
+    let passes = value_t!(matches, "passes", u32).unwrap();
+    let reduction = value_t!(matches, "reduction", f64).unwrap();
 
     let img = open(in_file).unwrap();
-    let params = QuilterParams::new((width, height), blocksize, overlap, None, None, l1).unwrap();
+    let params = QuilterParams::new((width, height), blocksize, overlap, None, None, distance).unwrap()
+                               .tileable(tileable)
+                               .seam(seam)
+                               .placement(placement)
+                               .blending(blending)
+                               .poisson_blending(poisson)
+                               .repetition_penalty(repetition_penalty)
+                               .min_repeat_distance(min_repeat_distance)
+                               .transforms(transforms)
+                               .orientations(orientations)
+                               .scales(scales.0, scales.1, scales.2)
+                               .scale_penalty(scale_penalty)
+                               .passes(passes, reduction);
+    let params = if let Some(seed) = random_seed { params.random_seed(seed) } else { params };
+    let debug_dir = matches.value_of("debug");
+    let params = params.debug(debug_dir.is_some());
     let mut quilter = Quilter::new(img.to_rgb(), params);
 
-    let res = quilter.quilt_image().unwrap();
+    // The scale range is validated by the synthesis
+    let res = if let Some(extension) = extension { args::check(quilter.extend_image(&img.to_rgb(), extension)) }
+              else if let Some(target_file) = matches.value_of("transfer") {
+                  let target = open(target_file).unwrap();
+                  let transfer = TransferParams::new(luminance, alpha, passes).unwrap();
+                  args::check(quilter.transfer_texture(&target.to_rgb(), &transfer))
+              }
+              else if let Some(fill_file) = matches.value_of("fill") {
+                  let image = open(fill_file).unwrap();
+                  let hole_mask = open(matches.value_of("hole-mask").unwrap()).unwrap();
+                  args::check(quilter.fill_holes(&image.to_rgb(), &hole_mask.to_luma()))
+              }
+              else if let Some(canvas_file) = matches.value_of("brush") {
+                  let canvas = open(canvas_file).unwrap();
+                  let points = matches.value_of("stroke").unwrap().split(';').map(|p| {
+                      let v = args::parse_list::<f64>(p, "stroke", ',', &[2], "X,Y for each point");
+                      (v[0], v[1])
+                  }).collect::<Vec<_>>();
+                  let stroke_width = value_t!(matches, "stroke-width", f64).unwrap();
+                  args::check(quilter.brush(&canvas.to_rgb(), &points, stroke_width))
+              }
+              else { args::check(quilter.quilt_image()) };
+    if matches.is_present("verbose") {
+        let stats = quilter.reuse_stats().unwrap();
+        println!("Quilted {} patches from {} distinct source patches, at most {} times the same one",
+                 stats.patches, stats.distinct_patches, stats.max_uses);
+        println!("{:.1}% of the source pixels used, {:.2} times each on average",
+                 stats.coverage * 100., stats.mean_pixel_uses);
+    }
+    if scales.2 > 1 {
+        let mut counts: Vec<(f64, usize)> = vec!();
+        for p in quilter.placements() {
+            match counts.iter().position(|&(s, _)| s == p.scale) {
+                Some(i) => counts[i].1 += 1,
+                None => counts.push((p.scale, 1))
+            }
+        }
+        counts.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        for (scale, n) in counts {
+            println!("{} patches at scale {:.2}", n, scale);
+        }
+    }
+    if let Some(map_file) = matches.value_of("source-map") {
+        quilter.source_map().unwrap().to_image(img.dimensions()).save(map_file).unwrap();
+    }
+    if let Some(dir) = debug_dir {
+        let dir = Path::new(dir);
+        create_dir_all(dir).unwrap();
+        let report = quilter.debug_report().unwrap();
+        report.seams.save(dir.join("seams.png")).unwrap();
+        report.outlines.save(dir.join("outlines.png")).unwrap();
+        report.candidates.save(dir.join("candidates.png")).unwrap();
+        for (i, surface) in report.error_surfaces.iter().enumerate() {
+            surface.save(dir.join(format!("error_surface_{:04}.png", i))).unwrap();
+        }
+    }
     res.save(out_file).unwrap();
 }
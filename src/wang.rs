@@ -0,0 +1,84 @@
+#[macro_use]
+extern crate clap;
+extern crate libtexsyn;
+
+mod args;
+
+use clap::{Arg, App};
+
+use libtexsyn::generators::patch::{WangTiler, WangTilerParams};
+use libtexsyn::distance::{l1, l2, l2_squared};
+use libtexsyn::image::*;
+
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::path::Path;
+
+fn main() {
+    let matches = App::new("Wang").version(crate_version!())
+                                  .arg(Arg::with_name("input")
+                                           .help("Input image")
+                                           .index(1)
+                                           .required(true))
+                                  .arg(Arg::with_name("output")
+                                           .help("Output directory, where the tiles, the atlas and its JSON description are written")
+                                           .default_value("wang")
+                                           .index(2))
+                                  .arg(Arg::with_name("tilesize")
+                                           .help("Tile size. Must be even.")
+                                           .takes_value(true)
+                                           .short("t")
+                                           .long("tilesize")
+                                           .default_value("128"))
+                                  .arg(Arg::with_name("colors")
+                                           .help("Number of colors of the edges, either the same in both directions or as HORIZONTALxVERTICAL")
+                                           .takes_value(true)
+                                           .short("c")
+                                           .long("colors")
+                                           .default_value("2"))
+                                  .arg(Arg::with_name("overlap")
+                                           .help("Maximum distance between the seams inside the tiles and their diagonals")
+                                           .takes_value(true)
+                                           .short("o")
+                                           .long("overlap")
+                                           .default_value("12"))
+                                  .arg(Arg::with_name("distance")
+                                           .help("Distance function used to compute the seams")
+                                           .takes_value(true)
+                                           .short("d")
+                                           .long("distance")
+                                           .possible_values(&["l1", "l2", "l2_squared"])
+                                           .default_value("l1"))
+                                  .arg(Arg::with_name("random-seed")
+                                           .help("Seed of the choice of the edge samples, to reproduce a tile set")
+                                           .takes_value(true)
+                                           .long("random-seed"))
+                                  .get_matches();
+
+    let in_file = matches.value_of("input").unwrap();
+    let out_dir = Path::new(matches.value_of("output").unwrap());
+    let tile_size = value_t_or_exit!(matches, "tilesize", u32);
+    let colors = args::dimensions(&matches, "colors").unwrap();
+    let overlap = value_t_or_exit!(matches, "overlap", u32);
+    let dist = match matches.value_of("distance").unwrap() {
+        "l1" => l1,
+        "l2" => l2,
+        "l2_squared" => l2_squared,
+        _ => unreachable!()
+    };
+    let random_seed = matches.value_of("random-seed").map(|_| value_t_or_exit!(matches, "random-seed", u64));
+
+    let img = open(in_file).unwrap();
+    let params = args::check(WangTilerParams::new(tile_size, colors, overlap, dist));
+    let params = if let Some(seed) = random_seed { params.random_seed(seed) } else { params };
+    let mut tiler = WangTiler::new(img.to_rgb(), params);
+
+    let set = args::check(tiler.generate());
+    create_dir_all(out_dir).unwrap();
+    for (i, tile) in set.tiles().iter().enumerate() {
+        tile.image.save(out_dir.join(format!("tile_{:02}.png", i))).unwrap();
+    }
+    set.atlas().save(out_dir.join("atlas.png")).unwrap();
+    File::create(out_dir.join("atlas.json")).unwrap().write_all(set.to_json().as_bytes()).unwrap();
+    println!("{} tiles written to {}", set.tiles().len(), out_dir.display());
+}